@@ -0,0 +1,78 @@
+// Decode cost per webhook_event frame, the read loop's hot path:
+//   cargo bench --bench parse
+// Each iteration decodes one second's worth of frames at 5k events/sec, so
+// anything under 1s per iteration keeps up with that rate. `value` is the
+// old path through a full serde_json::Value, `raw` is Frame::parse.
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use serde_json::json;
+use stripelistener::{Frame, IncomingMessage, StripeEventPayload, WebhookEvent};
+
+const EVENTS_PER_SEC: usize = 5_000;
+
+fn frame(n: usize) -> String {
+    let payload = json!({
+        "id": format!("evt_{:024}", n),
+        "object": "event",
+        "api_version": "2023-10-16",
+        "created": 1_700_000_000 + n as u64,
+        "livemode": false,
+        "pending_webhooks": 1,
+        "request": { "id": format!("req_{:014}", n), "idempotency_key": format!("{:036}", n) },
+        "type": "payment_intent.succeeded",
+        "data": {
+            "object": {
+                "id": format!("pi_{:024}", n),
+                "object": "payment_intent",
+                "amount": 2000,
+                "amount_received": 2000,
+                "currency": "usd",
+                "customer": format!("cus_{:014}", n),
+                "description": "benchmark payment",
+                "metadata": { "order_id": n.to_string(), "source": "bench" },
+                "payment_method_types": ["card"],
+                "status": "succeeded"
+            }
+        }
+    });
+    json!({
+        "type": "webhook_event",
+        "webhook_id": format!("we_{:024}", n),
+        "webhook_conversation_id": format!("wc_{:024}", n),
+        "event_payload": payload.to_string(),
+        "http_headers": {
+            "Content-Type": "application/json; charset=utf-8",
+            "Stripe-Signature": format!("t=1700000000,v1={:064x}", n)
+        },
+        "endpoint": { "url": "http://localhost:4242/webhook", "api_version": "2023-10-16" }
+    })
+    .to_string()
+}
+
+fn value_path(text: &str) -> (WebhookEvent, StripeEventPayload) {
+    let incoming: IncomingMessage = serde_json::from_str(text).unwrap();
+    let evt: WebhookEvent = serde_json::from_value(incoming.data.clone()).unwrap();
+    let parsed = serde_json::from_str(&evt.event_payload).unwrap();
+    (evt, parsed)
+}
+
+fn raw_path(text: &str) -> (WebhookEvent, StripeEventPayload) {
+    let Frame::Webhook(evt) = Frame::parse(text).unwrap() else { unreachable!() };
+    let parsed = serde_json::from_str(&evt.event_payload).unwrap();
+    (evt, parsed)
+}
+
+fn webhook_event(c: &mut Criterion) {
+    let frames: Vec<String> = (0..EVENTS_PER_SEC).map(frame).collect();
+    let mut group = c.benchmark_group("webhook_event");
+    group.throughput(Throughput::Elements(EVENTS_PER_SEC as u64));
+    group.bench_function("value", |b| {
+        b.iter(|| frames.iter().for_each(|f| drop(black_box(value_path(f)))))
+    });
+    group.bench_function("raw", |b| {
+        b.iter(|| frames.iter().for_each(|f| drop(black_box(raw_path(f)))))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, webhook_event);
+criterion_main!(benches);
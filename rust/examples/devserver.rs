@@ -0,0 +1,51 @@
+use std::sync::Arc;
+use stripelistener::devserver::{DevServer, DevServerConfig};
+use stripelistener::{Config, EventHandler, ForwardResult, SecretString, StripeEventPayload, StripeListener, V2Event, V2EventPayload, WebhookEvent};
+
+// Forwards live events into the embedded receiver and prints what it stored.
+// Run with: cargo run --example devserver --features devserver
+
+struct PrintResults;
+
+impl EventHandler for PrintResults {
+    fn on_webhook_event(&self, _evt: WebhookEvent, _parsed: StripeEventPayload) {}
+    fn on_v2_event(&self, _evt: V2Event, _parsed: V2EventPayload) {}
+    fn on_unknown_message(&self, _raw_type: String, _data: serde_json::Value) {}
+
+    fn on_forward_result(&self, result: &ForwardResult) {
+        println!("forwarded {} -> {:?} in {:?}", result.event_id, result.status, result.duration);
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = std::env::var("STRIPE_API_KEY").unwrap_or_default();
+    if api_key.is_empty() {
+        eprintln!("Please set STRIPE_API_KEY environment variable");
+        return Ok(());
+    }
+
+    // Authorize first so the dev server can verify signatures with the
+    // session's signing secret, then point forwarding at it.
+    let mut listener = StripeListener::new(Config::new(api_key, Arc::new(PrintResults)));
+    let session = listener.authorize().await?;
+    let dev = DevServer::start(DevServerConfig {
+        secret: session.signing_secret().map(SecretString::from),
+        ..Default::default()
+    })
+    .await?;
+    println!("dev server listening on {}", dev.url());
+    listener.config_handle().update_forward_target(Some(dev.url()));
+
+    tokio::select! {
+        res = listener.run() => {
+            res?;
+        }
+        _ = tokio::signal::ctrl_c() => {}
+    }
+
+    for event in dev.events() {
+        println!("{} {:?}", event.path, event.payload.get("type"));
+    }
+    Ok(())
+}
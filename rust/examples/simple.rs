@@ -38,34 +38,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
-    let config = Config {
-        api_key,
-        device_name: Some("rust-example-listener".to_string()),
-        websocket_features: Some(vec!["webhooks".to_string()]),
-        handler: Arc::new(SimpleHandler),
-        logger: Some(Arc::new(ConsoleLogger)),
-        pong_wait: None,
-        ping_period: None,
-    };
+    let mut config = Config::new(api_key, Arc::new(SimpleHandler));
+    config.device_name = Some("rust-example-listener".to_string());
+    config.websocket_features = Some(vec!["webhooks".to_string()]);
+    config.logger = Some(Arc::new(ConsoleLogger));
 
     let mut listener = StripeListener::new(config);
 
-    println!("Authorizing...");
-    listener.authorize().await?;
-    
-    println!("Connecting...");
-    listener.connect().await?;
+    let session = listener.authorize().await?;
+    if let Some(secret) = session.signing_secret() {
+        println!("Your webhook signing secret is {}", secret);
+    }
 
     println!("Listening for events (Ctrl+C to stop)...");
-    // In a real app, you'd probably run this in a loop or handle reconnection
-    // The current implementation's listen loop isn't fully exposed as a single blocking call yet
-    // because `connect` spawns tasks. We need to keep the main thread alive.
-    
-    // Changing the library slightly to expose a `listen` method similar to Go would be good,
-    // but for now let's just wait.
-    
-    tokio::signal::ctrl_c().await?;
-    println!("Shutting down...");
+    let handle = listener.handle();
+    tokio::spawn(async move {
+        let _ = tokio::signal::ctrl_c().await;
+        println!("Shutting down...");
+        handle.shutdown();
+    });
+    // run() authorizes, connects and reconnects according to the
+    // configured ReconnectPolicy (exponential backoff by default), and
+    // returns a summary of the run once shut down.
+    let report = listener.run().await?;
+    println!("{}", report);
 
     Ok(())
 }
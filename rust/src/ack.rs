@@ -0,0 +1,65 @@
+// event_ack construction. The fields devproxy expects have changed across
+// stripe-cli versions, so acks are built by an AckFormat selected by the
+// subprotocol the server negotiated on the handshake.
+use serde_json::Value;
+
+use crate::protocol::SUBPROTOCOL;
+use crate::{Error, OutgoingMessage, Result};
+
+/// Identity of the event being acknowledged.
+#[derive(Debug, Clone, Copy)]
+pub struct AckFields<'a> {
+    pub event_id: &'a str,
+    /// `webhook_id` of a webhook event, `destination_id` of a v2 event.
+    pub webhook_id: &'a str,
+    /// Empty for v2 events.
+    pub webhook_conversation_id: &'a str,
+}
+
+/// Builds ack frames for the subprotocols it understands. The listener
+/// offers `subprotocols()` on the handshake; if the server negotiates one
+/// that is not listed, connect() fails with `Error::Protocol` rather than
+/// sending acks the server would not recognize.
+pub trait AckFormat: Send + Sync {
+    /// Supported subprotocols, most preferred first.
+    fn subprotocols(&self) -> Vec<String>;
+    /// The ack frame for `subprotocol`, one of `subprotocols()`.
+    fn build(&self, subprotocol: &str, fields: &AckFields) -> Result<Value>;
+}
+
+/// The `stripecli-devproxy-v1` ack: `type`, `event_id`,
+/// `webhook_conversation_id` and `webhook_id`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DevproxyV1Ack;
+
+impl AckFormat for DevproxyV1Ack {
+    fn subprotocols(&self) -> Vec<String> {
+        vec![SUBPROTOCOL.to_string()]
+    }
+
+    fn build(&self, subprotocol: &str, fields: &AckFields) -> Result<Value> {
+        if subprotocol != SUBPROTOCOL {
+            return Err(Error::Protocol(subprotocol.to_string()));
+        }
+        let ack = OutgoingMessage::EventAck {
+            event_id: fields.event_id.to_string(),
+            webhook_id: fields.webhook_id.to_string(),
+            webhook_conversation_id: fields.webhook_conversation_id.to_string(),
+        };
+        Ok(ack.to_json())
+    }
+}
+
+// The subprotocol to build acks for: the server's choice, or our first
+// offer when the server does not echo one.
+pub(crate) fn negotiate(format: &dyn AckFormat, offered: &[String], accepted: Option<&str>) -> Result<String> {
+    match accepted {
+        Some(p) if format.subprotocols().iter().any(|s| s == p) => Ok(p.to_string()),
+        Some(p) => Err(Error::Protocol(p.to_string())),
+        None => offered
+            .iter()
+            .find(|p| format.subprotocols().contains(p))
+            .cloned()
+            .ok_or_else(|| Error::Protocol(offered.join(", "))),
+    }
+}
@@ -0,0 +1,254 @@
+// Minimal Stripe REST client shared by authorize() and the REST helpers.
+// Rate-limited (429) requests are retried as the ReconnectPolicy decides.
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT_ENCODING, AUTHORIZATION, RETRY_AFTER, USER_AGENT};
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use crate::session::{API_BASE, CLI_VERSION};
+use crate::{Clock, Error, ReconnectAction, ReconnectPolicy, Result, SecretString, TlsOptions};
+
+/// JSON sent as X-Stripe-Client-User-Agent, identifying as the Stripe CLI.
+pub(crate) fn client_user_agent() -> String {
+    serde_json::json!({
+        "name": "stripe-cli",
+        "version": CLI_VERSION,
+        "publisher": "stripe",
+        "os": std::env::consts::OS,
+        "uname": format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+    })
+    .to_string()
+}
+
+#[derive(Clone)]
+pub(crate) struct ApiClient {
+    client: reqwest::Client,
+    api_key: SecretString,
+    stripe_account: Option<String>,
+    policy: Arc<dyn ReconnectPolicy>,
+    clock: Arc<dyn Clock>,
+    attempt_timeout: Option<Duration>,
+}
+
+impl ApiClient {
+    pub(crate) fn new(
+        tls: &TlsOptions,
+        api_key: &SecretString,
+        stripe_account: Option<&str>,
+        policy: Arc<dyn ReconnectPolicy>,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self> {
+        Ok(Self {
+            client: tls.http_client()?,
+            api_key: api_key.clone(),
+            stripe_account: stripe_account.map(str::to_string),
+            policy,
+            clock,
+            attempt_timeout: None,
+        })
+    }
+
+    /// Fails each attempt that gets no response within `limit` with
+    /// Error::Timeout instead of waiting on it.
+    pub(crate) fn with_attempt_timeout(mut self, limit: Option<Duration>) -> Self {
+        self.attempt_timeout = limit;
+        self
+    }
+
+    // Sends the request built by `build`, retrying 429s for as long as the
+    // policy returns Delay. The wait is at least the server's Retry-After.
+    async fn send(&self, build: impl Fn() -> reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let mut attempt = 0u32;
+        loop {
+            let resp = match self.attempt_timeout {
+                Some(after) => tokio::select! {
+                    resp = build().send() => resp?,
+                    _ = self.clock.sleep(after) => return Err(Error::Timeout { operation: "api request", after }),
+                },
+                None => build().send().await?,
+            };
+            if resp.status() != StatusCode::TOO_MANY_REQUESTS {
+                return Ok(resp);
+            }
+            attempt += 1;
+            let retry_after = resp
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(Duration::from_secs);
+            let should_retry = resp.headers().get("Stripe-Should-Retry").is_none_or(|v| v != "false");
+            let err = Error::RateLimited {
+                retry_after,
+                body: resp.text().await?,
+            };
+            match self.policy.next_action(attempt, &err) {
+                ReconnectAction::Delay(d) if should_retry => self.clock.sleep(d.max(retry_after.unwrap_or_default())).await,
+                _ => return Err(err),
+            }
+        }
+    }
+
+    fn headers(&self) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("identity"));
+        headers.insert(USER_AGENT, HeaderValue::from_str(&format!("Stripe/v1 stripe-cli/{}", CLI_VERSION))?);
+        headers.insert("X-Stripe-Client-User-Agent", HeaderValue::from_str(&client_user_agent())?);
+        if !self.api_key.is_empty() {
+            let mut auth = HeaderValue::from_str(&format!("Bearer {}", self.api_key.expose()))?;
+            auth.set_sensitive(true);
+            headers.insert(AUTHORIZATION, auth);
+        }
+        if let Some(account) = &self.stripe_account {
+            headers.insert("Stripe-Account", HeaderValue::from_str(account)?);
+        }
+        Ok(headers)
+    }
+
+    /// POSTs form params; non-2xx responses other than an exhausted 429 come
+    /// back as the raw response so callers can map them to their own error.
+    pub(crate) async fn post_form(&self, path: &str, params: &[(&str, &str)]) -> Result<reqwest::Response> {
+        let headers = self.headers()?;
+        self.send(|| self.client.post(format!("{}{}", API_BASE, path)).headers(headers.clone()).form(params))
+            .await
+    }
+
+    /// GETs a JSON resource; non-2xx responses become Error::Api.
+    pub(crate) async fn get_json(&self, path: &str, query: &[(&str, &str)]) -> Result<Value> {
+        let headers = self.headers()?;
+        let resp = self
+            .send(|| self.client.get(format!("{}{}", API_BASE, path)).headers(headers.clone()).query(query))
+            .await?;
+        if !resp.status().is_success() {
+            let status = resp.status().as_u16();
+            let body = resp.text().await?;
+            return Err(Error::Api { status, body });
+        }
+        Ok(resp.json().await?)
+    }
+
+    /// DELETEs a resource; non-2xx responses become Error::Api.
+    #[cfg(feature = "tunnel")]
+    pub(crate) async fn delete(&self, path: &str) -> Result<()> {
+        let headers = self.headers()?;
+        let resp = self
+            .send(|| self.client.delete(format!("{}{}", API_BASE, path)).headers(headers.clone()))
+            .await?;
+        if !resp.status().is_success() {
+            let status = resp.status().as_u16();
+            let body = resp.text().await?;
+            return Err(Error::Api { status, body });
+        }
+        Ok(())
+    }
+
+    /// The same client sending `account` as Stripe-Account, e.g. for an
+    /// object of a connected account's event. None keeps the configured one.
+    pub(crate) fn for_account(&self, account: Option<&str>) -> ApiClient {
+        let mut api = self.clone();
+        if let Some(account) = account {
+            api.stripe_account = Some(account.to_string());
+        }
+        api
+    }
+
+    /// Retrieves an object by its `object` type and id with `expand[]`
+    /// paths: `invoice` from /v1/invoices/{id}, `checkout.session` from
+    /// /v1/checkout/sessions/{id}.
+    pub(crate) async fn fetch_object(&self, object: &str, id: &str, expand: &[&str]) -> Result<Value> {
+        let path = format!("/v1/{}s/{}", object.replace('.', "/"), id);
+        let query: Vec<(&str, &str)> = expand.iter().map(|p| ("expand[]", *p)).collect();
+        self.get_json(&path, &query).await
+    }
+
+    /// Fetches the full event object, e.g. to replace a truncated delivery.
+    pub(crate) async fn fetch_event(&self, id: &str) -> Result<Value> {
+        self.get_json(&format!("/v1/events/{}", id), &[]).await
+    }
+
+    /// Lists every event created at or after `created_gte` (unix seconds),
+    /// oldest first.
+    pub(crate) async fn list_events_since(&self, created_gte: i64) -> Result<Vec<Value>> {
+        let created = created_gte.to_string();
+        let mut events = self.list_all("/v1/events", &[("created[gte]", created.as_str())]).await?;
+        // The API lists newest first.
+        events.reverse();
+        Ok(events)
+    }
+
+    /// Lists the events created in `[created_gte, created_lt)`; with
+    /// `undelivered`, only those not yet delivered successfully to every
+    /// webhook endpoint subscribed to them.
+    pub(crate) async fn list_events_between(&self, created_gte: u64, created_lt: u64, undelivered: bool) -> Result<Vec<Value>> {
+        let (gte, lt) = (created_gte.to_string(), created_lt.to_string());
+        let mut query = vec![("created[gte]", gte.as_str()), ("created[lt]", lt.as_str())];
+        if undelivered {
+            query.push(("delivery_success", "false"));
+        }
+        self.list_all("/v1/events", &query).await
+    }
+
+    /// An event destination's configuration.
+    pub(crate) async fn fetch_event_destination(&self, id: &str) -> Result<Value> {
+        self.get_json(&format!("/v2/core/event_destinations/{}", id), &[]).await
+    }
+
+    /// Creates a webhook endpoint; the response includes its signing secret.
+    #[cfg(feature = "tunnel")]
+    pub(crate) async fn create_webhook_endpoint(&self, url: &str, enabled_events: &[String], api_version: Option<&str>) -> Result<Value> {
+        let mut params = vec![("url", url), ("description", "stripelistener tunnel")];
+        params.extend(enabled_events.iter().map(|e| ("enabled_events[]", e.as_str())));
+        if let Some(version) = api_version {
+            params.push(("api_version", version));
+        }
+        let resp = self.post_form("/v1/webhook_endpoints", &params).await?;
+        if !resp.status().is_success() {
+            let status = resp.status().as_u16();
+            let body = resp.text().await?;
+            return Err(Error::Api { status, body });
+        }
+        Ok(resp.json().await?)
+    }
+
+    /// Enabled webhook endpoints on the account.
+    pub(crate) async fn list_enabled_endpoints(&self) -> Result<Vec<Value>> {
+        let endpoints = self.list_all("/v1/webhook_endpoints", &[]).await?;
+        Ok(endpoints
+            .into_iter()
+            .filter(|e| e.get("status").and_then(Value::as_str) == Some("enabled"))
+            .collect())
+    }
+
+    // Follows `has_more` / `starting_after` pagination of a list endpoint.
+    async fn list_all(&self, path: &str, query: &[(&str, &str)]) -> Result<Vec<Value>> {
+        let mut items = Vec::new();
+        let mut starting_after: Option<String> = None;
+        loop {
+            let mut query = query.to_vec();
+            query.push(("limit", "100"));
+            if let Some(after) = &starting_after {
+                query.push(("starting_after", after.as_str()));
+            }
+            let page = self.get_json(path, &query).await?;
+            let data = page.get("data").and_then(Value::as_array).cloned().unwrap_or_default();
+            starting_after = data.last().and_then(|e| e.get("id")).and_then(Value::as_str).map(str::to_string);
+            items.extend(data);
+            if starting_after.is_none() || !page.get("has_more").and_then(Value::as_bool).unwrap_or(false) {
+                break;
+            }
+        }
+        Ok(items)
+    }
+}
+
+/// Pulls the top-level event id (`evt_...`) out of a payload that may be cut
+/// off and no longer valid JSON. The envelope starts with `"id"`, so it
+/// survives any truncation that matters.
+pub(crate) fn truncated_event_id(payload: &str) -> Option<&str> {
+    let rest = &payload[payload.find("\"id\"")? + 4..];
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start().strip_prefix('"')?;
+    let id = &rest[..rest.find('"')?];
+    id.starts_with("evt_").then_some(id)
+}
@@ -0,0 +1,137 @@
+// Batched dispatch for handlers that write events in bulk: webhook events
+// are collected and passed to EventHandler::on_webhook_batch together, and
+// acknowledged only once the handler accepts the batch.
+use std::time::{Duration, SystemTime};
+
+use serde::Deserialize;
+
+use crate::config_file::de_duration;
+use crate::dispatch::{Admitted, Dispatcher};
+use crate::transport::{send_ack, Acker, WriteQueue};
+use crate::{AckFields, Clock, LogLevel, StripeEventPayload, WebhookEvent};
+#[cfg(feature = "tower")]
+use crate::{V2Event, V2EventPayload};
+
+const DEFAULT_MAX_SIZE: usize = 100;
+const DEFAULT_MAX_WAIT: Duration = Duration::from_secs(1);
+
+/// Webhook events are dispatched to EventHandler::on_webhook_batch in
+/// batches instead of one by one. A batch is flushed when it holds
+/// `max_size` events or its first event has waited `max_wait`, whichever
+/// comes first, and when the connection ends.
+///
+/// Acks are sent and forward routes called per event after the handler
+/// accepts the batch. A rejected batch is neither acknowledged nor
+/// forwarded, so Stripe redelivers its events. Events that the filter or
+/// sampling drop are acknowledged right away. V2 events are not batched.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Batching {
+    pub max_size: usize,
+    #[serde(deserialize_with = "de_duration")]
+    pub max_wait: Duration,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_SIZE,
+            max_wait: DEFAULT_MAX_WAIT,
+        }
+    }
+}
+
+// What an ack needs, taken before the event moves into the pipeline.
+pub(crate) struct AckKey {
+    event_id: String,
+    webhook_id: String,
+    conversation_id: String,
+}
+
+impl AckKey {
+    pub(crate) fn of(evt: &WebhookEvent, parsed: &StripeEventPayload) -> Self {
+        Self {
+            event_id: parsed.id.clone(),
+            webhook_id: evt.webhook_id.clone(),
+            conversation_id: evt.webhook_conversation_id.clone(),
+        }
+    }
+
+    #[cfg(feature = "tower")]
+    pub(crate) fn of_v2(evt: &V2Event, parsed: &V2EventPayload) -> Self {
+        Self {
+            event_id: parsed.id.clone(),
+            webhook_id: evt.destination_id.clone(),
+            conversation_id: String::new(),
+        }
+    }
+
+    pub(crate) fn fields(&self) -> AckFields<'_> {
+        AckFields {
+            event_id: &self.event_id,
+            webhook_id: &self.webhook_id,
+            webhook_conversation_id: &self.conversation_id,
+        }
+    }
+}
+
+// The events of one connection waiting to be flushed.
+pub(crate) struct Batch {
+    limits: Batching,
+    items: Vec<Admitted>,
+    acks: Vec<AckKey>,
+    started: Option<SystemTime>,
+}
+
+impl Batch {
+    pub(crate) fn new(limits: Batching) -> Self {
+        Self {
+            limits,
+            items: Vec::new(),
+            acks: Vec::new(),
+            started: None,
+        }
+    }
+
+    /// Adds an event that passed Dispatcher::admit; true once the batch is
+    /// full.
+    pub(crate) fn push(&mut self, key: AckKey, admitted: Admitted, now: SystemTime) -> bool {
+        self.acks.push(key);
+        self.items.push(admitted);
+        self.started.get_or_insert(now);
+        self.items.len() >= self.limits.max_size.max(1)
+    }
+
+    /// Resolves when the oldest pending event has waited `max_wait`; never
+    /// while the batch is empty.
+    pub(crate) async fn due(&self, clock: &dyn Clock) {
+        match self.started {
+            Some(started) => {
+                let waited = clock.now().duration_since(started).unwrap_or_default();
+                clock.sleep(self.limits.max_wait.saturating_sub(waited)).await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Hands the pending events to the handler and, if it accepts them,
+    /// queues their acks.
+    pub(crate) async fn flush(&mut self, tx: &WriteQueue, dispatcher: &Dispatcher, acker: &Acker) {
+        if self.items.is_empty() {
+            return;
+        }
+        self.started = None;
+        let acks = std::mem::take(&mut self.acks);
+        if !dispatcher.webhook_batch(std::mem::take(&mut self.items)) {
+            dispatcher.logger.log(
+                LogLevel::Warn,
+                "batch rejected; its events are not acknowledged",
+                &[("size", &acks.len()), ("first_event_id", &acks[0].event_id)],
+            );
+            return;
+        }
+        for key in &acks {
+            send_ack(tx, dispatcher, acker, key.fields()).await;
+        }
+    }
+}
@@ -0,0 +1,79 @@
+// Time source for pings, backoff, retry deadlines and stats timestamps, so
+// tests can drive time with tokio's pause/advance and simulate host sleep.
+use std::future::Future;
+use std::pin::Pin;
+#[cfg(feature = "testing")]
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use tokio::time::Instant;
+
+pub trait Clock: Send + Sync {
+    /// Wall-clock time, used for timestamps and sleep detection.
+    fn now(&self) -> SystemTime;
+    /// Monotonic time, used for elapsed times and deadlines.
+    fn instant(&self) -> Instant;
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+/// The default clock: the system wall clock and tokio's timer, which honors
+/// `tokio::time::pause()` and `advance()`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Deterministic clock for tests. Its wall clock starts at a fixed time and
+/// moves with tokio time (so a paused runtime controls it), plus any jumps
+/// made with `jump`.
+#[cfg(feature = "testing")]
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    base: SystemTime,
+    start: Instant,
+    jumped: Arc<Mutex<Duration>>,
+}
+
+#[cfg(feature = "testing")]
+impl MockClock {
+    pub fn new(base: SystemTime) -> Self {
+        Self {
+            base,
+            start: Instant::now(),
+            jumped: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    /// Moves the wall clock forward without monotonic time passing, as
+    /// happens across a laptop suspend.
+    pub fn jump(&self, by: Duration) {
+        *self.jumped.lock().unwrap_or_else(|e| e.into_inner()) += by;
+    }
+}
+
+#[cfg(feature = "testing")]
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        self.base + self.start.elapsed() + *self.jumped.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
@@ -0,0 +1,530 @@
+// Listener configuration: the Config passed to StripeListener::new, the
+// hot-reloadable LiveConfig behind ConfigHandle, and reconnect policies.
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+const DEFAULT_PONG_WAIT: Duration = Duration::from_secs(10);
+const DEFAULT_PING_PERIOD: Duration = Duration::from_secs(2);
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_AUTHORIZE_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_RESUME_THRESHOLD: Duration = Duration::from_secs(30);
+const DEFAULT_CLOCK_SKEW_WARNING: Duration = Duration::from_secs(60);
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 << 20;
+const DEFAULT_MAX_FRAME_SIZE: usize = 16 << 20;
+#[cfg(feature = "forwarder")]
+const DEFAULT_RECENT_DELIVERIES: usize = 50;
+// const DEFAULT_WRITE_WAIT: Duration = Duration::from_secs(1);
+
+/// What the listener should do after a connection attempt fails or drops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReconnectAction {
+    /// Wait, then reconnect with the current session.
+    Delay(Duration),
+    /// Discard the session and call authorize() again before reconnecting.
+    Reauthorize,
+    /// Stop and return the error from run().
+    GiveUp,
+}
+
+/// Decides how run() recovers from failures. `attempt` starts at 1 and resets
+/// once a connection is established. REST calls consult it too when they are
+/// rate limited, with `Error::RateLimited` and their own attempt count; any
+/// action other than Delay stops retrying.
+pub trait ReconnectPolicy: Send + Sync {
+    fn next_action(&self, attempt: u32, error: &Error) -> ReconnectAction;
+
+    /// Whether an error message from the server ends the connection. Fatal
+    /// errors close it and come back to next_action as Error::Server; the
+    /// rest are logged and reported to the handler only.
+    fn is_fatal(&self, error: &ServerError) -> bool {
+        error.is_fatal()
+    }
+}
+
+/// Retries with exponentially growing delays. Gives up on rejected API keys
+/// and on close codes that reconnecting cannot fix.
+#[derive(Debug, Clone)]
+pub struct ExponentialBackoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub multiplier: f64,
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            multiplier: 2.0,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ExponentialBackoff {
+    fn delay_for(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.powi(attempt.saturating_sub(1).min(i32::MAX as u32) as i32);
+        self.initial.mul_f64(factor).min(self.max)
+    }
+}
+
+impl ReconnectPolicy for ExponentialBackoff {
+    fn next_action(&self, attempt: u32, error: &Error) -> ReconnectAction {
+        if self.max_attempts.is_some_and(|max| attempt > max) {
+            return ReconnectAction::GiveUp;
+        }
+        match error {
+            Error::Authorize { status: 401 | 403, .. } => ReconnectAction::GiveUp,
+            Error::Closed(reason) if !reason.is_retryable() => ReconnectAction::GiveUp,
+            Error::Protocol(_) => ReconnectAction::GiveUp,
+            Error::WebSocket(e)
+                if matches!(e.as_ref(), tokio_tungstenite::tungstenite::Error::Http(resp)
+                    if matches!(resp.status().as_u16(), 401 | 403 | 404))
+                    && attempt == 1 =>
+            {
+                ReconnectAction::Reauthorize
+            }
+            Error::Resumed { .. } => ReconnectAction::Reauthorize,
+            Error::Server(e) if e.is_unauthorized() => ReconnectAction::GiveUp,
+            Error::Server(e) if e.is_session_invalid() => ReconnectAction::Reauthorize,
+            Error::RateLimited { retry_after, .. } => ReconnectAction::Delay(self.delay_for(attempt).max(retry_after.unwrap_or_default())),
+            Error::Other(_) | Error::Config(_) | Error::Tls(_) => ReconnectAction::GiveUp,
+            _ => ReconnectAction::Delay(self.delay_for(attempt)),
+        }
+    }
+}
+
+/// Never reconnects; run() returns the first error. Useful in CI.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Never;
+
+impl ReconnectPolicy for Never {
+    fn next_action(&self, _attempt: u32, _error: &Error) -> ReconnectAction {
+        ReconnectAction::GiveUp
+    }
+}
+
+/// Always reconnects after a fixed delay, whatever the error.
+#[derive(Debug, Clone, Copy)]
+pub struct Always(pub Duration);
+
+impl Default for Always {
+    fn default() -> Self {
+        Always(Duration::from_secs(1))
+    }
+}
+
+impl ReconnectPolicy for Always {
+    fn next_action(&self, _attempt: u32, _error: &Error) -> ReconnectAction {
+        ReconnectAction::Delay(self.0)
+    }
+}
+
+// Hot-reloadable configuration
+/// Settings the running listener re-reads for every message, so they can be
+/// changed through a ConfigHandle without reconnecting.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct LiveConfig {
+    /// Event types to dispatch; `None` or `"*"` dispatches everything.
+    pub events: Option<Vec<EventType>>,
+    /// Endpoints that webhook payloads are POSTed to, like
+    /// `stripe listen --forward-to`.
+    #[cfg(feature = "forwarder")]
+    pub forward: Vec<ForwardRoute>,
+    /// Secondary endpoints that get a copy of every matching delivery, e.g.
+    /// a teammate's tunnel. One attempt each; failures are only logged and
+    /// never reach on_forward_result or the dead-letter sink.
+    #[cfg(feature = "forwarder")]
+    #[serde(default)]
+    pub mirror: Vec<ForwardRoute>,
+    pub log_level: LogLevel,
+    /// Account, livemode and created-time conditions, applied after the
+    /// event-type filter.
+    #[serde(default)]
+    pub filter: Option<EventFilter>,
+    /// Dispatch only a sample of the events that pass the filter.
+    #[serde(default)]
+    pub sampling: Option<Sampling>,
+}
+
+impl LiveConfig {
+    pub(crate) fn from_config(cfg: &Config) -> Self {
+        Self {
+            events: cfg.events.clone(),
+            #[cfg(feature = "forwarder")]
+            forward: cfg.forward.clone().unwrap_or_default(),
+            #[cfg(feature = "forwarder")]
+            mirror: cfg.mirror.clone().unwrap_or_default(),
+            log_level: cfg.log_level.unwrap_or_default(),
+            filter: cfg.filter.clone(),
+            sampling: cfg.sampling.clone(),
+        }
+    }
+
+    /// Whether an event type passes the configured filter.
+    pub fn matches_event(&self, event_type: &str) -> bool {
+        match &self.events {
+            None => true,
+            Some(events) => events.iter().any(|e| e.is_wildcard() || e == event_type),
+        }
+    }
+}
+
+/// Shared handle to the listener's LiveConfig. Each setter swaps in a new
+/// snapshot, so the read loop never observes a half-applied update.
+#[derive(Clone)]
+pub struct ConfigHandle {
+    inner: Arc<RwLock<Arc<LiveConfig>>>,
+}
+
+impl ConfigHandle {
+    pub(crate) fn new(cfg: LiveConfig) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Arc::new(cfg))),
+        }
+    }
+
+    /// The configuration currently in effect.
+    pub fn snapshot(&self) -> Arc<LiveConfig> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Applies several changes as one atomic update.
+    pub fn update(&self, f: impl FnOnce(&mut LiveConfig)) {
+        let mut guard = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        let mut next = (**guard).clone();
+        f(&mut next);
+        *guard = Arc::new(next);
+    }
+
+    pub fn replace(&self, cfg: LiveConfig) {
+        *self.inner.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(cfg);
+    }
+
+    pub fn update_filters(&self, events: Option<Vec<EventType>>) {
+        self.update(|c| c.events = events);
+    }
+
+    /// Replaces all forward routes with a single catch-all route, or stops
+    /// forwarding when `url` is `None`.
+    #[cfg(feature = "forwarder")]
+    pub fn update_forward_target(&self, url: Option<String>) {
+        self.update(|c| c.forward = url.map(ForwardRoute::new).into_iter().collect());
+    }
+
+    #[cfg(feature = "forwarder")]
+    pub fn update_forward_routes(&self, routes: Vec<ForwardRoute>) {
+        self.update(|c| c.forward = routes);
+    }
+
+    pub fn update_filter(&self, filter: Option<EventFilter>) {
+        self.update(|c| c.filter = filter);
+    }
+
+    pub fn update_sampling(&self, sampling: Option<Sampling>) {
+        self.update(|c| c.sampling = sampling);
+    }
+
+    #[cfg(feature = "forwarder")]
+    pub fn update_mirror_routes(&self, routes: Vec<ForwardRoute>) {
+        self.update(|c| c.mirror = routes);
+    }
+
+    pub fn set_log_level(&self, level: LogLevel) {
+        self.update(|c| c.log_level = level);
+    }
+}
+
+/// What run() does when filtered event types are not enabled on any of the
+/// account's webhook endpoints.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EndpointCheck {
+    /// Skip the check.
+    #[default]
+    Off,
+    /// Log a warning listing the missing event types.
+    Warn,
+    /// Return Error::Config instead of connecting.
+    Refuse,
+}
+
+/// What the read loop does with a webhook event whose event_payload does not
+/// parse, even after fetching it from the API.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UnparseablePayload {
+    /// Log and acknowledge it, so Stripe stops redelivering.
+    DropAndAck,
+    /// Log it without an ack; Stripe keeps redelivering it.
+    #[default]
+    DropNoAck,
+    /// Acknowledge it and pass it to EventHandler::on_raw_webhook_event.
+    DeliverRaw,
+}
+
+// Configuration
+#[derive(Clone)]
+pub struct Config {
+    pub api_key: SecretString,
+    pub device_name: Option<String>,
+    pub websocket_features: Option<Vec<String>>,
+    pub handler: Arc<dyn EventHandler>,
+    pub logger: Option<Arc<dyn Logger>>,
+    pub pong_wait: Option<Duration>,
+    pub ping_period: Option<Duration>,
+    /// Recovery from connection failures; also decides whether and how long
+    /// rate-limited (HTTP 429) REST calls wait before retrying.
+    pub reconnect_policy: Option<Arc<dyn ReconnectPolicy>>,
+    /// Initial event filter; see LiveConfig::events.
+    pub events: Option<Vec<EventType>>,
+    /// Initial forward routes; see LiveConfig::forward.
+    #[cfg(feature = "forwarder")]
+    pub forward: Option<Vec<ForwardRoute>>,
+    /// Initial mirror routes; see LiveConfig::mirror.
+    #[cfg(feature = "forwarder")]
+    pub mirror: Option<Vec<ForwardRoute>>,
+    pub log_level: Option<LogLevel>,
+    /// Log the first per-event message of each type in full and summarize
+    /// the rest per window (default off); see LogCoalescing.
+    pub log_coalescing: Option<LogCoalescing>,
+    /// Initial account, livemode and created-time filter; see
+    /// LiveConfig::filter.
+    pub filter: Option<EventFilter>,
+    /// Maps webhook events to tenants, passed to
+    /// EventHandler::on_tenant_webhook_event, counted per tenant in the
+    /// SessionReport and filtered by EventFilter::tenants.
+    pub tenant_resolver: Option<Arc<dyn TenantResolver>>,
+    /// Drops webhook events it returns false for, after `events` and
+    /// `filter` and before sampling, dispatch and forwarding. Not applied
+    /// to v2 events.
+    pub predicate: Option<Arc<dyn EventPredicate>>,
+    /// Rewrites payloads before dispatch and forwarding; compose several
+    /// stages with a Pipeline.
+    pub transform: Option<Arc<dyn Transform>>,
+    /// Handlers for message types beyond webhook_event and v2_event.
+    pub messages: Option<MessageRegistry>,
+    /// TLS overrides for corporate proxies; see TlsOptions.
+    pub tls: Option<TlsOptions>,
+    /// Receives deliveries whose forwarding failed after all retries.
+    #[cfg(feature = "forwarder")]
+    pub dead_letter: Option<Arc<dyn DeadLetterSink>>,
+    /// Check every event payload against EventEnvelope and report
+    /// differences through EventHandler::on_schema_drift.
+    pub strict_parse: Option<bool>,
+    /// Largest websocket message accepted, in bytes (default 64 MiB). A
+    /// larger message fails the connection with a capacity error.
+    pub max_message_size: Option<usize>,
+    /// Largest single websocket frame accepted, in bytes (default 16 MiB).
+    pub max_frame_size: Option<usize>,
+    /// Fetch the objects of matching webhook events with extra expansions
+    /// before dispatch; see Expand.
+    pub expand: Option<Vec<Expand>>,
+    /// When an event payload arrives truncated, fetch the full event from
+    /// `GET /v1/events/{id}` instead of dropping it (default true).
+    pub rest_fallback: Option<bool>,
+    /// A wall-clock gap between pings this much longer than ping_period is
+    /// treated as the host having slept; the connection is torn down and
+    /// re-authorized (default 30s).
+    pub resume_threshold: Option<Duration>,
+    /// After a resume, fetch events created while asleep from the REST API
+    /// and run them through the normal pipeline (default false). Events near
+    /// the sleep boundary may be delivered twice.
+    pub catch_up_on_resume: Option<bool>,
+    /// Time source for pings, backoff, retries and stats; defaults to
+    /// TokioClock. Use MockClock in tests.
+    pub clock: Option<Arc<dyn Clock>>,
+    /// Warn when the clock and Stripe's signing time of a delivery differ
+    /// by more than this (default 60s); see ListenerStats::clock_skew_secs.
+    pub clock_skew_warning: Option<Duration>,
+    /// Before connecting, compare the event filters against the account's
+    /// enabled webhook endpoints (default Off).
+    pub verify_endpoints: Option<EndpointCheck>,
+    /// Connected account (`acct_...`) sent as Stripe-Account on every API
+    /// request, scoping the session to that account.
+    pub stripe_account: Option<String>,
+    /// Records the last dispatched event. When set, run() first catches up
+    /// on events created since the stored position, and resume catch-up
+    /// starts from it too.
+    pub cursor: Option<Arc<dyn Cursor>>,
+    /// Adds headers or changes subprotocols on the websocket handshake, e.g.
+    /// for an egress gateway that requires its own auth header.
+    pub handshake: Option<Arc<dyn HandshakeCustomizer>>,
+    /// Builds event acks for the negotiated subprotocol; defaults to
+    /// DevproxyV1Ack. Its subprotocols are offered on the handshake.
+    pub ack_format: Option<Arc<dyn AckFormat>>,
+    /// How long ListenerHandle::shutdown waits for queued acks and in-flight
+    /// forwards before giving up on them (default 10s).
+    pub drain_timeout: Option<Duration>,
+    /// Webhook events created longer ago than this, e.g. redeliveries after
+    /// a long disconnect, go to EventHandler::on_stale_event instead of the
+    /// handler and forward routes (default none). Age is measured from the
+    /// payload's `created` with `clock`; v2 events are not checked.
+    pub max_event_age: Option<Duration>,
+    /// Upper bound on authorize(), rate-limit retries included; it fails
+    /// with `Error::Timeout` once this runs out (default none).
+    pub authorize_timeout: Option<Duration>,
+    /// How long one session request may wait for Stripe's response before
+    /// authorize() fails with `Error::Timeout` (default 30s).
+    pub authorize_attempt_timeout: Option<Duration>,
+    /// Initial sampling limits; see LiveConfig::sampling.
+    pub sampling: Option<Sampling>,
+    /// What run() does when another listener with the same device name is
+    /// running on this host (default Warn). Listeners on other hosts are not
+    /// detected; see DuplicateSessions.
+    pub duplicate_sessions: Option<DuplicateSessions>,
+    /// Dispatch webhook events in batches to EventHandler::on_webhook_batch,
+    /// acknowledging them once the batch is accepted (default off).
+    pub batching: Option<Batching>,
+    /// Deliver events that pass the filters to this tower Service instead
+    /// of EventHandler::on_webhook_event and on_v2_event, acknowledging each
+    /// by the service's AckDecision and forwarding it once that is Ack; see
+    /// EventService. Batching is not applied, and replays and tunnel
+    /// deliveries still go to the handler.
+    #[cfg(feature = "tower")]
+    pub service: Option<EventService>,
+    /// Compare the events received here with Stripe's deliveries to the
+    /// account's webhook endpoints (default off); see Shadow.
+    pub shadow: Option<Shadow>,
+    /// What to do with webhook events whose payload does not parse (default
+    /// DropNoAck).
+    pub unparseable_payload: Option<UnparseablePayload>,
+    /// How many forwarded requests ListenerHandle::recent_deliveries keeps
+    /// (default 50); 0 keeps none.
+    #[cfg(feature = "forwarder")]
+    pub recent_deliveries: Option<usize>,
+}
+
+impl Config {
+    /// Config with every optional field unset; defaults are filled in by
+    /// StripeListener::new.
+    pub fn new(api_key: impl Into<SecretString>, handler: Arc<dyn EventHandler>) -> Self {
+        Self {
+            api_key: api_key.into(),
+            device_name: None,
+            websocket_features: None,
+            handler,
+            logger: None,
+            pong_wait: None,
+            ping_period: None,
+            reconnect_policy: None,
+            events: None,
+            #[cfg(feature = "forwarder")]
+            forward: None,
+            #[cfg(feature = "forwarder")]
+            mirror: None,
+            log_level: None,
+            log_coalescing: None,
+            filter: None,
+            tenant_resolver: None,
+            predicate: None,
+            transform: None,
+            messages: None,
+            tls: None,
+            #[cfg(feature = "forwarder")]
+            dead_letter: None,
+            strict_parse: None,
+            max_message_size: None,
+            max_frame_size: None,
+            expand: None,
+            rest_fallback: None,
+            resume_threshold: None,
+            catch_up_on_resume: None,
+            clock: None,
+            clock_skew_warning: None,
+            verify_endpoints: None,
+            stripe_account: None,
+            cursor: None,
+            handshake: None,
+            ack_format: None,
+            drain_timeout: None,
+            max_event_age: None,
+            authorize_timeout: None,
+            authorize_attempt_timeout: None,
+            sampling: None,
+            duplicate_sessions: None,
+            batching: None,
+            #[cfg(feature = "tower")]
+            service: None,
+            shadow: None,
+            unparseable_payload: None,
+            #[cfg(feature = "forwarder")]
+            recent_deliveries: None,
+        }
+    }
+
+    pub(crate) fn defaults(&mut self) {
+        if self.device_name.is_none() {
+            self.device_name = Some("custom-stripe-listener".to_string());
+        }
+        if self.websocket_features.is_none() {
+            self.websocket_features = Some(vec!["webhooks".to_string()]);
+        }
+        if self.pong_wait.is_none() {
+            self.pong_wait = Some(DEFAULT_PONG_WAIT);
+        }
+        if self.ping_period.is_none() {
+            self.ping_period = Some(DEFAULT_PING_PERIOD);
+        }
+        if self.logger.is_none() {
+            self.logger = Some(Arc::new(NopLogger));
+        }
+        if self.reconnect_policy.is_none() {
+            self.reconnect_policy = Some(Arc::new(ExponentialBackoff::default()));
+        }
+        if self.tls.is_none() {
+            self.tls = Some(TlsOptions::default());
+        }
+        if self.log_level.is_none() {
+            self.log_level = Some(LogLevel::default());
+        }
+        if self.max_message_size.is_none() {
+            self.max_message_size = Some(DEFAULT_MAX_MESSAGE_SIZE);
+        }
+        if self.max_frame_size.is_none() {
+            self.max_frame_size = Some(DEFAULT_MAX_FRAME_SIZE);
+        }
+        if self.rest_fallback.is_none() {
+            self.rest_fallback = Some(true);
+        }
+        if self.clock.is_none() {
+            self.clock = Some(Arc::new(TokioClock));
+        }
+        if self.clock_skew_warning.is_none() {
+            self.clock_skew_warning = Some(DEFAULT_CLOCK_SKEW_WARNING);
+        }
+        if self.resume_threshold.is_none() {
+            self.resume_threshold = Some(DEFAULT_RESUME_THRESHOLD);
+        }
+        if self.ack_format.is_none() {
+            self.ack_format = Some(Arc::new(DevproxyV1Ack));
+        }
+        if self.drain_timeout.is_none() {
+            self.drain_timeout = Some(DEFAULT_DRAIN_TIMEOUT);
+        }
+        if self.authorize_attempt_timeout.is_none() {
+            self.authorize_attempt_timeout = Some(DEFAULT_AUTHORIZE_ATTEMPT_TIMEOUT);
+        }
+        #[cfg(feature = "forwarder")]
+        if self.recent_deliveries.is_none() {
+            self.recent_deliveries = Some(DEFAULT_RECENT_DELIVERIES);
+        }
+    }
+
+    // Checks made before events flow, by listeners and replays alike.
+    #[cfg_attr(not(all(feature = "forwarder", not(feature = "types"))), allow(unused_variables))]
+    pub(crate) fn check(&self, live: &LiveConfig) -> Result<()> {
+        // Without `types` a transformed delivery cannot be re-signed, and
+        // Stripe's signature no longer matches it.
+        #[cfg(all(feature = "forwarder", not(feature = "types")))]
+        if self.transform.is_some() && (!live.forward.is_empty() || !live.mirror.is_empty()) {
+            return Err(Error::Config("a transform with forward or mirror routes needs the `types` feature to re-sign deliveries".to_string()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+// Declarative configuration loaded from TOML or YAML, with STRIPE_LISTENER_*
+// environment overrides.
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+
+#[cfg(feature = "forwarder")]
+use crate::ForwardRoute;
+use crate::{
+    Always, Batching, Config, ConfigHandle, DuplicateSessions, EndpointCheck, Error, EventFilter, EventType, Expand, ExponentialBackoff, LogCoalescing, LogLevel, Never,
+    NopHandler, Sampling, ReconnectPolicy, Result, SecretString, Shadow, TlsOptions, TlsVersion, UnparseablePayload,
+};
+
+const ENV_PREFIX: &str = "STRIPE_LISTENER_";
+
+/// On-disk form of Config. Durations accept a number of seconds or a string
+/// such as `"500ms"`, `"10s"` or `"2m"`.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    /// Inline API key. Prefer `api_key_env` or `api_key_file` to keep secrets
+    /// out of the file.
+    pub api_key: Option<SecretString>,
+    /// Name of an environment variable holding the API key.
+    pub api_key_env: Option<String>,
+    /// Path of a file whose trimmed contents are the API key.
+    pub api_key_file: Option<String>,
+    pub device_name: Option<String>,
+    pub websocket_features: Option<Vec<String>>,
+    pub events: Option<Vec<EventType>>,
+    /// Shorthand for a single catch-all forward route.
+    #[cfg(feature = "forwarder")]
+    pub forward_to: Option<String>,
+    #[cfg(feature = "forwarder")]
+    pub forward: Vec<ForwardRoute>,
+    /// Mirror routes; see LiveConfig::mirror.
+    #[cfg(feature = "forwarder")]
+    pub mirror: Vec<ForwardRoute>,
+    pub log_level: Option<LogLevel>,
+    /// `[log_coalescing]` table; see LogCoalescing.
+    pub log_coalescing: Option<LogCoalescing>,
+    #[serde(deserialize_with = "de_duration_opt")]
+    pub pong_wait: Option<Duration>,
+    #[serde(deserialize_with = "de_duration_opt")]
+    pub ping_period: Option<Duration>,
+    pub reconnect: Option<ReconnectConfig>,
+    pub tls: Option<TlsConfig>,
+    pub strict_parse: Option<bool>,
+    /// Websocket limits in bytes; see Config::max_message_size.
+    pub max_message_size: Option<usize>,
+    pub max_frame_size: Option<usize>,
+    /// `[[expand]]` tables; see Expand.
+    pub expand: Vec<Expand>,
+    pub rest_fallback: Option<bool>,
+    #[serde(deserialize_with = "de_duration_opt")]
+    pub resume_threshold: Option<Duration>,
+    pub catch_up_on_resume: Option<bool>,
+    #[serde(deserialize_with = "de_duration_opt")]
+    pub clock_skew_warning: Option<Duration>,
+    /// `off`, `warn` or `refuse`; see EndpointCheck.
+    pub verify_endpoints: Option<EndpointCheck>,
+    pub stripe_account: Option<String>,
+    #[serde(deserialize_with = "de_duration_opt")]
+    pub drain_timeout: Option<Duration>,
+    #[serde(deserialize_with = "de_duration_opt")]
+    pub max_event_age: Option<Duration>,
+    #[serde(deserialize_with = "de_duration_opt")]
+    pub authorize_timeout: Option<Duration>,
+    #[serde(deserialize_with = "de_duration_opt")]
+    pub authorize_attempt_timeout: Option<Duration>,
+    /// `[filter]` table; see EventFilter.
+    pub filter: Option<EventFilter>,
+    /// `[sampling]` table; see Sampling.
+    pub sampling: Option<Sampling>,
+    /// `warn`, `shared` or `takeover`; see DuplicateSessions.
+    pub duplicate_sessions: Option<DuplicateSessions>,
+    /// `[batching]` table; see Batching.
+    pub batching: Option<Batching>,
+    /// `[shadow]` table; see Shadow.
+    pub shadow: Option<Shadow>,
+    /// `drop_and_ack`, `drop_no_ack` or `deliver_raw`; see UnparseablePayload.
+    pub unparseable_payload: Option<UnparseablePayload>,
+    #[cfg(feature = "forwarder")]
+    pub recent_deliveries: Option<usize>,
+}
+
+/// `[tls]` table; see TlsOptions. `ca_files` are PEM paths.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub insecure_skip_verify: bool,
+    pub ca_files: Vec<String>,
+    pub min_version: Option<TlsVersion>,
+}
+
+impl TlsConfig {
+    fn into_options(self) -> Result<TlsOptions> {
+        let mut tls = TlsOptions {
+            insecure_skip_verify: self.insecure_skip_verify,
+            extra_root_certs: Vec::new(),
+            min_version: self.min_version,
+        };
+        for path in &self.ca_files {
+            tls.add_root_cert_file(path)?;
+        }
+        Ok(tls)
+    }
+}
+
+/// `[reconnect]` table selecting one of the built-in policies.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "strategy", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ReconnectConfig {
+    Exponential {
+        #[serde(default, deserialize_with = "de_duration_opt")]
+        initial: Option<Duration>,
+        #[serde(default, deserialize_with = "de_duration_opt")]
+        max: Option<Duration>,
+        multiplier: Option<f64>,
+        max_attempts: Option<u32>,
+    },
+    Never,
+    Always {
+        #[serde(default, deserialize_with = "de_duration_opt")]
+        delay: Option<Duration>,
+    },
+}
+
+impl ReconnectConfig {
+    fn into_policy(self) -> Arc<dyn ReconnectPolicy> {
+        match self {
+            ReconnectConfig::Exponential { initial, max, multiplier, max_attempts } => {
+                let defaults = ExponentialBackoff::default();
+                Arc::new(ExponentialBackoff {
+                    initial: initial.unwrap_or(defaults.initial),
+                    max: max.unwrap_or(defaults.max),
+                    multiplier: multiplier.unwrap_or(defaults.multiplier),
+                    max_attempts,
+                })
+            }
+            ReconnectConfig::Never => Arc::new(Never),
+            ReconnectConfig::Always { delay } => Arc::new(delay.map(Always).unwrap_or_default()),
+        }
+    }
+}
+
+impl FileConfig {
+    /// Reads a `.yaml`/`.yml` file as YAML and anything else as TOML, then
+    /// applies environment overrides.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+        let is_yaml = matches!(path.extension().and_then(|e| e.to_str()), Some("yaml" | "yml"));
+        let mut file: FileConfig = if is_yaml {
+            serde_yaml::from_str(&text).map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?
+        } else {
+            toml::from_str(&text).map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?
+        };
+        file.apply_env(|key| std::env::var(format!("{}{}", ENV_PREFIX, key)).ok())?;
+        Ok(file)
+    }
+
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        if let Some(v) = var("API_KEY") {
+            self.api_key = Some(v.into());
+        }
+        if let Some(v) = var("STRIPE_ACCOUNT") {
+            self.stripe_account = Some(v);
+        }
+        if let Some(v) = var("DEVICE_NAME") {
+            self.device_name = Some(v);
+        }
+        if let Some(v) = var("WEBSOCKET_FEATURES") {
+            self.websocket_features = Some(split_list(&v));
+        }
+        if let Some(v) = var("EVENTS") {
+            self.events = Some(split_list(&v).into_iter().map(EventType::from).collect());
+        }
+        #[cfg(feature = "forwarder")]
+        if let Some(v) = var("FORWARD_TO") {
+            self.forward_to = Some(v);
+        }
+        if let Some(v) = var("LOG_LEVEL") {
+            self.log_level = Some(
+                serde_json::from_value(serde_json::Value::String(v.to_lowercase()))
+                    .map_err(|_| Error::Config(format!("{}LOG_LEVEL: unknown level {:?}", ENV_PREFIX, v)))?,
+            );
+        }
+        if let Some(v) = var("PONG_WAIT") {
+            self.pong_wait = Some(parse_duration(&v).map_err(|e| Error::Config(format!("{}PONG_WAIT: {}", ENV_PREFIX, e)))?);
+        }
+        if let Some(v) = var("PING_PERIOD") {
+            self.ping_period = Some(parse_duration(&v).map_err(|e| Error::Config(format!("{}PING_PERIOD: {}", ENV_PREFIX, e)))?);
+        }
+        if let Some(v) = var("TLS_INSECURE_SKIP_VERIFY") {
+            self.tls.get_or_insert_with(TlsConfig::default).insecure_skip_verify = matches!(v.as_str(), "1" | "true" | "yes");
+        }
+        if let Some(v) = var("TLS_CA_FILES") {
+            self.tls.get_or_insert_with(TlsConfig::default).ca_files = split_list(&v);
+        }
+        if let Some(v) = var("RECONNECT") {
+            self.reconnect = Some(match v.as_str() {
+                "exponential" => ReconnectConfig::Exponential { initial: None, max: None, multiplier: None, max_attempts: None },
+                "never" => ReconnectConfig::Never,
+                "always" => ReconnectConfig::Always { delay: None },
+                other => return Err(Error::Config(format!("{}RECONNECT: unknown strategy {:?}", ENV_PREFIX, other))),
+            });
+        }
+        Ok(())
+    }
+
+    fn resolve_api_key(&self) -> Result<SecretString> {
+        if let Some(key) = &self.api_key {
+            return Ok(key.clone());
+        }
+        if let Some(var) = &self.api_key_env {
+            return std::env::var(var)
+                .map(SecretString::from)
+                .map_err(|_| Error::Config(format!("api_key_env: {} is not set", var)));
+        }
+        if let Some(path) = &self.api_key_file {
+            return std::fs::read_to_string(path)
+                .map(|s| SecretString::from(s.trim()))
+                .map_err(|e| Error::Config(format!("api_key_file {}: {}", path, e)));
+        }
+        Err(Error::Config(format!(
+            "no api key: set api_key, api_key_env, api_key_file or {}API_KEY",
+            ENV_PREFIX
+        )))
+    }
+
+    /// Forward routes with `forward_to` folded in as a catch-all route.
+    #[cfg(feature = "forwarder")]
+    pub fn forward_routes(&self) -> Vec<ForwardRoute> {
+        let mut routes = self.forward.clone();
+        if let Some(url) = &self.forward_to {
+            routes.insert(0, ForwardRoute::new(url.clone()));
+        }
+        routes
+    }
+
+    /// Applies the hot-reloadable subset. A missing log_level keeps the
+    /// current level.
+    pub(crate) fn apply_live(&self, handle: &ConfigHandle) {
+        #[cfg(feature = "forwarder")]
+        let routes = self.forward_routes();
+        handle.update(|c| {
+            c.events = self.events.clone();
+            #[cfg(feature = "forwarder")]
+            {
+                c.forward = routes;
+                c.mirror = self.mirror.clone();
+            }
+            c.filter = self.filter.clone();
+            c.sampling = self.sampling.clone();
+            if let Some(level) = self.log_level {
+                c.log_level = level;
+            }
+        });
+    }
+
+    pub fn into_config(self) -> Result<Config> {
+        let api_key = self.resolve_api_key()?;
+        #[cfg(feature = "forwarder")]
+        let forward = self.forward_routes();
+        let mut cfg = Config::new(api_key, Arc::new(NopHandler));
+        cfg.device_name = self.device_name;
+        cfg.websocket_features = self.websocket_features;
+        cfg.events = self.events;
+        #[cfg(feature = "forwarder")]
+        {
+            cfg.forward = if forward.is_empty() { None } else { Some(forward) };
+            cfg.mirror = if self.mirror.is_empty() { None } else { Some(self.mirror) };
+        }
+        cfg.log_level = self.log_level;
+        cfg.log_coalescing = self.log_coalescing;
+        cfg.pong_wait = self.pong_wait;
+        cfg.ping_period = self.ping_period;
+        cfg.reconnect_policy = self.reconnect.map(ReconnectConfig::into_policy);
+        cfg.tls = self.tls.map(TlsConfig::into_options).transpose()?;
+        cfg.strict_parse = self.strict_parse;
+        cfg.max_message_size = self.max_message_size;
+        cfg.max_frame_size = self.max_frame_size;
+        cfg.expand = if self.expand.is_empty() { None } else { Some(self.expand) };
+        cfg.rest_fallback = self.rest_fallback;
+        cfg.resume_threshold = self.resume_threshold;
+        cfg.catch_up_on_resume = self.catch_up_on_resume;
+        cfg.clock_skew_warning = self.clock_skew_warning;
+        cfg.verify_endpoints = self.verify_endpoints;
+        cfg.stripe_account = self.stripe_account;
+        cfg.drain_timeout = self.drain_timeout;
+        cfg.max_event_age = self.max_event_age;
+        cfg.authorize_timeout = self.authorize_timeout;
+        cfg.authorize_attempt_timeout = self.authorize_attempt_timeout;
+        cfg.filter = self.filter;
+        cfg.sampling = self.sampling;
+        cfg.duplicate_sessions = self.duplicate_sessions;
+        cfg.batching = self.batching;
+        cfg.shadow = self.shadow;
+        cfg.unparseable_payload = self.unparseable_payload;
+        #[cfg(feature = "forwarder")]
+        {
+            cfg.recent_deliveries = self.recent_deliveries;
+        }
+        Ok(cfg)
+    }
+}
+
+impl Config {
+    /// Loads a TOML or YAML config file with STRIPE_LISTENER_* environment
+    /// overrides. The handler is a NopHandler; replace `handler` (and
+    /// `logger`) before building the listener.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Config> {
+        FileConfig::load(path)?.into_config()
+    }
+}
+
+fn split_list(v: &str) -> Vec<String> {
+    v.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect()
+}
+
+pub(crate) fn parse_duration(v: &str) -> std::result::Result<Duration, String> {
+    let v = v.trim();
+    let split = v.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(v.len());
+    let (num, unit) = v.split_at(split);
+    let num: f64 = num.parse().map_err(|_| format!("invalid duration {:?}", v))?;
+    let secs = match unit.trim() {
+        "" | "s" => num,
+        "ms" => num / 1000.0,
+        "m" => num * 60.0,
+        "h" => num * 3600.0,
+        _ => return Err(format!("invalid duration unit in {:?}", v)),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("invalid duration {:?}: {}", v, e))
+}
+
+pub(crate) fn de_duration<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Duration, D::Error> {
+    de_duration_opt(d)?.ok_or_else(|| serde::de::Error::custom("expected a duration"))
+}
+
+pub(crate) fn de_duration_opt<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Option<Duration>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Secs(f64),
+        Text(String),
+    }
+    match Option::<Raw>::deserialize(d)? {
+        None => Ok(None),
+        Some(Raw::Secs(s)) => Duration::try_from_secs_f64(s).map(Some).map_err(serde::de::Error::custom),
+        Some(Raw::Text(t)) => parse_duration(&t).map(Some).map_err(serde::de::Error::custom),
+    }
+}
@@ -0,0 +1,122 @@
+// Persisted position of the last dispatched event, so a restarted listener
+// can catch up on what it missed from exactly where it stopped.
+use std::io::Write;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CursorPosition {
+    pub event_id: String,
+    /// The event's `created` timestamp (unix seconds).
+    pub created: u64,
+}
+
+/// Storage for the last successfully dispatched event. `save` runs after
+/// every handled event and must leave either the old or the new position
+/// behind, never a partial write.
+pub trait Cursor: Send + Sync {
+    fn load(&self) -> Result<Option<CursorPosition>>;
+    fn save(&self, position: &CursorPosition) -> Result<()>;
+}
+
+/// Cursor stored as JSON in a file, replaced atomically through a rename.
+#[derive(Debug, Clone)]
+pub struct FileCursor {
+    path: PathBuf,
+}
+
+impl FileCursor {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn err(&self, e: impl std::fmt::Display) -> Error {
+        Error::Other(format!("cursor {}: {}", self.path.display(), e))
+    }
+}
+
+impl Cursor for FileCursor {
+    fn load(&self) -> Result<Option<CursorPosition>> {
+        match std::fs::read(&self.path) {
+            Ok(data) => serde_json::from_slice(&data).map(Some).map_err(|e| self.err(e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(self.err(e)),
+        }
+    }
+
+    fn save(&self, position: &CursorPosition) -> Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        let data = serde_json::to_vec(position).map_err(|e| self.err(e))?;
+        let mut file = std::fs::File::create(&tmp).map_err(|e| self.err(e))?;
+        file.write_all(&data).and_then(|_| file.sync_all()).map_err(|e| self.err(e))?;
+        std::fs::rename(&tmp, &self.path).map_err(|e| self.err(e))
+    }
+}
+
+/// Cursor kept in a SQLite table, one row per `name`, so several listeners
+/// can share a database.
+#[cfg(feature = "sqlite")]
+pub struct SqliteCursor {
+    conn: std::sync::Mutex<rusqlite::Connection>,
+    name: String,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteCursor {
+    pub fn open(path: impl AsRef<std::path::Path>, name: impl Into<String>) -> Result<Self> {
+        let conn = rusqlite::Connection::open(path).map_err(sqlite_err)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS stripelistener_cursor (
+                name TEXT PRIMARY KEY,
+                event_id TEXT NOT NULL,
+                created INTEGER NOT NULL
+            )",
+            [],
+        )
+        .map_err(sqlite_err)?;
+        Ok(Self {
+            conn: std::sync::Mutex::new(conn),
+            name: name.into(),
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Cursor for SqliteCursor {
+    fn load(&self) -> Result<Option<CursorPosition>> {
+        use rusqlite::OptionalExtension;
+        let conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        conn.query_row(
+            "SELECT event_id, created FROM stripelistener_cursor WHERE name = ?1",
+            [&self.name],
+            |row| {
+                Ok(CursorPosition {
+                    event_id: row.get(0)?,
+                    created: row.get::<_, i64>(1)? as u64,
+                })
+            },
+        )
+        .optional()
+        .map_err(sqlite_err)
+    }
+
+    fn save(&self, position: &CursorPosition) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let tx = conn.transaction().map_err(sqlite_err)?;
+        tx.execute(
+            "INSERT INTO stripelistener_cursor (name, event_id, created) VALUES (?1, ?2, ?3)
+             ON CONFLICT(name) DO UPDATE SET event_id = excluded.event_id, created = excluded.created",
+            rusqlite::params![self.name, position.event_id, position.created as i64],
+        )
+        .map_err(sqlite_err)?;
+        tx.commit().map_err(sqlite_err)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_err(e: rusqlite::Error) -> Error {
+    Error::Other(format!("sqlite cursor: {}", e))
+}
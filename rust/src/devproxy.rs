@@ -0,0 +1,229 @@
+// The devproxy protocol below StripeListener: creating a CLI session,
+// dialing its websocket and exchanging frames. StripeListener adds
+// reconnects, acking, dispatch and forwarding on top; DevProxyClient exposes
+// the bare connection for callers who want to do that themselves.
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async_tls_with_config, MaybeTlsStream, WebSocketStream};
+use url::Url;
+
+use crate::ack;
+use crate::api::ApiClient;
+use crate::session::SESSION_PATH;
+use crate::transport::Acker;
+use crate::*;
+
+pub(crate) type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub(crate) fn api_client(cfg: &Config) -> Result<ApiClient> {
+    ApiClient::new(
+        cfg.tls.as_ref().unwrap(),
+        &cfg.api_key,
+        cfg.stripe_account.as_deref(),
+        cfg.reconnect_policy.clone().unwrap(),
+        cfg.clock.clone().unwrap(),
+    )
+}
+
+// POST /v1/stripecli/sessions with the configured device name and features,
+// bounded by the authorize timeouts. Holds no state, so dropping it midway
+// is safe.
+pub(crate) async fn create_session(cfg: &Config) -> Result<Session> {
+    match cfg.authorize_timeout {
+        Some(after) => tokio::select! {
+            session = request_session(cfg) => session,
+            _ = cfg.clock.as_ref().unwrap().sleep(after) => Err(Error::Timeout { operation: "authorize", after }),
+        },
+        None => request_session(cfg).await,
+    }
+}
+
+async fn request_session(cfg: &Config) -> Result<Session> {
+    let api = api_client(cfg)?.with_attempt_timeout(cfg.authorize_attempt_timeout);
+    let mut params = Vec::new();
+
+    if let Some(name) = &cfg.device_name {
+        params.push(("device_name", name.as_str()));
+    }
+    if let Some(features) = &cfg.websocket_features {
+        for f in features {
+            params.push(("websocket_features[]", f.as_str()));
+        }
+    }
+
+    let resp = api.post_form(SESSION_PATH, &params).await?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let text = resp.text().await?;
+        return Err(Error::Authorize { status: status.as_u16(), body: text });
+    }
+
+    Ok(resp.json().await?)
+}
+
+// Opens the session's websocket and settles the ack subprotocol.
+pub(crate) async fn dial(cfg: &Config, session: &Session, logger: &dyn Logger) -> Result<(WsStream, Acker)> {
+    let ws_url = format!("{}?websocket_feature={}", session.websocket_url, session.websocket_authorized_feature);
+
+    let url = Url::parse(&ws_url)?;
+    let ack_format = cfg.ack_format.clone().unwrap();
+    let mut handshake = HandshakeRequest::new(url.as_str(), &session.websocket_id, ack_format.subprotocols())?;
+    if let Some(customizer) = &cfg.handshake {
+        customizer.customize(&mut handshake)?;
+    }
+    let (request, offered) = handshake.into_request()?;
+
+    logger.log(LogLevel::Debug, "dialing", &[("url", &url), ("websocket_id", &session.websocket_id)]);
+
+    let connector = cfg.tls.as_ref().unwrap().ws_connector()?;
+    let ws_config = WebSocketConfig {
+        max_message_size: cfg.max_message_size,
+        max_frame_size: cfg.max_frame_size,
+        ..Default::default()
+    };
+    let (ws_stream, response) = connect_async_tls_with_config(request, Some(ws_config), false, connector).await?;
+    let accepted = response.headers().get("Sec-WebSocket-Protocol").and_then(|v| v.to_str().ok());
+    let acker = Acker {
+        subprotocol: ack::negotiate(ack_format.as_ref(), &offered, accepted)?,
+        format: ack_format,
+    };
+    Ok((ws_stream, acker))
+}
+
+/// What DevProxyClient::recv read from the websocket.
+#[derive(Debug, Clone)]
+pub enum IncomingFrame {
+    /// A text frame, decoded.
+    Message(Frame),
+    /// A text frame that is not a valid devproxy message.
+    Malformed { text: String, error: String },
+    /// A ping from the server; its pong has already been sent.
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The server closed the websocket. recv returns None from then on.
+    Close(CloseReason),
+}
+
+/// One devproxy websocket connection with none of StripeListener's
+/// machinery: no reconnects, acks, keepalive pings, filters or handler
+/// dispatch. Events are redelivered until acknowledged with
+/// `OutgoingMessage::EventAck`.
+pub struct DevProxyClient {
+    ws: WsStream,
+    acker: Acker,
+    session: Session,
+    closed: bool,
+}
+
+impl DevProxyClient {
+    /// Creates a CLI session with the config's API key, device name and
+    /// websocket features, then connects to it. Of the rest of the config
+    /// only TLS, the handshake customizer, ack format and message size
+    /// limits apply.
+    pub async fn connect(mut cfg: Config) -> Result<DevProxyClient> {
+        cfg.defaults();
+        let session = create_session(&cfg).await?;
+        Self::dial(cfg, session).await
+    }
+
+    /// Connects to an existing session, e.g. one from
+    /// StripeListener::authorize.
+    pub async fn connect_session(mut cfg: Config, session: Session) -> Result<DevProxyClient> {
+        cfg.defaults();
+        Self::dial(cfg, session).await
+    }
+
+    async fn dial(cfg: Config, session: Session) -> Result<DevProxyClient> {
+        let (ws, acker) = dial(&cfg, &session, cfg.logger.as_deref().unwrap()).await?;
+        Ok(DevProxyClient {
+            ws,
+            acker,
+            session,
+            closed: false,
+        })
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// The subprotocol negotiated on the handshake, which acks are built for.
+    pub fn subprotocol(&self) -> &str {
+        &self.acker.subprotocol
+    }
+
+    /// Sends one message. EventAck is built by the config's AckFormat for
+    /// the negotiated subprotocol.
+    pub async fn send(&mut self, msg: OutgoingMessage) -> Result<()> {
+        let value = match &msg {
+            OutgoingMessage::EventAck {
+                event_id,
+                webhook_id,
+                webhook_conversation_id,
+            } => self.acker.format.build(
+                &self.acker.subprotocol,
+                &AckFields {
+                    event_id,
+                    webhook_id,
+                    webhook_conversation_id,
+                },
+            )?,
+            _ => msg.to_json(),
+        };
+        self.ws.send(Message::Text(value.to_string())).await?;
+        Ok(())
+    }
+
+    pub async fn ping(&mut self, payload: Vec<u8>) -> Result<()> {
+        self.ws.send(Message::Ping(payload)).await?;
+        Ok(())
+    }
+
+    /// The next frame from the server; None once the websocket is closed.
+    pub async fn recv(&mut self) -> Result<Option<IncomingFrame>> {
+        if self.closed {
+            return Ok(None);
+        }
+        loop {
+            let msg = match self.ws.next().await {
+                Some(msg) => msg?,
+                None => {
+                    self.closed = true;
+                    return Ok(Some(IncomingFrame::Close(CloseReason::abnormal())));
+                }
+            };
+            return Ok(Some(match msg {
+                Message::Text(text) => match Frame::parse(&text) {
+                    Ok(frame) => IncomingFrame::Message(frame),
+                    Err(e) => IncomingFrame::Malformed { text, error: e.to_string() },
+                },
+                Message::Ping(payload) => {
+                    // tungstenite queued the pong; push it out now rather
+                    // than with the next send.
+                    self.ws.flush().await?;
+                    IncomingFrame::Ping(payload)
+                }
+                Message::Pong(payload) => IncomingFrame::Pong(payload),
+                Message::Close(frame) => {
+                    self.closed = true;
+                    IncomingFrame::Close(CloseReason::from_frame(frame))
+                }
+                Message::Binary(_) | Message::Frame(_) => continue,
+            }));
+        }
+    }
+
+    /// Sends a normal close frame and waits for the server's reply.
+    pub async fn close(mut self) -> Result<()> {
+        self.ws.close(None).await?;
+        while let Some(msg) = self.ws.next().await {
+            if let Message::Close(_) = msg? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
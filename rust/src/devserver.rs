@@ -0,0 +1,202 @@
+// Embedded webhook receiver for demos, examples and integration tests of the
+// forwarding pipeline. Point a ForwardRoute at DevServer::url().
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use crate::signature::{self, DEFAULT_TOLERANCE};
+use crate::{Error, Result, SecretString};
+
+#[derive(Debug, Clone)]
+pub struct DevServerConfig {
+    /// Address to listen on; port 0 picks a free port.
+    pub addr: SocketAddr,
+    /// Signing secret (`whsec_...`) used to verify Stripe-Signature. When
+    /// unset, signatures are not checked.
+    pub secret: Option<SecretString>,
+    /// Number of most recent events kept in memory.
+    pub capacity: usize,
+    pub tolerance: Duration,
+}
+
+impl Default for DevServerConfig {
+    fn default() -> Self {
+        Self {
+            addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            secret: None,
+            capacity: 100,
+            tolerance: DEFAULT_TOLERANCE,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "status", content = "detail", rename_all = "snake_case")]
+pub enum SignatureStatus {
+    Verified,
+    Invalid(String),
+    Missing,
+    /// No secret configured.
+    NotChecked,
+}
+
+/// One request received by the dev server.
+#[derive(Serialize, Debug, Clone)]
+pub struct ReceivedEvent {
+    pub received_at: SystemTime,
+    pub path: String,
+    pub headers: BTreeMap<String, String>,
+    /// The body as JSON, or a JSON string if it did not parse.
+    pub payload: serde_json::Value,
+    pub signature: SignatureStatus,
+}
+
+struct State {
+    cfg: DevServerConfig,
+    events: Mutex<VecDeque<ReceivedEvent>>,
+}
+
+/// HTTP receiver that accepts POSTs on any path, verifies their signature,
+/// keeps the last `capacity` of them, and serves them as JSON from
+/// `GET /events` (`DELETE /events` clears the buffer). Requests with an
+/// invalid or missing signature are recorded but answered with 400.
+/// The server stops when the DevServer is dropped.
+pub struct DevServer {
+    addr: SocketAddr,
+    state: Arc<State>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl DevServer {
+    pub async fn start(cfg: DevServerConfig) -> Result<Self> {
+        let state = Arc::new(State {
+            events: Mutex::new(VecDeque::with_capacity(cfg.capacity)),
+            cfg,
+        });
+        let svc_state = state.clone();
+        let make_svc = make_service_fn(move |_| {
+            let state = svc_state.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req))) }
+        });
+
+        let server = hyper::Server::try_bind(&state.cfg.addr)
+            .map_err(|e| Error::Other(format!("devserver bind {}: {}", state.cfg.addr, e)))?
+            .serve(make_svc);
+        let addr = server.local_addr();
+        let (tx, rx) = oneshot::channel::<()>();
+        tokio::spawn(server.with_graceful_shutdown(async {
+            let _ = rx.await;
+        }));
+
+        Ok(Self {
+            addr,
+            state,
+            shutdown: Some(tx),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// URL to use as a forward target.
+    pub fn url(&self) -> String {
+        format!("http://{}/webhooks", self.addr)
+    }
+
+    /// Recorded events, oldest first.
+    pub fn events(&self) -> Vec<ReceivedEvent> {
+        self.state.events.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.state.events.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+impl Drop for DevServer {
+    fn drop(&mut self) {
+        if let Some(tx) = self.shutdown.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+async fn handle(state: Arc<State>, req: Request<Body>) -> std::result::Result<Response<Body>, Infallible> {
+    let path = req.uri().path().to_string();
+    match (req.method(), path.as_str()) {
+        (&Method::GET, "/events") => {
+            let events: Vec<ReceivedEvent> = state.events.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect();
+            Ok(json_response(StatusCode::OK, &events))
+        }
+        (&Method::DELETE, "/events") => {
+            state.events.lock().unwrap_or_else(|e| e.into_inner()).clear();
+            Ok(Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty()).unwrap_or_default())
+        }
+        (&Method::POST, _) => {
+            let headers: BTreeMap<String, String> = req
+                .headers()
+                .iter()
+                .map(|(k, v)| (k.as_str().to_string(), String::from_utf8_lossy(v.as_bytes()).into_owned()))
+                .collect();
+            let body = match hyper::body::to_bytes(req.into_body()).await {
+                Ok(b) => b,
+                Err(e) => return Ok(text_response(StatusCode::BAD_REQUEST, &e.to_string())),
+            };
+
+            let status = match (&state.cfg.secret, headers.get("stripe-signature")) {
+                (None, _) => SignatureStatus::NotChecked,
+                (Some(_), None) => SignatureStatus::Missing,
+                (Some(secret), Some(header)) => match signature::verify(&body, header, secret.expose(), state.cfg.tolerance) {
+                    Ok(()) => SignatureStatus::Verified,
+                    Err(e) => SignatureStatus::Invalid(e.to_string()),
+                },
+            };
+            let payload = serde_json::from_slice(&body)
+                .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(&body).into_owned()));
+            let accepted = matches!(status, SignatureStatus::Verified | SignatureStatus::NotChecked);
+
+            let mut events = state.events.lock().unwrap_or_else(|e| e.into_inner());
+            if events.len() == state.cfg.capacity.max(1) {
+                events.pop_front();
+            }
+            events.push_back(ReceivedEvent {
+                received_at: SystemTime::now(),
+                path,
+                headers,
+                payload,
+                signature: status,
+            });
+            drop(events);
+
+            if accepted {
+                Ok(json_response(StatusCode::OK, &serde_json::json!({ "received": true })))
+            } else {
+                Ok(text_response(StatusCode::BAD_REQUEST, "signature verification failed"))
+            }
+        }
+        _ => Ok(text_response(StatusCode::NOT_FOUND, "not found")),
+    }
+}
+
+fn json_response<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(value).unwrap_or_default()))
+        .unwrap_or_default()
+}
+
+fn text_response(status: StatusCode, msg: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(msg.to_string()))
+        .unwrap_or_default()
+}
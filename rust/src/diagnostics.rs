@@ -0,0 +1,222 @@
+// Ring buffers of recent frames, connection state changes and errors, and
+// the redacted bundle ListenerHandle::dump_diagnostics writes from them.
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::session::CLI_VERSION;
+use crate::{Clock, Config, Error, ListenerStats, LiveConfig, SessionReport};
+
+const FRAMES: usize = 50;
+const TRANSITIONS: usize = 100;
+const ERRORS: usize = 20;
+
+// Frame fields whose values are masked wherever they appear.
+const SECRET_KEYS: &[&str] = &["secret", "stripe-signature", "authorization"];
+// Frame fields holding event payloads or request bodies, replaced by a
+// summary.
+const PAYLOAD_KEYS: &[&str] = &["event_payload", "payload", "body", "request_body"];
+
+struct FrameRecord {
+    at: u64,
+    direction: &'static str,
+    kind: &'static str,
+    len: usize,
+    text: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+struct Transition {
+    at: u64,
+    state: &'static str,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    detail: String,
+}
+
+#[derive(Serialize, Clone)]
+struct ErrorRecord {
+    at: u64,
+    error: String,
+}
+
+#[derive(Default)]
+struct Buffers {
+    frames: VecDeque<FrameRecord>,
+    transitions: VecDeque<Transition>,
+    errors: VecDeque<ErrorRecord>,
+}
+
+#[derive(Clone)]
+pub(crate) struct Diagnostics {
+    buffers: Arc<Mutex<Buffers>>,
+    clock: Arc<dyn Clock>,
+    // The static part of the config, rendered with secrets masked.
+    config: Arc<Value>,
+}
+
+impl Diagnostics {
+    pub(crate) fn new(cfg: &Config) -> Self {
+        let config = json!({
+            "api_key": mask_key(cfg.api_key.expose()),
+            "device_name": cfg.device_name,
+            "stripe_account": cfg.stripe_account,
+            "websocket_features": cfg.websocket_features,
+            "ping_period": format!("{:?}", cfg.ping_period),
+            "pong_wait": format!("{:?}", cfg.pong_wait),
+            "drain_timeout": format!("{:?}", cfg.drain_timeout),
+            "authorize_timeout": format!("{:?}", cfg.authorize_timeout),
+            "authorize_attempt_timeout": format!("{:?}", cfg.authorize_attempt_timeout),
+            "resume_threshold": format!("{:?}", cfg.resume_threshold),
+            "max_event_age": format!("{:?}", cfg.max_event_age),
+            "max_message_size": cfg.max_message_size,
+            "max_frame_size": cfg.max_frame_size,
+            "strict_parse": cfg.strict_parse,
+            "rest_fallback": cfg.rest_fallback,
+            "catch_up_on_resume": cfg.catch_up_on_resume,
+            "batching": format!("{:?}", cfg.batching),
+            "unparseable_payload": format!("{:?}", cfg.unparseable_payload),
+        });
+        Self {
+            buffers: Arc::default(),
+            clock: cfg.clock.clone().unwrap(),
+            config: Arc::new(config),
+        }
+    }
+
+    fn now(&self) -> u64 {
+        unix_millis(self.clock.now())
+    }
+
+    fn with(&self, f: impl FnOnce(&mut Buffers)) {
+        f(&mut self.buffers.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    // Text frames are kept as sent and redacted when the bundle is built.
+    pub(crate) fn frame(&self, direction: &'static str, msg: &Message) {
+        let (kind, text) = match msg {
+            Message::Text(text) => ("text", Some(text.clone())),
+            Message::Binary(_) => ("binary", None),
+            Message::Ping(_) => ("ping", None),
+            Message::Pong(_) => ("pong", None),
+            Message::Close(_) => ("close", None),
+            Message::Frame(_) => ("frame", None),
+        };
+        let record = FrameRecord {
+            at: self.now(),
+            direction,
+            kind,
+            len: msg.len(),
+            text,
+        };
+        self.with(|b| push(&mut b.frames, record, FRAMES));
+    }
+
+    pub(crate) fn transition(&self, state: &'static str, detail: impl ToString) {
+        let record = Transition {
+            at: self.now(),
+            state,
+            detail: detail.to_string(),
+        };
+        self.with(|b| push(&mut b.transitions, record, TRANSITIONS));
+    }
+
+    pub(crate) fn error(&self, error: &Error) {
+        let record = ErrorRecord {
+            at: self.now(),
+            error: error.to_string(),
+        };
+        self.with(|b| push(&mut b.errors, record, ERRORS));
+    }
+
+    pub(crate) fn bundle(&self, live: &LiveConfig, stats: &ListenerStats, report: &SessionReport) -> Value {
+        let buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+        let frames: Vec<Value> = buffers
+            .frames
+            .iter()
+            .map(|f| {
+                json!({
+                    "at": f.at,
+                    "direction": f.direction,
+                    "kind": f.kind,
+                    "len": f.len,
+                    "frame": f.text.as_deref().map(redact_frame),
+                })
+            })
+            .collect();
+        json!({
+            "generated_at": self.now(),
+            "crate_version": env!("CARGO_PKG_VERSION"),
+            "cli_version": CLI_VERSION,
+            "config": *self.config,
+            "live_config": live,
+            "stats": stats,
+            "report": report,
+            "transitions": buffers.transitions,
+            "errors": buffers.errors,
+            "frames": frames,
+        })
+    }
+}
+
+fn push<T>(ring: &mut VecDeque<T>, item: T, capacity: usize) {
+    if ring.len() == capacity {
+        ring.pop_front();
+    }
+    ring.push_back(item);
+}
+
+fn unix_millis(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default()
+}
+
+// Keeps the key's type and mode prefix (`sk_test_`) only.
+fn mask_key(key: &str) -> String {
+    let parts: Vec<&str> = key.splitn(3, '_').collect();
+    match parts.as_slice() {
+        [kind, mode, _] => format!("{}_{}_[redacted]", kind, mode),
+        [""] => String::new(),
+        _ => "[redacted]".to_string(),
+    }
+}
+
+fn redact_frame(text: &str) -> Value {
+    match serde_json::from_str::<Value>(text) {
+        Ok(mut frame) => {
+            redact(&mut frame);
+            frame
+        }
+        Err(_) => Value::String(format!("[{} bytes, not JSON]", text.len())),
+    }
+}
+
+fn redact(node: &mut Value) {
+    match node {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if PAYLOAD_KEYS.contains(&key.as_str()) && value.is_string() {
+                    *value = Value::String(summarize(value.as_str().unwrap_or_default()));
+                } else if SECRET_KEYS.iter().any(|k| key.eq_ignore_ascii_case(k)) {
+                    *value = Value::String("[redacted]".to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+// An event payload keeps its id and type, anything else only its length.
+fn summarize(payload: &str) -> String {
+    let parsed: Option<Value> = serde_json::from_str(payload).ok();
+    let field = |name: &str| parsed.as_ref().and_then(|v| v.get(name)).and_then(Value::as_str).map(str::to_string);
+    match (field("id"), field("type")) {
+        (Some(id), Some(event_type)) => format!("[redacted {} bytes: {} {}]", payload.len(), id, event_type),
+        _ => format!("[redacted {} bytes]", payload.len()),
+    }
+}
@@ -0,0 +1,717 @@
+// EventHandler callbacks and, with `client`, the per-connection pipeline
+// that runs events through filters, transforms and forwarding into them.
+#[cfg(feature = "client")]
+use std::sync::Arc;
+#[cfg(feature = "client")]
+use std::time::UNIX_EPOCH;
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+#[cfg(feature = "tower")]
+use tracing::Instrument;
+
+#[cfg(feature = "forwarder")]
+use crate::forward::Forwarder;
+#[cfg(feature = "otel")]
+use crate::otel;
+#[cfg(feature = "client")]
+use crate::pool::PoolMember;
+#[cfg(feature = "client")]
+use crate::sampling::Sampler;
+#[cfg(feature = "client")]
+use crate::stats::StatsRecorder;
+#[cfg(feature = "forwarder")]
+use crate::transport::InFlight;
+use crate::{Error, SchemaDrift, ServerError, StripeEventPayload, Tenant, V2Event, V2EventPayload, WebhookEvent};
+#[cfg(feature = "client")]
+use crate::{Batching, Clock, ConfigHandle, Cursor, CursorPosition, EventPredicate, LiveConfig, LogLevel, Logger, TenantResolver, Transform};
+#[cfg(all(feature = "forwarder", feature = "types"))]
+use crate::Signing;
+#[cfg(feature = "forwarder")]
+use crate::{DeadLetterSink, ForwardRoute};
+#[cfg(feature = "tower")]
+use crate::tower::ServiceAnswer;
+#[cfg(feature = "tower")]
+use crate::{AckDecision, EventService, ListenerEvent};
+
+pub trait EventHandler: Send + Sync {
+    fn on_webhook_event(&self, evt: WebhookEvent, parsed: StripeEventPayload);
+    fn on_v2_event(&self, evt: V2Event, parsed: V2EventPayload);
+    fn on_unknown_message(&self, raw_type: String, data: serde_json::Value);
+
+    /// With `Config::batching` set, called instead of on_webhook_event with
+    /// up to `max_size` events in delivery order. Returning false rejects
+    /// the batch: none of its events are acknowledged and Stripe redelivers
+    /// them. A panic counts as a rejection. The default passes each event
+    /// to on_webhook_event and accepts.
+    fn on_webhook_batch(&self, batch: Vec<(WebhookEvent, StripeEventPayload)>) -> bool {
+        for (evt, parsed) in batch {
+            self.on_webhook_event(evt, parsed);
+        }
+        true
+    }
+
+    /// With Config::unparseable_payload set to DeliverRaw, called for webhook
+    /// events whose event_payload does not parse as StripeEventPayload, with
+    /// the payload left as received.
+    fn on_raw_webhook_event(&self, _evt: WebhookEvent) {}
+
+    /// With Config::tenant_resolver set, called instead of on_webhook_event
+    /// for events it resolves to a tenant. Batches carry no tenant. The
+    /// default ignores the tenant and calls on_webhook_event.
+    fn on_tenant_webhook_event(&self, _tenant: &Tenant, evt: WebhookEvent, parsed: StripeEventPayload) {
+        self.on_webhook_event(evt, parsed);
+    }
+
+    /// With Config::max_event_age set, called instead of on_webhook_event
+    /// for events created longer ago than that, with their age. They are
+    /// acknowledged and not forwarded.
+    fn on_stale_event(&self, _evt: WebhookEvent, _parsed: StripeEventPayload, _age: Duration) {}
+
+    /// Called once per route with the final outcome of forwarding an event.
+    fn on_forward_result(&self, _result: &ForwardResult) {}
+
+    /// With `strict_parse` enabled, called for events whose payload does not
+    /// match EventEnvelope exactly. The event is still dispatched.
+    fn on_schema_drift(&self, _drift: &SchemaDrift) {}
+
+    /// Called once an event_ack frame has been written to the socket. V2
+    /// events have an empty conversation id.
+    fn on_ack_sent(&self, _event_id: &str, _conversation_id: &str, _timestamp: SystemTime) {}
+
+    /// Called when an ACK could not be written, e.g. because the connection
+    /// dropped first. Stripe will redeliver the event.
+    fn on_ack_failed(&self, _event_id: &str, _conversation_id: &str, _error: &Error) {}
+
+    /// Called when another callback panicked. The panic is contained and the
+    /// connection stays up; the event is not redelivered since it was already
+    /// acknowledged. Has no effect when built with `panic = "abort"`.
+    fn on_handler_panic(&self, _panic: &HandlerPanic) {}
+
+    /// Called for each error message from the server. Fatal ones (see
+    /// ReconnectPolicy::is_fatal) are followed by the connection closing.
+    fn on_server_error(&self, _error: &ServerError) {}
+
+    /// With Config::shadow set, called for each difference found between
+    /// the events received here and Stripe's deliveries to the account's
+    /// webhook endpoints.
+    fn on_shadow_discrepancy(&self, _discrepancy: &ShadowDiscrepancy) {}
+
+    /// Protocol debugging: called for each ping from the server, after its
+    /// pong has been queued.
+    fn on_ping_received(&self, _payload: &[u8]) {}
+
+    /// Protocol debugging: called for each pong from the server. `rtt` is set
+    /// when it answers one of the listener's own pings.
+    fn on_pong_received(&self, _payload: &[u8], _rtt: Option<Duration>) {}
+}
+
+/// A panic caught while running an EventHandler callback.
+#[derive(Debug, Clone)]
+pub struct HandlerPanic {
+    /// The callback that panicked, e.g. `on_webhook_event`.
+    pub callback: &'static str,
+    pub event_id: Option<String>,
+    pub message: String,
+}
+
+// Runs `f`, returning the panic instead of unwinding into the caller.
+#[cfg(feature = "client")]
+pub(crate) fn catch_handler_panic(callback: &'static str, event_id: Option<&str>, f: impl FnOnce()) -> Option<HandlerPanic> {
+    let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).err()?;
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string panic payload".to_string());
+    Some(HandlerPanic {
+        callback,
+        event_id: event_id.map(str::to_string),
+        message,
+    })
+}
+
+/// Final outcome of forwarding one event to one route, after retries.
+#[derive(Serialize, Debug, Clone)]
+pub struct ForwardResult {
+    pub event_id: String,
+    pub url: String,
+    pub attempts: u32,
+    /// Status of the last response; None if no response was received.
+    pub status: Option<u16>,
+    /// Body of the last response, truncated to 64 KiB.
+    pub body: Option<String>,
+    /// Transport error of the last attempt, if any.
+    pub error: Option<String>,
+    /// Time from the first attempt to the final outcome.
+    pub duration: Duration,
+}
+
+impl ForwardResult {
+    pub fn is_success(&self) -> bool {
+        self.status.is_some_and(|s| (200..300).contains(&s))
+    }
+}
+
+/// A difference found by shadow mode; see Config::shadow.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ShadowDiscrepancy {
+    pub event_id: String,
+    pub event_type: String,
+    /// The event's creation time, in Unix seconds.
+    pub created: u64,
+    pub kind: DiscrepancyKind,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscrepancyKind {
+    /// Delivered to the account's webhook endpoints but never received by
+    /// this listener.
+    NotReceived,
+    /// Received by this listener but not delivered successfully to every
+    /// webhook endpoint subscribed to it.
+    NotDelivered,
+}
+
+pub struct NopHandler;
+impl EventHandler for NopHandler {
+    fn on_webhook_event(&self, _evt: WebhookEvent, _parsed: StripeEventPayload) {}
+    fn on_v2_event(&self, _evt: V2Event, _parsed: V2EventPayload) {}
+    fn on_unknown_message(&self, _raw_type: String, _data: serde_json::Value) {}
+}
+
+// Per-connection event pipeline shared by the read loop and catch-up replay:
+// schema check, live filter, transform, forwarding and handler dispatch.
+#[cfg(feature = "client")]
+#[derive(Clone)]
+pub(crate) struct Dispatcher {
+    pub(crate) handler: Arc<dyn EventHandler>,
+    pub(crate) logger: Arc<dyn Logger>,
+    pub(crate) stats: StatsRecorder,
+    pub(crate) cursor: Option<Arc<dyn Cursor>>,
+    pub(crate) live: ConfigHandle,
+    pub(crate) transform: Option<Arc<dyn Transform>>,
+    pub(crate) predicate: Option<Arc<dyn EventPredicate>>,
+    pub(crate) tenants: Option<Arc<dyn TenantResolver>>,
+    #[cfg(feature = "forwarder")]
+    pub(crate) forwarder: Forwarder,
+    #[cfg(feature = "forwarder")]
+    pub(crate) dead_letter: Option<Arc<dyn DeadLetterSink>>,
+    pub(crate) strict_parse: bool,
+    pub(crate) max_event_age: Option<Duration>,
+    pub(crate) clock: Arc<dyn Clock>,
+    #[cfg(feature = "forwarder")]
+    pub(crate) inflight: InFlight,
+    pub(crate) sampler: Sampler,
+    pub(crate) batching: Option<Batching>,
+    #[cfg(feature = "tower")]
+    pub(crate) service: Option<EventService>,
+    /// Set for the dispatcher of a websocket connection.
+    pub(crate) connection_id: Option<String>,
+    /// Set for the listeners of a ListenerPool.
+    pub(crate) pool: Option<PoolMember>,
+}
+
+// A webhook event that passed the filters, ready for the handler.
+#[cfg(feature = "client")]
+pub(crate) struct Admitted {
+    evt: WebhookEvent,
+    parsed: StripeEventPayload,
+    tenant: Option<Tenant>,
+    #[cfg(feature = "forwarder")]
+    forwards: Option<Forwards>,
+    #[cfg(feature = "otel")]
+    otel_cx: opentelemetry::Context,
+}
+
+// The forward and mirror deliveries of an admitted event, with the routes it
+// matched when admitted. Started once the handler has taken the event, or
+// accepted its batch, so a rejected batch is not forwarded twice.
+#[cfg(feature = "forwarder")]
+pub(crate) struct Forwards {
+    routes: Vec<ForwardRoute>,
+    mirrors: Vec<ForwardRoute>,
+    evt: WebhookEvent,
+    parsed: StripeEventPayload,
+}
+
+#[cfg(feature = "client")]
+impl Dispatcher {
+    // Runs a handler callback, containing any panic so the read loop and
+    // connection survive it.
+    // Returns false if the callback panicked.
+    pub(crate) fn guarded(&self, callback: &'static str, event_id: Option<&str>, f: impl FnOnce(&dyn EventHandler)) -> bool {
+        let handler = self.handler.as_ref();
+        match catch_handler_panic(callback, event_id, || f(handler)) {
+            None => true,
+            Some(panic) => {
+                self.stats.handler_panic();
+                self.logger.log(
+                    LogLevel::Error,
+                    "handler panicked",
+                    &[("callback", &panic.callback), ("event_id", &event_id.unwrap_or("-")), ("error", &panic.message)],
+                );
+                let _ = catch_handler_panic("on_handler_panic", event_id, || handler.on_handler_panic(&panic));
+                false
+            }
+        }
+    }
+
+    // Counts the failed ack and reports it to on_ack_failed.
+    pub(crate) fn ack_failed(&self, event_id: &str, conversation_id: &str, error: &Error) {
+        self.stats.ack_failed();
+        self.guarded("on_ack_failed", Some(event_id), |h| h.on_ack_failed(event_id, conversation_id, error));
+    }
+
+    fn filtered_out(&self, event_id: &str, event_type: &str) {
+        self.stats.filtered_out();
+        self.logger.log(LogLevel::Debug, "filtered out", &[("event_id", &event_id), ("event_type", &event_type)]);
+    }
+
+    // Applies LiveConfig::sampling; false means the event is dropped.
+    pub(crate) fn sampled(&self, live: &LiveConfig, event_id: &str, event_type: &str) -> bool {
+        let Some(sampling) = &live.sampling else { return true };
+        if self.sampler.keep(sampling, event_type) {
+            return true;
+        }
+        self.stats.sampled_out();
+        self.logger.log(LogLevel::Debug, "sampled out", &[("event_id", &event_id), ("event_type", &event_type)]);
+        false
+    }
+
+    pub(crate) fn webhook(&self, evt: WebhookEvent, parsed: StripeEventPayload) {
+        let Some(Admitted {
+            evt,
+            parsed,
+            tenant,
+            #[cfg(feature = "forwarder")]
+            forwards,
+            #[cfg(feature = "otel")]
+            otel_cx,
+        }) = self.admit(evt, parsed)
+        else {
+            return;
+        };
+        let span = tracing::info_span!(
+            "stripe_event",
+            event_id = %parsed.id,
+            event_type = %parsed.event_type,
+            request_id = parsed.request_id().unwrap_or_default(),
+            idempotency_key = parsed.idempotency_key().unwrap_or_default(),
+            connection_id = self.connection_id.as_deref().unwrap_or_default(),
+        );
+        let _enter = span.enter();
+        #[cfg(feature = "otel")]
+        let _attached = otel_cx.clone().attach();
+        let position = CursorPosition {
+            event_id: parsed.id.clone(),
+            created: parsed.created,
+        };
+        let handled = match tenant {
+            Some(tenant) => self.guarded("on_tenant_webhook_event", Some(&position.event_id), |h| h.on_tenant_webhook_event(&tenant, evt, parsed)),
+            None => self.guarded("on_webhook_event", Some(&position.event_id), |h| h.on_webhook_event(evt, parsed)),
+        };
+        if handled {
+            self.save_cursor(&position);
+        }
+        #[cfg(feature = "forwarder")]
+        self.forward(forwards);
+        #[cfg(feature = "otel")]
+        otel::end(&otel_cx);
+    }
+
+    // Passes a batch of admitted events to on_webhook_batch; true if the
+    // handler accepted it.
+    pub(crate) fn webhook_batch(&self, batch: Vec<Admitted>) -> bool {
+        let Some(last) = batch.last() else { return true };
+        let position = CursorPosition {
+            event_id: last.parsed.id.clone(),
+            created: last.parsed.created,
+        };
+        let span = tracing::info_span!(
+            "stripe_event_batch",
+            size = batch.len(),
+            last_event_id = %position.event_id,
+            connection_id = self.connection_id.as_deref().unwrap_or_default(),
+        );
+        let _enter = span.enter();
+        #[cfg(feature = "otel")]
+        let contexts: Vec<opentelemetry::Context> = batch.iter().map(|a| a.otel_cx.clone()).collect();
+        #[cfg(feature = "forwarder")]
+        let (batch, forwards): (Vec<Admitted>, Vec<Option<Forwards>>) = batch
+            .into_iter()
+            .map(|mut a| {
+                let forwards = a.forwards.take();
+                (a, forwards)
+            })
+            .unzip();
+        let ids: Vec<String> = batch.iter().map(|a| a.parsed.id.clone()).collect();
+        let events = batch.into_iter().map(|a| (a.evt, a.parsed)).collect();
+        let mut accepted = false;
+        let handled = self.guarded("on_webhook_batch", Some(&position.event_id), |h| accepted = h.on_webhook_batch(events));
+        if handled && accepted {
+            self.save_cursor(&position);
+            #[cfg(feature = "forwarder")]
+            forwards.into_iter().for_each(|f| self.forward(f));
+        } else {
+            self.stats.batch_rejected();
+            if let Some(pool) = &self.pool {
+                pool.release(ids.iter().map(String::as_str));
+            }
+        }
+        #[cfg(feature = "otel")]
+        contexts.iter().for_each(otel::end);
+        handled && accepted
+    }
+
+    fn save_cursor(&self, position: &CursorPosition) {
+        if let Some(cursor) = &self.cursor {
+            if let Err(e) = cursor.save(position) {
+                self.logger.log(LogLevel::Warn, "could not save cursor", &[("event_id", &position.event_id), ("error", &e)]);
+            }
+        }
+    }
+
+    // Everything before the handler: the pool's claim, schema check, live
+    // filter, sampling, transform and picking the forward routes, whose
+    // deliveries start once the handler has the event. None means the event
+    // is dropped.
+    pub(crate) fn admit(&self, evt: WebhookEvent, parsed: StripeEventPayload) -> Option<Admitted> {
+        if !self.pool.as_ref().is_none_or(|p| p.claim(&parsed.id, &evt.webhook_conversation_id)) {
+            return None;
+        }
+        self.stats.event_type(parsed.event_type.as_str());
+        if self.strict_parse {
+            let drift = serde_json::from_str(&evt.event_payload).ok().and_then(|v| SchemaDrift::detect(&v));
+            if let Some(drift) = drift {
+                self.logger.log(
+                    LogLevel::Warn,
+                    "schema drift",
+                    &[
+                        ("event_id", &parsed.id),
+                        ("event_type", &parsed.event_type),
+                        ("unknown", &format!("{:?}", drift.unknown_fields)),
+                        ("missing", &format!("{:?}", drift.missing_fields)),
+                    ],
+                );
+                self.guarded("on_schema_drift", Some(&parsed.id), |h| h.on_schema_drift(&drift));
+            }
+        }
+
+        let live = self.live.snapshot();
+        let kept = live.matches_event(parsed.event_type.as_str())
+            && live.filter.as_ref().is_none_or(|f| f.matches(&parsed))
+            && self.predicate.as_ref().is_none_or(|p| p.keep(&parsed));
+        if !kept {
+            self.filtered_out(&parsed.id, parsed.event_type.as_str());
+            return None;
+        }
+        let tenant = self.tenants.as_ref().and_then(|r| r.resolve(&evt, &parsed));
+        if let Some(tenant) = &tenant {
+            self.stats.tenant_event(&tenant.id);
+        }
+        if !live.filter.as_ref().is_none_or(|f| f.matches_tenant(tenant.as_ref())) {
+            self.filtered_out(&parsed.id, parsed.event_type.as_str());
+            return None;
+        }
+        if let Some(age) = self.stale_age(&parsed) {
+            self.stats.stale_event();
+            self.logger.log(
+                LogLevel::Warn,
+                "stale event",
+                &[("event_id", &parsed.id), ("event_type", &parsed.event_type), ("age_secs", &age.as_secs())],
+            );
+            let event_id = parsed.id.clone();
+            self.guarded("on_stale_event", Some(&event_id), |h| h.on_stale_event(evt, parsed, age));
+            return None;
+        }
+        if !self.sampled(&live, &parsed.id, parsed.event_type.as_str()) {
+            return None;
+        }
+        #[allow(unused_mut, unused_variables)]
+        let (mut evt, parsed, transformed) = match &self.transform {
+            Some(t) => transform_webhook(t.as_ref(), evt, parsed),
+            None => (evt, parsed, false),
+        };
+        #[cfg(feature = "otel")]
+        let otel_cx = otel::event_context(&parsed.id, parsed.event_type.as_str(), Some(&mut evt.http_headers));
+        #[cfg(feature = "forwarder")]
+        let forwards = {
+            let event_type = parsed.event_type.as_str();
+            let routes: Vec<ForwardRoute> = live.forward.iter().filter(|r| r.matches(event_type)).map(|r| self.resigned(r, transformed)).collect();
+            let mirrors: Vec<ForwardRoute> = live.mirror.iter().filter(|r| r.matches(event_type)).map(|r| self.resigned(r, transformed)).collect();
+            (!routes.is_empty() || !mirrors.is_empty()).then(|| Forwards {
+                routes,
+                mirrors,
+                evt: self.delivered(&evt, transformed),
+                parsed: parsed.clone(),
+            })
+        };
+        Some(Admitted {
+            evt,
+            parsed,
+            tenant,
+            #[cfg(feature = "forwarder")]
+            forwards,
+            #[cfg(feature = "otel")]
+            otel_cx,
+        })
+    }
+
+    // Stripe's Stripe-Signature covers the payload as Stripe sent it, so a
+    // transformed delivery is re-signed with the session's secret on routes
+    // that do not sign already.
+    #[cfg(feature = "forwarder")]
+    fn resigned(&self, route: &ForwardRoute, transformed: bool) -> ForwardRoute {
+        #[allow(unused_mut)]
+        let mut route = route.clone();
+        #[cfg(feature = "types")]
+        if transformed && route.signing.is_none() && self.forwarder.session_secret.is_some() {
+            route.signing = Some(Signing::default());
+        }
+        #[cfg(not(feature = "types"))]
+        let _ = transformed;
+        route
+    }
+
+    // The copy of an event to forward. Without a secret to re-sign with, as
+    // when replaying without one, a transformed delivery goes out without
+    // the signature that no longer matches it.
+    #[cfg(feature = "forwarder")]
+    fn delivered(&self, evt: &WebhookEvent, transformed: bool) -> WebhookEvent {
+        #[allow(unused_mut)]
+        let mut evt = evt.clone();
+        #[cfg(feature = "types")]
+        if transformed && self.forwarder.session_secret.is_none() {
+            evt.http_headers.retain(|name, _| !name.eq_ignore_ascii_case("stripe-signature"));
+        }
+        #[cfg(not(feature = "types"))]
+        let _ = transformed;
+        evt
+    }
+
+    // Warns once per connection or replay when transformed events will be
+    // forwarded unsigned for want of a signing secret.
+    #[cfg(all(feature = "forwarder", feature = "types"))]
+    pub(crate) fn warn_unsigned(&self) {
+        let live = self.live.snapshot();
+        if self.transform.is_some() && self.forwarder.session_secret.is_none() && (!live.forward.is_empty() || !live.mirror.is_empty()) {
+            self.logger.log(LogLevel::Warn, "no signing secret; transformed events are forwarded without a Stripe-Signature", &[]);
+        }
+    }
+
+    // Starts an admitted event's forward and mirror deliveries.
+    #[cfg(feature = "forwarder")]
+    fn forward(&self, forwards: Option<Forwards>) {
+        let Some(Forwards { routes, mirrors, evt, parsed }) = forwards else { return };
+        for route in routes {
+            let forwarder = self.forwarder.clone();
+            let logger_fwd = self.logger.clone();
+            let delivery = evt.clone();
+            let delivered = parsed.clone();
+            let dispatcher = self.clone();
+            self.inflight.spawn(async move {
+                let result = forwarder.deliver(&route, &delivery, &delivered).await;
+                let event_id = &delivered.id;
+                if result.is_success() {
+                    logger_fwd.log(
+                        LogLevel::Info,
+                        "forwarded",
+                        &[
+                            ("event_id", event_id),
+                            ("event_type", &delivered.event_type),
+                            ("url", &route.url),
+                            ("status", &result.status.unwrap_or_default()),
+                        ],
+                    );
+                } else {
+                    let reason = result.error.clone().unwrap_or_else(|| format!("HTTP {}", result.status.unwrap_or_default()));
+                    logger_fwd.log(
+                        LogLevel::Error,
+                        "forwarding failed",
+                        &[("event_id", event_id), ("url", &route.url), ("attempts", &result.attempts), ("error", &reason)],
+                    );
+                    if let Some(sink) = &dispatcher.dead_letter {
+                        dispatcher.guarded("dead_letter", Some(event_id), |_| sink.dead_letter(&delivery, &result));
+                    }
+                }
+                dispatcher.stats.forward_result(&result);
+                dispatcher.guarded("on_forward_result", Some(event_id), |h| h.on_forward_result(&result));
+            });
+        }
+        for route in mirrors {
+            let forwarder = self.forwarder.clone();
+            let logger = self.logger.clone();
+            let delivery = evt.clone();
+            let delivered = parsed.clone();
+            self.inflight.spawn(async move {
+                let outcome = forwarder.forward(&route, &delivery, &delivered).await;
+                let (event_id, url) = (&delivered.id, &route.url);
+                match outcome {
+                    Ok(resp) if (200..300).contains(&resp.status) => {
+                        logger.log(LogLevel::Debug, "mirrored", &[("event_id", event_id), ("event_type", &delivered.event_type), ("url", url)]);
+                    }
+                    Ok(resp) => logger.log(LogLevel::Warn, "mirror rejected delivery", &[("event_id", event_id), ("url", url), ("status", &resp.status)]),
+                    Err(e) => logger.log(LogLevel::Warn, "mirror failed", &[("event_id", event_id), ("url", url), ("error", &e)]),
+                }
+            });
+        }
+    }
+
+    // The event's age, if it is older than Config::max_event_age.
+    fn stale_age(&self, parsed: &StripeEventPayload) -> Option<Duration> {
+        let max = self.max_event_age?;
+        let created = UNIX_EPOCH + Duration::from_secs(parsed.created);
+        let age = self.clock.now().duration_since(created).ok()?;
+        (age > max).then_some(age)
+    }
+
+    pub(crate) fn v2(&self, evt: V2Event, parsed: V2EventPayload) {
+        let Some((evt, parsed)) = self.admit_v2(evt, parsed) else { return };
+        #[cfg(feature = "otel")]
+        let otel_cx = otel::event_context(&parsed.id, &parsed.event_type, None);
+        #[cfg(feature = "otel")]
+        let _attached = otel_cx.clone().attach();
+        let event_id = parsed.id.clone();
+        self.guarded("on_v2_event", Some(&event_id), |h| h.on_v2_event(evt, parsed));
+        #[cfg(feature = "otel")]
+        otel::end(&otel_cx);
+    }
+
+    // The pool's claim, filter, sampling and transform for v2 events, which
+    // have no conversation and are routed by id. None means the event is
+    // dropped.
+    pub(crate) fn admit_v2(&self, evt: V2Event, parsed: V2EventPayload) -> Option<(V2Event, V2EventPayload)> {
+        if !self.pool.as_ref().is_none_or(|p| p.claim(&parsed.id, &parsed.id)) {
+            return None;
+        }
+        self.stats.event_type(&parsed.event_type);
+        let live = self.live.snapshot();
+        if !live.filter.as_ref().is_none_or(|f| f.matches_v2(&parsed)) {
+            self.filtered_out(&parsed.id, &parsed.event_type);
+            return None;
+        }
+        if !self.sampled(&live, &parsed.id, &parsed.event_type) {
+            return None;
+        }
+        Some(match &self.transform {
+            Some(t) => transform_v2(t.as_ref(), evt, parsed),
+            None => (evt, parsed),
+        })
+    }
+
+    // Passes an admitted webhook event to the service once it is ready. The
+    // returned answer saves the cursor when the service acks.
+    #[cfg(feature = "tower")]
+    pub(crate) async fn serve_webhook(&self, service: &EventService, admitted: Admitted) -> ServiceAnswer {
+        let Admitted {
+            evt,
+            parsed,
+            tenant,
+            #[cfg(feature = "forwarder")]
+            forwards,
+            #[cfg(feature = "otel")]
+            otel_cx,
+        } = admitted;
+        let span = tracing::info_span!(
+            "stripe_event",
+            event_id = %parsed.id,
+            event_type = %parsed.event_type,
+            request_id = parsed.request_id().unwrap_or_default(),
+            idempotency_key = parsed.idempotency_key().unwrap_or_default(),
+            connection_id = self.connection_id.as_deref().unwrap_or_default(),
+        );
+        let position = CursorPosition {
+            event_id: parsed.id.clone(),
+            created: parsed.created,
+        };
+        let answer = self.serve(service, ListenerEvent::Webhook { evt, parsed, tenant }, span).await;
+        let dispatcher = self.clone();
+        Box::pin(async move {
+            let decision = answer.await;
+            if decision == AckDecision::Ack {
+                dispatcher.save_cursor(&position);
+                #[cfg(feature = "forwarder")]
+                dispatcher.forward(forwards);
+            }
+            #[cfg(feature = "otel")]
+            otel::end(&otel_cx);
+            decision
+        })
+    }
+
+    #[cfg(feature = "tower")]
+    pub(crate) async fn serve_v2(&self, service: &EventService, evt: V2Event, parsed: V2EventPayload) -> ServiceAnswer {
+        let span = tracing::info_span!(
+            "stripe_event",
+            event_id = %parsed.id,
+            event_type = %parsed.event_type,
+            connection_id = self.connection_id.as_deref().unwrap_or_default(),
+        );
+        self.serve(service, ListenerEvent::V2 { evt, parsed }, span).await
+    }
+
+    // A service error, whether from poll_ready or the response, is logged
+    // and answered as NoAck. A pool forgets events answered NoAck, for their
+    // redelivery.
+    #[cfg(feature = "tower")]
+    async fn serve(&self, service: &EventService, event: ListenerEvent, span: tracing::Span) -> ServiceAnswer {
+        let event_id = event.id().to_string();
+        let call = service.call(event).instrument(span.clone()).await;
+        let logger = self.logger.clone();
+        let pool = self.pool.clone();
+        let answer = async move {
+            let response = match call {
+                Ok(response) => response.await,
+                Err(e) => Err(e),
+            };
+            let decision = response.unwrap_or_else(|e| {
+                logger.log(LogLevel::Warn, "service failed; the event is not acknowledged", &[("event_id", &event_id), ("error", &e)]);
+                AckDecision::NoAck
+            });
+            if let (AckDecision::NoAck, Some(pool)) = (decision, &pool) {
+                pool.release([event_id.as_str()]);
+            }
+            decision
+        };
+        Box::pin(answer.instrument(span))
+    }
+}
+
+// Runs the transform over the raw payload and re-derives the typed view from
+// the result. A payload that no longer parses keeps the original typed view.
+// The payload is only re-serialized, and true returned, when the transform
+// changed it: serialization reorders keys, which alone breaks Stripe's
+// signature.
+#[cfg(feature = "client")]
+fn transform_webhook(t: &dyn Transform, mut evt: WebhookEvent, parsed: StripeEventPayload) -> (WebhookEvent, StripeEventPayload, bool) {
+    let mut value: serde_json::Value = match serde_json::from_str(&evt.event_payload) {
+        Ok(v) => v,
+        Err(_) => return (evt, parsed, false),
+    };
+    let original = value.clone();
+    t.apply(&mut value);
+    if value == original {
+        return (evt, parsed, false);
+    }
+    evt.event_payload = value.to_string();
+    let parsed = serde_json::from_value(value).unwrap_or(parsed);
+    (evt, parsed, true)
+}
+
+#[cfg(feature = "client")]
+fn transform_v2(t: &dyn Transform, mut evt: V2Event, parsed: V2EventPayload) -> (V2Event, V2EventPayload) {
+    let mut value: serde_json::Value = match serde_json::from_str(&evt.payload) {
+        Ok(v) => v,
+        Err(_) => return (evt, parsed),
+    };
+    let original = value.clone();
+    t.apply(&mut value);
+    if value == original {
+        return (evt, parsed);
+    }
+    evt.payload = value.to_string();
+    let parsed = serde_json::from_value(value).unwrap_or(parsed);
+    (evt, parsed)
+}
@@ -0,0 +1,266 @@
+// The crate's error type and the close codes the server reports.
+use std::fmt;
+use std::time::Duration;
+
+use crate::ServerError;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Transport failure talking to the Stripe REST API.
+    #[cfg(feature = "client")]
+    Http(reqwest::Error),
+    /// The session request was rejected by Stripe.
+    Authorize { status: u16, body: String },
+    /// The host was suspended (laptop sleep) long enough that the connection
+    /// is presumed dead; it is torn down and re-authorized.
+    Resumed { slept: Duration },
+    /// A REST API call other than authorize returned a non-2xx status.
+    Api { status: u16, body: String },
+    /// A REST API call (authorize included) was still rate limited (HTTP 429)
+    /// when the ReconnectPolicy stopped retrying. `retry_after` is the
+    /// server's Retry-After, if it sent one.
+    RateLimited { retry_after: Option<Duration>, body: String },
+    /// `operation` did not finish within `after`: `"authorize"` when
+    /// Config::authorize_timeout ran out, `"api request"` when one attempt
+    /// ran past Config::authorize_attempt_timeout.
+    Timeout { operation: &'static str, after: Duration },
+    /// Transport failure on the websocket.
+    #[cfg(feature = "client")]
+    WebSocket(Box<tokio_tungstenite::tungstenite::Error>),
+    /// The server closed the websocket with anything other than a normal closure.
+    Closed(CloseReason),
+    /// The devproxy sent an error message that ReconnectPolicy::is_fatal
+    /// judged fatal to the connection.
+    Server(ServerError),
+    /// A newer listener with the same device name started with
+    /// DuplicateSessions::Takeover; `pid` is its process id.
+    TakenOver { pid: u32 },
+    /// A config file or environment override could not be read or parsed.
+    Config(String),
+    /// TlsOptions could not be applied (unreadable or invalid certificate).
+    Tls(String),
+    /// A forward route could not be reached.
+    Forward(String),
+    /// The tunnel for StripeListener::run_tunnel could not be started or
+    /// exited while running.
+    Tunnel(String),
+    /// The server negotiated a subprotocol the AckFormat cannot ack under.
+    Protocol(String),
+    /// Misuse or invalid input (bad url, header value, missing session).
+    Other(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "client")]
+            Error::Http(e) => write!(f, "http error: {}", e),
+            Error::Authorize { status, body } => write!(f, "authorize failed (HTTP {}): {}", status, body),
+            Error::Resumed { slept } => write!(f, "resumed after {:?} asleep", slept),
+            Error::Api { status, body } => write!(f, "api request failed (HTTP {}): {}", status, body),
+            Error::RateLimited { body, .. } => write!(f, "rate limited (HTTP 429): {}", body),
+            Error::Timeout { operation, after } => write!(f, "{} timed out after {:?}", operation, after),
+            #[cfg(feature = "client")]
+            Error::WebSocket(e) => write!(f, "websocket error: {}", e),
+            Error::Closed(reason) => write!(f, "websocket closed: {}", reason),
+            Error::Server(e) => write!(f, "server error: {}", e),
+            Error::TakenOver { pid } => write!(f, "taken over by listener pid {}", pid),
+            Error::Config(msg) => write!(f, "config error: {}", msg),
+            Error::Tls(msg) => write!(f, "tls error: {}", msg),
+            Error::Forward(msg) => write!(f, "forward error: {}", msg),
+            Error::Tunnel(msg) => write!(f, "tunnel error: {}", msg),
+            Error::Protocol(p) => write!(f, "no ack format for subprotocol {:?}", p),
+            Error::Other(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl Error {
+    /// A plain-language explanation of the failures new users run into most
+    /// (expired or invalid API key, test key used for live mode, account not
+    /// activated, websocket feature not enabled), with what to do about it
+    /// and where to read more, followed by the raw error. Anything else gets
+    /// the Display text. The CLI prints this when the listener fails.
+    pub fn user_message(&self) -> String {
+        match self.help() {
+            Some(help) => format!("{}\n  {}\n  See {}\n  ({})", help.problem, help.hint, help.link, self),
+            None => self.to_string(),
+        }
+    }
+
+    fn help(&self) -> Option<&'static Help> {
+        let (status, code, message) = match self {
+            Error::Authorize { status, body } | Error::Api { status, body } => {
+                let (code, message) = stripe_error(body);
+                (Some(*status), code, message)
+            }
+            Error::Server(e) => (None, e.code.clone(), e.message.clone()),
+            _ => return None,
+        };
+        let code = code.as_deref().unwrap_or_default();
+        let message = message.to_lowercase();
+        if code == "api_key_expired" || message.contains("expired api key") {
+            Some(&EXPIRED_KEY)
+        } else if code == "livemode_mismatch" || message.contains("live mode") || message.contains("livemode") {
+            Some(&MODE_MISMATCH)
+        } else if matches!(code, "account_inactive" | "account_invalid") || message.contains("activate your account") {
+            Some(&ACCOUNT_NOT_ACTIVATED)
+        } else if code == "feature_not_authorized" || message.contains("websocket feature") || message.contains("websocket_feature") {
+            Some(&FEATURE_NOT_ENABLED)
+        } else if message.contains("does not have the required permissions") {
+            Some(&KEY_PERMISSIONS)
+        } else if status == Some(401) || message.contains("invalid api key") {
+            Some(&INVALID_KEY)
+        } else {
+            None
+        }
+    }
+}
+
+// An explanation for Error::user_message.
+struct Help {
+    problem: &'static str,
+    hint: &'static str,
+    link: &'static str,
+}
+
+const EXPIRED_KEY: Help = Help {
+    problem: "The Stripe API key has expired.",
+    hint: "Roll the key or create a new one in the Dashboard, then update api_key in the config.",
+    link: "https://dashboard.stripe.com/apikeys",
+};
+
+const INVALID_KEY: Help = Help {
+    problem: "Stripe did not accept the API key.",
+    hint: "Check that api_key holds a complete secret (sk_...) or restricted (rk_...) key for the right account.",
+    link: "https://dashboard.stripe.com/apikeys",
+};
+
+const MODE_MISMATCH: Help = Help {
+    problem: "The request mixes test mode and live mode.",
+    hint: "Use a live key (sk_live_...) for live mode, or a test key (sk_test_...) with test-mode settings.",
+    link: "https://docs.stripe.com/keys#test-live-modes",
+};
+
+const ACCOUNT_NOT_ACTIVATED: Help = Help {
+    problem: "The Stripe account is not activated for live mode yet.",
+    hint: "Finish activating the account in the Dashboard, or use a test key until then.",
+    link: "https://dashboard.stripe.com/account/onboarding",
+};
+
+const FEATURE_NOT_ENABLED: Help = Help {
+    problem: "The API key may not use the requested websocket feature.",
+    hint: "Check websocket_features in the config, and give a restricted key write access to webhook endpoints and CLI sessions.",
+    link: "https://docs.stripe.com/stripe-cli",
+};
+
+const KEY_PERMISSIONS: Help = Help {
+    problem: "The restricted API key lacks a permission this call needs.",
+    hint: "Edit the key in the Dashboard to grant it, or use a secret key.",
+    link: "https://docs.stripe.com/keys#limit-access",
+};
+
+// The code and message of a Stripe error response body; the whole body is
+// the message when it is not one.
+fn stripe_error(body: &str) -> (Option<String>, String) {
+    let parsed: Option<serde_json::Value> = serde_json::from_str(body).ok();
+    let error = parsed.as_ref().map(|v| &v["error"]);
+    let field = |name: &str| error.and_then(|e| e[name].as_str()).map(str::to_string);
+    (field("code"), field("message").unwrap_or_else(|| body.to_string()))
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "client")]
+            Error::Http(e) => Some(e),
+            #[cfg(feature = "client")]
+            Error::WebSocket(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+#[cfg(feature = "client")]
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error::WebSocket(Box::new(e))
+    }
+}
+
+#[cfg(feature = "client")]
+impl From<reqwest::header::InvalidHeaderValue> for Error {
+    fn from(e: reqwest::header::InvalidHeaderValue) -> Self {
+        Error::Other(format!("invalid header value: {}", e))
+    }
+}
+
+#[cfg(feature = "client")]
+impl From<url::ParseError> for Error {
+    fn from(e: url::ParseError) -> Self {
+        Error::Other(format!("invalid url: {}", e))
+    }
+}
+
+#[cfg(feature = "client")]
+impl From<tokio_tungstenite::tungstenite::http::Error> for Error {
+    fn from(e: tokio_tungstenite::tungstenite::http::Error) -> Self {
+        Error::Other(format!("invalid handshake request: {}", e))
+    }
+}
+
+// Close frames
+/// Close code and reason sent by the server when it shuts the websocket down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseReason {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl CloseReason {
+    /// 1000: normal closure.
+    pub const NORMAL: u16 = 1000;
+    /// 1001: the server is going away.
+    pub const GOING_AWAY: u16 = 1001;
+    /// 1005: the server closed without a status code.
+    pub const NO_STATUS: u16 = 1005;
+    /// 1006: the connection dropped without a close frame.
+    pub const ABNORMAL: u16 = 1006;
+
+    /// 1000: the server finished the session cleanly.
+    pub fn is_normal(&self) -> bool {
+        self.code == Self::NORMAL
+    }
+
+    /// 1001: the server is restarting or shedding connections.
+    pub fn is_going_away(&self) -> bool {
+        self.code == Self::GOING_AWAY
+    }
+
+    /// Policy violations (usually auth or feature access) and protocol errors
+    /// will fail the same way on a fresh connection.
+    pub fn is_retryable(&self) -> bool {
+        // Protocol error, unsupported data, invalid payload, policy
+        // violation, missing extension.
+        !matches!(self.code, 1002 | 1003 | 1007 | 1008 | 1010)
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.reason.is_empty() {
+            write!(f, "code {}", self.code)
+        } else {
+            write!(f, "code {} ({})", self.code, self.reason)
+        }
+    }
+}
@@ -0,0 +1,188 @@
+// Known Stripe event types as an enum, so handlers and filters can match on
+// variants instead of strings. Types not listed here parse as Other.
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+macro_rules! event_types {
+    ($($variant:ident => $name:literal,)*) => {
+        /// A Stripe event type such as `payment_intent.succeeded`. Filters
+        /// also accept `"*"`, which parses as `Other("*")`.
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        pub enum EventType {
+            $(
+                #[doc = concat!("`", $name, "`")]
+                $variant,
+            )*
+            /// Any type not listed above, kept verbatim.
+            Other(String),
+        }
+
+        impl EventType {
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $(EventType::$variant => $name,)*
+                    EventType::Other(name) => name,
+                }
+            }
+        }
+
+        impl From<&str> for EventType {
+            fn from(name: &str) -> Self {
+                match name {
+                    $($name => EventType::$variant,)*
+                    other => EventType::Other(other.to_string()),
+                }
+            }
+        }
+    };
+}
+
+event_types! {
+    AccountUpdated => "account.updated",
+    AccountApplicationAuthorized => "account.application.authorized",
+    AccountApplicationDeauthorized => "account.application.deauthorized",
+    AccountExternalAccountCreated => "account.external_account.created",
+    BalanceAvailable => "balance.available",
+    ChargeCaptured => "charge.captured",
+    ChargeDisputeClosed => "charge.dispute.closed",
+    ChargeDisputeCreated => "charge.dispute.created",
+    ChargeExpired => "charge.expired",
+    ChargeFailed => "charge.failed",
+    ChargePending => "charge.pending",
+    ChargeRefunded => "charge.refunded",
+    ChargeSucceeded => "charge.succeeded",
+    ChargeUpdated => "charge.updated",
+    CheckoutSessionAsyncPaymentFailed => "checkout.session.async_payment_failed",
+    CheckoutSessionAsyncPaymentSucceeded => "checkout.session.async_payment_succeeded",
+    CheckoutSessionCompleted => "checkout.session.completed",
+    CheckoutSessionExpired => "checkout.session.expired",
+    CouponCreated => "coupon.created",
+    CustomerCreated => "customer.created",
+    CustomerDeleted => "customer.deleted",
+    CustomerUpdated => "customer.updated",
+    CustomerDiscountCreated => "customer.discount.created",
+    CustomerSourceCreated => "customer.source.created",
+    CustomerSubscriptionCreated => "customer.subscription.created",
+    CustomerSubscriptionDeleted => "customer.subscription.deleted",
+    CustomerSubscriptionPaused => "customer.subscription.paused",
+    CustomerSubscriptionResumed => "customer.subscription.resumed",
+    CustomerSubscriptionTrialWillEnd => "customer.subscription.trial_will_end",
+    CustomerSubscriptionUpdated => "customer.subscription.updated",
+    InvoiceCreated => "invoice.created",
+    InvoiceFinalized => "invoice.finalized",
+    InvoiceMarkedUncollectible => "invoice.marked_uncollectible",
+    InvoicePaid => "invoice.paid",
+    InvoicePaymentActionRequired => "invoice.payment_action_required",
+    InvoicePaymentFailed => "invoice.payment_failed",
+    InvoicePaymentSucceeded => "invoice.payment_succeeded",
+    InvoiceSent => "invoice.sent",
+    InvoiceUpcoming => "invoice.upcoming",
+    InvoiceUpdated => "invoice.updated",
+    InvoiceVoided => "invoice.voided",
+    PaymentIntentAmountCapturableUpdated => "payment_intent.amount_capturable_updated",
+    PaymentIntentCanceled => "payment_intent.canceled",
+    PaymentIntentCreated => "payment_intent.created",
+    PaymentIntentPartiallyFunded => "payment_intent.partially_funded",
+    PaymentIntentPaymentFailed => "payment_intent.payment_failed",
+    PaymentIntentProcessing => "payment_intent.processing",
+    PaymentIntentRequiresAction => "payment_intent.requires_action",
+    PaymentIntentSucceeded => "payment_intent.succeeded",
+    PaymentMethodAttached => "payment_method.attached",
+    PaymentMethodDetached => "payment_method.detached",
+    PaymentMethodUpdated => "payment_method.updated",
+    PayoutCreated => "payout.created",
+    PayoutFailed => "payout.failed",
+    PayoutPaid => "payout.paid",
+    PriceCreated => "price.created",
+    PriceUpdated => "price.updated",
+    ProductCreated => "product.created",
+    ProductUpdated => "product.updated",
+    RefundCreated => "refund.created",
+    RefundUpdated => "refund.updated",
+    SetupIntentCanceled => "setup_intent.canceled",
+    SetupIntentCreated => "setup_intent.created",
+    SetupIntentRequiresAction => "setup_intent.requires_action",
+    SetupIntentSetupFailed => "setup_intent.setup_failed",
+    SetupIntentSucceeded => "setup_intent.succeeded",
+    SubscriptionScheduleCreated => "subscription_schedule.created",
+    SubscriptionScheduleUpdated => "subscription_schedule.updated",
+    TransferCreated => "transfer.created",
+    TransferReversed => "transfer.reversed",
+}
+
+impl EventType {
+    /// The `"*"` filter entry, matching every type.
+    pub fn is_wildcard(&self) -> bool {
+        self.as_str() == "*"
+    }
+}
+
+impl From<String> for EventType {
+    fn from(name: String) -> Self {
+        match EventType::from(name.as_str()) {
+            EventType::Other(_) => EventType::Other(name),
+            known => known,
+        }
+    }
+}
+
+impl FromStr for EventType {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Infallible> {
+        Ok(EventType::from(s))
+    }
+}
+
+impl fmt::Display for EventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialEq<str> for EventType {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for EventType {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Serialize for EventType {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.as_str())
+    }
+}
+
+// Known types are matched on the borrowed string; only Other allocates.
+struct EventTypeVisitor;
+
+impl Visitor<'_> for EventTypeVisitor {
+    type Value = EventType;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("an event type string")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<EventType, E> {
+        Ok(EventType::from(v))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<EventType, E> {
+        Ok(EventType::from(v))
+    }
+}
+
+impl<'de> Deserialize<'de> for EventType {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_str(EventTypeVisitor)
+    }
+}
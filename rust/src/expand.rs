@@ -0,0 +1,73 @@
+// Object expansion on delivery: for selected event types, the event's
+// object is fetched from the REST API with `expand[]` and the expanded
+// fields are merged into the payload before dispatch.
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::api::ApiClient;
+use crate::{EventType, LogLevel, Logger, StripeEventPayload, WebhookEvent};
+
+/// Expansions for webhook events of the listed types, e.g. `customer` for
+/// `invoice.paid`. Paths are relative to the event's object, as in the API's
+/// `expand[]` (`customer`, `lines.data.price`).
+///
+/// Only the top-level fields named by the paths are taken from the fetched
+/// object; the rest of the payload keeps the event's snapshot. The fetch
+/// happens before dispatch and holds up the connection's later events, so
+/// expand sparingly. When it fails the event is delivered unexpanded.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Expand {
+    /// Event types to expand; `None` or `"*"` expands every event that
+    /// passed the listener's filter.
+    #[serde(default)]
+    pub events: Option<Vec<EventType>>,
+    pub paths: Vec<String>,
+}
+
+impl Expand {
+    pub fn new(events: Vec<EventType>, paths: Vec<String>) -> Self {
+        Self { events: Some(events), paths }
+    }
+
+    pub fn matches(&self, event_type: &str) -> bool {
+        match &self.events {
+            None => true,
+            Some(events) => events.iter().any(|e| e.is_wildcard() || e == event_type),
+        }
+    }
+}
+
+// Expands `evt` in place by the paths of every rule matching its type.
+pub(crate) async fn expand(api: &ApiClient, rules: &[Expand], evt: &mut WebhookEvent, parsed: &StripeEventPayload, logger: &dyn Logger) {
+    let mut paths: Vec<&str> = Vec::new();
+    for path in rules.iter().filter(|r| r.matches(parsed.event_type.as_str())).flat_map(|r| &r.paths) {
+        if !paths.contains(&path.as_str()) {
+            paths.push(path);
+        }
+    }
+    if paths.is_empty() {
+        return;
+    }
+    let Ok(mut payload) = serde_json::from_str::<Value>(&evt.event_payload) else { return };
+    let object = &mut payload["data"]["object"];
+    let (Some(kind), Some(id)) = (object["object"].as_str(), object["id"].as_str()) else {
+        logger.log(LogLevel::Warn, "cannot expand an object without type and id", &[("event_id", &parsed.id)]);
+        return;
+    };
+    let api = api.for_account(parsed.account.as_deref());
+    let fetched = match api.fetch_object(kind, id, &paths).await {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            logger.log(LogLevel::Warn, "could not expand event; delivering it unexpanded", &[("event_id", &parsed.id), ("error", &e)]);
+            return;
+        }
+    };
+    for path in paths {
+        let field = path.split('.').next().unwrap_or(path);
+        if let Some(value) = fetched.get(field) {
+            object[field] = value.clone();
+        }
+    }
+    evt.event_payload = payload.to_string();
+}
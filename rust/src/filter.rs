@@ -0,0 +1,86 @@
+// Event filters beyond the event-type list: connected account, livemode,
+// created-time window and user predicates. They run before sampling, so a
+// dropped event is never forwarded, dead-lettered or handed to the handler.
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{StripeEventPayload, Tenant, V2EventPayload};
+
+/// Declarative event filter, hot-reloadable as LiveConfig::filter. Unset
+/// fields match every event; an event is kept only if all set fields match.
+/// Dropped events are still acknowledged and counted in
+/// `ListenerStats::events_filtered_out`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventFilter {
+    /// Connected accounts (`acct_...`) whose events are kept. Events of the
+    /// platform account itself carry no account and are dropped once this
+    /// is set.
+    pub accounts: Option<Vec<String>>,
+    pub livemode: Option<bool>,
+    /// Keep events created at or after this Unix time.
+    pub created_gte: Option<u64>,
+    /// Keep events created before this Unix time.
+    pub created_lt: Option<u64>,
+    /// Tenant ids whose events are kept; see Config::tenant_resolver. Events
+    /// that resolve to no tenant are dropped once this is set.
+    pub tenants: Option<Vec<String>>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &StripeEventPayload) -> bool {
+        self.matches_account(event.account.as_deref())
+            && self.livemode.is_none_or(|l| l == event.livemode)
+            && self.created_gte.is_none_or(|t| event.created >= t)
+            && self.created_lt.is_none_or(|t| event.created < t)
+    }
+
+    /// The account and livemode conditions; v2 payloads carry `created` as
+    /// RFC 3339, so the created window is not applied to them.
+    pub fn matches_v2(&self, event: &V2EventPayload) -> bool {
+        self.matches_account(event.context.as_deref()) && self.livemode.is_none_or(|l| l == event.livemode)
+    }
+
+    /// The tenant condition, checked once Config::tenant_resolver has run.
+    pub fn matches_tenant(&self, tenant: Option<&Tenant>) -> bool {
+        match (&self.tenants, tenant) {
+            (None, _) => true,
+            (Some(tenants), Some(tenant)) => tenants.contains(&tenant.id),
+            (Some(_), None) => false,
+        }
+    }
+
+    fn matches_account(&self, account: Option<&str>) -> bool {
+        match (&self.accounts, account) {
+            (None, _) => true,
+            (Some(accounts), Some(account)) => accounts.iter().any(|a| a == account),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// A user predicate over webhook events, run after EventFilter (see
+/// Config::predicate); false drops the event. Implemented for `Fn` closures,
+/// and for `Mutex<F>` so `FnMut` closures can keep state.
+pub trait EventPredicate: Send + Sync {
+    fn keep(&self, event: &StripeEventPayload) -> bool;
+}
+
+impl<F> EventPredicate for F
+where
+    F: Fn(&StripeEventPayload) -> bool + Send + Sync,
+{
+    fn keep(&self, event: &StripeEventPayload) -> bool {
+        self(event)
+    }
+}
+
+impl<F> EventPredicate for Mutex<F>
+where
+    F: FnMut(&StripeEventPayload) -> bool + Send,
+{
+    fn keep(&self, event: &StripeEventPayload) -> bool {
+        (self.lock().unwrap_or_else(|e| e.into_inner()))(event)
+    }
+}
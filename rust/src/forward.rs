@@ -0,0 +1,534 @@
+// Forwarding of webhook deliveries to a local HTTP endpoint, mirroring
+// `stripe listen --forward-to`.
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+#[cfg(feature = "types")]
+use std::time::UNIX_EPOCH;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, HOST, USER_AGENT};
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use url::Url;
+
+use crate::config_file::de_duration_opt;
+#[cfg(feature = "types")]
+use crate::SecretString;
+use crate::{Clock, Error, EventType, ForwardResult, Result, StripeEventPayload, TlsOptions, WebhookEvent};
+
+const FORWARD_USER_AGENT: &str = "Stripe/1.0 (+https://stripe.com/docs/webhooks)";
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(30);
+// Response bodies beyond this are truncated in ForwardResult.
+const MAX_CAPTURED_BODY: usize = 64 * 1024;
+
+/// A local endpoint that receives webhook deliveries.
+///
+/// Besides `http://` and `https://`, `url` accepts two shorthands:
+/// `h2c://host:port/path` speaks HTTP/2 without TLS, and
+/// `unix:///path/to.sock` posts to `/` over a unix domain socket. For a
+/// different request path over a socket, use an `http://` url together with
+/// `connector.unix_socket`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ForwardRoute {
+    pub url: String,
+    /// Event types sent to this route; `None` or `"*"` sends everything that
+    /// passed the listener's filter.
+    #[serde(default)]
+    pub events: Option<Vec<EventType>>,
+    #[serde(default)]
+    pub connector: ConnectorConfig,
+    #[serde(default)]
+    pub rewrite: RewriteRules,
+    #[serde(default)]
+    pub retry: ForwardRetry,
+    /// Re-sign deliveries instead of passing Stripe's Stripe-Signature
+    /// through; see Signing.
+    #[cfg(feature = "types")]
+    #[serde(default)]
+    pub signing: Option<Signing>,
+}
+
+/// Signs each forward attempt with a fresh Stripe-Signature, for endpoints
+/// whose clock disagrees with Stripe's: the header Stripe generated carries
+/// Stripe's signing time, which a skewed endpoint rejects as outside its
+/// tolerance.
+#[cfg(feature = "types")]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Signing {
+    /// `whsec_...` secret to sign with; defaults to the session's signing
+    /// secret, which is what Stripe signed the delivery with. Never
+    /// serialized.
+    #[serde(skip_serializing)]
+    pub secret: Option<SecretString>,
+    pub timestamp: SignatureTimestamp,
+}
+
+/// Where a re-signed delivery's `t=` comes from.
+#[cfg(feature = "types")]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureTimestamp {
+    /// Config::clock at the time of the attempt, so an endpoint on the same
+    /// skewed host accepts it.
+    #[default]
+    Clock,
+    /// The event's `created` time, independent of any local clock. Events
+    /// older than the endpoint's tolerance are rejected.
+    EventCreated,
+}
+
+/// Retries for a route whose endpoint is down or answering 429/5xx, e.g.
+/// while a dev server restarts. The default makes a single attempt.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ForwardRetry {
+    pub max_attempts: u32,
+    #[serde(deserialize_with = "de_duration_opt", skip_serializing)]
+    pub initial_backoff: Option<Duration>,
+    #[serde(deserialize_with = "de_duration_opt", skip_serializing)]
+    pub max_backoff: Option<Duration>,
+    /// Stop retrying once this much time has passed since the first attempt.
+    #[serde(deserialize_with = "de_duration_opt", skip_serializing)]
+    pub deadline: Option<Duration>,
+}
+
+impl Default for ForwardRetry {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_backoff: None,
+            max_backoff: None,
+            deadline: None,
+        }
+    }
+}
+
+impl ForwardRetry {
+    fn backoff(&self, attempt: u32) -> Duration {
+        let initial = self.initial_backoff.unwrap_or(Duration::from_millis(500));
+        let max = self.max_backoff.unwrap_or(Duration::from_secs(10));
+        initial.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1))).min(max)
+    }
+}
+
+/// Receives deliveries that could not be forwarded after all retries.
+pub trait DeadLetterSink: Send + Sync {
+    fn dead_letter(&self, evt: &WebhookEvent, result: &ForwardResult);
+}
+
+/// Appends each dead letter as one JSON line `{"event": .., "result": ..}`.
+pub struct JsonlDeadLetter {
+    file: Mutex<std::fs::File>,
+}
+
+impl JsonlDeadLetter {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+}
+
+impl DeadLetterSink for JsonlDeadLetter {
+    fn dead_letter(&self, evt: &WebhookEvent, result: &ForwardResult) {
+        let line = serde_json::json!({ "event": evt, "result": result }).to_string();
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = writeln!(file, "{}", line);
+    }
+}
+
+pub(crate) struct ForwardResponse {
+    pub(crate) status: u16,
+    headers: HeaderMap,
+    body: String,
+}
+
+/// One forwarded request and its response, as kept for
+/// ListenerHandle::recent_deliveries. Each retry is a separate delivery.
+#[derive(Serialize, Debug, Clone)]
+pub struct Delivery {
+    pub event_id: String,
+    /// Url the request was sent to, after rewrite rules.
+    pub url: String,
+    pub sent_at: SystemTime,
+    pub request_headers: BTreeMap<String, String>,
+    pub request_body: String,
+    /// None if no response was received.
+    pub status: Option<u16>,
+    pub response_headers: BTreeMap<String, String>,
+    /// Response body, truncated to 64 KiB.
+    pub response_body: Option<String>,
+    /// Transport error, if any.
+    pub error: Option<String>,
+    pub duration: Duration,
+}
+
+// Ring buffer of the latest deliveries, shared by every connection of a
+// listener so it survives reconnects.
+#[derive(Clone)]
+pub(crate) struct RecentDeliveries {
+    capacity: usize,
+    entries: Arc<Mutex<VecDeque<Delivery>>>,
+}
+
+impl RecentDeliveries {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    fn push(&self, delivery: Delivery) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(delivery);
+    }
+
+    /// Oldest first.
+    pub(crate) fn snapshot(&self) -> Vec<Delivery> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+    }
+}
+
+/// Adjusts the request sent to a route. Path rules apply in order:
+/// choose the base path, strip `strip_prefix`, then prepend `add_prefix`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RewriteRules {
+    /// Start from the path of the endpoint Stripe delivered for (the
+    /// delivery's `endpoint.url`) instead of the route url's path.
+    pub use_endpoint_path: bool,
+    /// Leading path segments to remove: `/stripe` turns `/stripe/hooks` into
+    /// `/hooks` and `/stripe` into `/`, but leaves `/stripehooks` alone.
+    pub strip_prefix: Option<String>,
+    pub add_prefix: Option<String>,
+    /// Append the query string of the delivery's endpoint url.
+    pub preserve_query: bool,
+    /// Host header to send instead of the route url's host.
+    pub host: Option<String>,
+    /// Headers set on every request, replacing any delivered value.
+    pub headers: BTreeMap<String, String>,
+}
+
+impl RewriteRules {
+    fn apply(&self, mut url: Url, endpoint_url: Option<&str>) -> Url {
+        let endpoint = endpoint_url.and_then(|u| Url::parse(u).ok());
+        let mut path = match (&endpoint, self.use_endpoint_path) {
+            (Some(e), true) => e.path().to_string(),
+            _ => url.path().to_string(),
+        };
+        if let Some(prefix) = &self.strip_prefix {
+            match path.strip_prefix(prefix.trim_end_matches('/')) {
+                Some("") => path = "/".to_string(),
+                Some(rest) if rest.starts_with('/') => path = rest.to_string(),
+                _ => {}
+            }
+        }
+        if let Some(prefix) = &self.add_prefix {
+            path = format!("{}/{}", prefix.trim_end_matches('/'), path.trim_start_matches('/'));
+        }
+        url.set_path(&path);
+
+        if self.preserve_query {
+            if let Some(original) = endpoint.as_ref().and_then(|e| e.query()) {
+                let query = match url.query() {
+                    Some(q) if !q.is_empty() => format!("{}&{}", q, original),
+                    _ => original.to_string(),
+                };
+                url.set_query(Some(&query));
+            }
+        }
+        url
+    }
+}
+
+/// How a route's connection is made.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectorConfig {
+    /// Dial this unix socket instead of the url's host. The url still supplies
+    /// the request path and Host header.
+    pub unix_socket: Option<PathBuf>,
+    /// Use HTTP/2 with prior knowledge (h2c for `http://` urls).
+    pub http2_prior_knowledge: bool,
+    /// Per-request timeout; defaults to 30s.
+    #[serde(deserialize_with = "de_duration_opt", skip_serializing)]
+    pub timeout: Option<Duration>,
+}
+
+impl ForwardRoute {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            events: None,
+            connector: ConnectorConfig::default(),
+            rewrite: RewriteRules::default(),
+            retry: ForwardRetry::default(),
+            #[cfg(feature = "types")]
+            signing: None,
+        }
+    }
+
+    pub fn matches(&self, event_type: &str) -> bool {
+        match &self.events {
+            None => true,
+            Some(events) => events.iter().any(|e| e.is_wildcard() || e == event_type),
+        }
+    }
+
+    // Resolves the url shorthands into a concrete url plus connector settings.
+    fn target(&self) -> Result<(Url, ConnectorConfig)> {
+        let mut connector = self.connector.clone();
+        let url = Url::parse(&self.url)?;
+        let url = match url.scheme() {
+            "http" | "https" => url,
+            "h2c" => {
+                connector.http2_prior_knowledge = true;
+                Url::parse(&format!("http{}", &self.url["h2c".len()..]))?
+            }
+            "unix" => {
+                connector.unix_socket = Some(PathBuf::from(url.path()));
+                Url::parse("http://localhost/")?
+            }
+            other => return Err(Error::Forward(format!("unsupported forward scheme {:?} in {}", other, self.url))),
+        };
+        Ok((url, connector))
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Forwarder {
+    client: reqwest::Client,
+    h2c: reqwest::Client,
+    clock: Arc<dyn Clock>,
+    recent: RecentDeliveries,
+    /// The session's secret, for routes whose Signing has none.
+    #[cfg(feature = "types")]
+    pub(crate) session_secret: Option<SecretString>,
+}
+
+impl Forwarder {
+    pub(crate) fn new(tls: &TlsOptions, clock: Arc<dyn Clock>, recent: RecentDeliveries) -> Result<Self> {
+        Ok(Self {
+            client: tls.http_client_builder()?.timeout(FORWARD_TIMEOUT).build()?,
+            h2c: tls
+                .http_client_builder()?
+                .timeout(FORWARD_TIMEOUT)
+                .http2_prior_knowledge()
+                .build()?,
+            clock,
+            recent,
+            #[cfg(feature = "types")]
+            session_secret: None,
+        })
+    }
+
+    /// Forwards with the route's retry policy and reports the final outcome.
+    pub(crate) async fn deliver(&self, route: &ForwardRoute, evt: &WebhookEvent, parsed: &StripeEventPayload) -> ForwardResult {
+        let started = self.clock.instant();
+        let max_attempts = route.retry.max_attempts.max(1);
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let outcome = self.forward(route, evt, parsed).await;
+            let retryable = match &outcome {
+                Ok(resp) => resp.status == 429 || resp.status >= 500,
+                Err(_) => true,
+            };
+            let backoff = route.retry.backoff(attempt);
+            let out_of_time = route.retry.deadline.is_some_and(|d| self.clock.instant().saturating_duration_since(started) + backoff > d);
+            if !retryable || attempt >= max_attempts || out_of_time {
+                let (status, body, error) = match outcome {
+                    Ok(resp) => (Some(resp.status), Some(resp.body), None),
+                    Err(e) => (None, None, Some(e.to_string())),
+                };
+                return ForwardResult {
+                    event_id: parsed.id.clone(),
+                    url: route.url.clone(),
+                    attempts: attempt,
+                    status,
+                    body,
+                    error,
+                    duration: self.clock.instant().saturating_duration_since(started),
+                };
+            }
+            self.clock.sleep(backoff).await;
+        }
+    }
+
+    /// POSTs the event payload to the route with the delivery's headers
+    /// (including Stripe-Signature) plus X-Stripe-Request-Id and
+    /// X-Stripe-Idempotency-Key when the event came from an API request.
+    /// The request and its outcome are kept in the recent deliveries.
+    pub(crate) async fn forward(&self, route: &ForwardRoute, evt: &WebhookEvent, parsed: &StripeEventPayload) -> Result<ForwardResponse> {
+        let (url, connector) = route.target()?;
+        let endpoint_url = evt.endpoint.as_ref().map(|e| e.url.as_str());
+        let url = route.rewrite.apply(url, endpoint_url);
+        #[allow(unused_mut)]
+        let mut headers = build_headers(evt, parsed, &route.rewrite)?;
+        #[cfg(feature = "types")]
+        if let Some(signing) = &route.signing {
+            headers.insert("stripe-signature", HeaderValue::from_str(&self.sign(signing, evt, parsed)?)?);
+        }
+        let sent_at = self.clock.now();
+        let started = self.clock.instant();
+        let outcome = self.send(url.clone(), &connector, headers.clone(), evt.event_payload.clone()).await;
+
+        let (status, response_headers, response_body, error) = match &outcome {
+            Ok(resp) => (Some(resp.status), header_map(&resp.headers), Some(resp.body.clone()), None),
+            Err(e) => (None, BTreeMap::new(), None, Some(e.to_string())),
+        };
+        self.recent.push(Delivery {
+            event_id: parsed.id.clone(),
+            url: url.to_string(),
+            sent_at,
+            request_headers: header_map(&headers),
+            request_body: captured_body(evt.event_payload.as_bytes()),
+            status,
+            response_headers,
+            response_body,
+            error,
+            duration: self.clock.instant().saturating_duration_since(started),
+        });
+        outcome
+    }
+
+    #[cfg(feature = "types")]
+    fn sign(&self, signing: &Signing, evt: &WebhookEvent, parsed: &StripeEventPayload) -> Result<String> {
+        let secret = signing
+            .secret
+            .as_ref()
+            .or(self.session_secret.as_ref())
+            .ok_or_else(|| Error::Forward("signing needs a secret and the session has none".to_string()))?;
+        let timestamp = match signing.timestamp {
+            SignatureTimestamp::Clock => self.clock.now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default(),
+            SignatureTimestamp::EventCreated => parsed.created as i64,
+        };
+        Ok(crate::signature::sign(evt.event_payload.as_bytes(), secret.expose(), timestamp))
+    }
+
+    async fn send(&self, url: Url, connector: &ConnectorConfig, headers: HeaderMap, payload: String) -> Result<ForwardResponse> {
+        let timeout = connector.timeout.unwrap_or(FORWARD_TIMEOUT);
+
+        if let Some(socket) = &connector.unix_socket {
+            let send = forward_unix(socket, &url, headers, payload, connector.http2_prior_knowledge);
+            return tokio::time::timeout(timeout, send)
+                .await
+                .map_err(|_| Error::Forward(format!("timed out after {:?}", timeout)))?;
+        }
+
+        let client = if connector.http2_prior_knowledge { &self.h2c } else { &self.client };
+        let resp = client
+            .post(url)
+            .headers(headers)
+            .timeout(timeout)
+            .body(payload)
+            .send()
+            .await?;
+        let status = resp.status().as_u16();
+        let headers = resp.headers().clone();
+        let body = resp.bytes().await?;
+        Ok(ForwardResponse {
+            status,
+            headers,
+            body: captured_body(&body),
+        })
+    }
+}
+
+// Repeated headers are joined with ", ".
+fn header_map(headers: &HeaderMap) -> BTreeMap<String, String> {
+    let mut map = BTreeMap::<String, String>::new();
+    for (name, value) in headers {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        map.entry(name.to_string())
+            .and_modify(|v| {
+                v.push_str(", ");
+                v.push_str(&value);
+            })
+            .or_insert_with(|| value.into_owned());
+    }
+    map
+}
+
+fn build_headers(evt: &WebhookEvent, parsed: &StripeEventPayload, rewrite: &RewriteRules) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &evt.http_headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| Error::Forward(format!("invalid header {:?}: {}", name, e)))?;
+        if name != HOST && name != CONTENT_LENGTH {
+            headers.insert(name, HeaderValue::from_str(value)?);
+        }
+    }
+    headers.entry(CONTENT_TYPE).or_insert(HeaderValue::from_static("application/json"));
+    headers.entry(USER_AGENT).or_insert(HeaderValue::from_static(FORWARD_USER_AGENT));
+    if let Some(id) = parsed.request_id() {
+        headers.insert("x-stripe-request-id", HeaderValue::from_str(id)?);
+    }
+    if let Some(key) = parsed.idempotency_key() {
+        headers.insert("x-stripe-idempotency-key", HeaderValue::from_str(key)?);
+    }
+    for (name, value) in &rewrite.headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| Error::Forward(format!("invalid header {:?}: {}", name, e)))?;
+        headers.insert(name, HeaderValue::from_str(value)?);
+    }
+    if let Some(host) = &rewrite.host {
+        headers.insert(HOST, HeaderValue::from_str(host)?);
+    }
+    Ok(headers)
+}
+
+fn captured_body(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(&bytes[..bytes.len().min(MAX_CAPTURED_BODY)]).into_owned()
+}
+
+#[cfg(not(unix))]
+async fn forward_unix(socket: &Path, _url: &Url, _headers: HeaderMap, _payload: String, _h2: bool) -> Result<ForwardResponse> {
+    Err(Error::Forward(format!("{}: unix sockets are not supported on this platform", socket.display())))
+}
+
+#[cfg(unix)]
+async fn forward_unix(socket: &Path, url: &Url, mut headers: HeaderMap, payload: String, h2: bool) -> Result<ForwardResponse> {
+    let stream = UnixStream::connect(socket)
+        .await
+        .map_err(|e| Error::Forward(format!("{}: {}", socket.display(), e)))?;
+    let (mut sender, conn) = hyper::client::conn::Builder::new()
+        .http2_only(h2)
+        .handshake::<_, hyper::Body>(stream)
+        .await
+        .map_err(|e| Error::Forward(e.to_string()))?;
+    tokio::spawn(conn);
+
+    let path = match url.query() {
+        Some(q) => format!("{}?{}", url.path(), q),
+        None => url.path().to_string(),
+    };
+    if !h2 && !headers.contains_key(HOST) {
+        headers.insert(HOST, HeaderValue::from_str(url.host_str().unwrap_or("localhost"))?);
+    }
+    let mut req = hyper::Request::post(path)
+        .body(hyper::Body::from(payload))
+        .map_err(|e| Error::Forward(e.to_string()))?;
+    req.headers_mut().extend(headers);
+
+    let resp = sender.send_request(req).await.map_err(|e| Error::Forward(e.to_string()))?;
+    let status = resp.status().as_u16();
+    let headers = resp.headers().clone();
+    let body = hyper::body::to_bytes(resp.into_body())
+        .await
+        .map_err(|e| Error::Forward(e.to_string()))?;
+    Ok(ForwardResponse {
+        status,
+        headers,
+        body: captured_body(&body),
+    })
+}
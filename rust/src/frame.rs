@@ -0,0 +1,92 @@
+// Decoding of websocket text frames without building a serde_json::Value for
+// the whole message. The frame is split into borrowed `&RawValue` fields and
+// each field is decoded straight into the struct the message type names.
+use std::collections::HashMap;
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde_json::value::RawValue;
+
+use crate::{IncomingMessage, ServerError, V2Event, WebhookEvent};
+
+/// A decoded text frame from the devproxy websocket.
+#[derive(Debug, Clone)]
+pub enum Frame {
+    Webhook(WebhookEvent),
+    V2(V2Event),
+    /// An `error` message.
+    Error(ServerError),
+    /// Any other message type, with `data` holding every field but `type`.
+    Other(IncomingMessage),
+}
+
+impl Frame {
+    /// Parses a text frame. Only the fields of the matching message are
+    /// copied out of `text`; unknown fields end up in `extra`.
+    pub fn parse(text: &str) -> serde_json::Result<Frame> {
+        // Keys with escape sequences cannot be borrowed; no known message has
+        // any, but take the slow path rather than reject the frame.
+        let Ok(fields) = serde_json::from_str::<HashMap<&str, &RawValue>>(text) else {
+            return Frame::from_incoming(serde_json::from_str(text)?);
+        };
+        let mut fields = Fields(fields);
+        let msg_type: String = fields.take("type")?;
+        match msg_type.as_str() {
+            "webhook_event" => Ok(Frame::Webhook(WebhookEvent {
+                webhook_id: fields.take("webhook_id")?,
+                webhook_conversation_id: fields.take("webhook_conversation_id")?,
+                event_payload: fields.take("event_payload")?,
+                http_headers: fields.take_or_default("http_headers")?,
+                endpoint: fields.take_or_default("endpoint")?,
+                extra: fields.rest()?,
+            })),
+            "v2_event" => Ok(Frame::V2(V2Event {
+                destination_id: fields.take("destination_id")?,
+                payload: fields.take("payload")?,
+                extra: fields.rest()?,
+            })),
+            "error" => Ok(Frame::Error(ServerError::from_data(fields.rest()?))),
+            _ => Ok(Frame::Other(IncomingMessage {
+                msg_type,
+                data: fields.rest()?,
+            })),
+        }
+    }
+
+    // The old path through a full serde_json::Value.
+    fn from_incoming(incoming: IncomingMessage) -> serde_json::Result<Frame> {
+        match incoming.msg_type.as_str() {
+            "webhook_event" => serde_json::from_value(incoming.data).map(Frame::Webhook),
+            "v2_event" => serde_json::from_value(incoming.data).map(Frame::V2),
+            "error" => Ok(Frame::Error(ServerError::from_data(incoming.data))),
+            _ => Ok(Frame::Other(incoming)),
+        }
+    }
+}
+
+struct Fields<'a>(HashMap<&'a str, &'a RawValue>);
+
+impl Fields<'_> {
+    fn take<T: DeserializeOwned>(&mut self, name: &'static str) -> serde_json::Result<T> {
+        match self.0.remove(name) {
+            Some(raw) => serde_json::from_str(raw.get()),
+            None => Err(serde_json::Error::missing_field(name)),
+        }
+    }
+
+    fn take_or_default<T: DeserializeOwned + Default>(&mut self, name: &'static str) -> serde_json::Result<T> {
+        match self.0.remove(name) {
+            Some(raw) if raw.get() != "null" => serde_json::from_str(raw.get()),
+            _ => Ok(T::default()),
+        }
+    }
+
+    // The remaining fields as an object; empty in the common case, so no
+    // allocation beyond the map itself.
+    fn rest(self) -> serde_json::Result<serde_json::Value> {
+        let mut map = serde_json::Map::new();
+        for (name, raw) in self.0 {
+            map.insert(name.to_string(), serde_json::from_str(raw.get())?);
+        }
+        Ok(serde_json::Value::Object(map))
+    }
+}
@@ -0,0 +1,82 @@
+// The websocket upgrade request, open to customization before dialing (extra
+// headers for egress gateways, different subprotocols).
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::Request;
+use tokio_tungstenite::tungstenite::http::header::{HeaderMap, HeaderName, HeaderValue};
+use tokio_tungstenite::tungstenite::http::Uri;
+
+use crate::api::client_user_agent;
+use crate::session::CLI_VERSION;
+use crate::{Error, Result};
+
+/// Handshake request as the listener would send it: the upgrade headers,
+/// `Websocket-Id`, the stripe-cli user agents and the subprotocols of the
+/// configured AckFormat.
+pub struct HandshakeRequest {
+    inner: Request,
+    subprotocols: Vec<String>,
+}
+
+impl HandshakeRequest {
+    pub(crate) fn new(url: &str, websocket_id: &str, subprotocols: Vec<String>) -> Result<Self> {
+        let mut inner = url.into_client_request()?;
+        let headers = inner.headers_mut();
+        headers.insert("Websocket-Id", HeaderValue::from_str(websocket_id)?);
+        headers.insert("User-Agent", HeaderValue::from_str(&format!("Stripe/v1 stripe-cli/{}", CLI_VERSION))?);
+        headers.insert("X-Stripe-Client-User-Agent", HeaderValue::from_str(&client_user_agent())?);
+        Ok(Self {
+            inner,
+            subprotocols,
+        })
+    }
+
+    pub fn uri(&self) -> &Uri {
+        self.inner.uri()
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        self.inner.headers()
+    }
+
+    /// Headers sent with the upgrade. Removing the upgrade headers or
+    /// `Websocket-Id` breaks the handshake.
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.inner.headers_mut()
+    }
+
+    /// Sets a header, replacing any previous value.
+    pub fn insert_header(&mut self, name: &str, value: &str) -> Result<()> {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| Error::Other(format!("invalid header {:?}: {}", name, e)))?;
+        self.inner.headers_mut().insert(name, HeaderValue::from_str(value)?);
+        Ok(())
+    }
+
+    /// Offered in `Sec-WebSocket-Protocol`, in order. The server's choice
+    /// must be one the AckFormat supports.
+    pub fn subprotocols_mut(&mut self) -> &mut Vec<String> {
+        &mut self.subprotocols
+    }
+
+    // The request and the subprotocols it offers.
+    pub(crate) fn into_request(mut self) -> Result<(Request, Vec<String>)> {
+        if !self.subprotocols.is_empty() {
+            let protocols = HeaderValue::from_str(&self.subprotocols.join(", "))?;
+            self.inner.headers_mut().insert("Sec-WebSocket-Protocol", protocols);
+        }
+        Ok((self.inner, self.subprotocols))
+    }
+}
+
+/// Adjusts the handshake request before each dial. Implemented for closures.
+pub trait HandshakeCustomizer: Send + Sync {
+    fn customize(&self, request: &mut HandshakeRequest) -> Result<()>;
+}
+
+impl<F> HandshakeCustomizer for F
+where
+    F: Fn(&mut HandshakeRequest) -> Result<()> + Send + Sync,
+{
+    fn customize(&self, request: &mut HandshakeRequest) -> Result<()> {
+        self(request)
+    }
+}
@@ -0,0 +1,311 @@
+// Logger trait the listener reports through, the live level filter and
+// coalescing of per-event messages.
+#[cfg(feature = "client")]
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::fmt;
+#[cfg(feature = "client")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "client")]
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "client")]
+use tokio::time::Instant;
+
+#[cfg(feature = "client")]
+use crate::config_file::de_duration;
+#[cfg(feature = "client")]
+use crate::{Clock, ConfigHandle};
+
+#[cfg(feature = "client")]
+const DEFAULT_COALESCE_WINDOW: Duration = Duration::from_secs(10);
+
+/// A structured log field; the value is rendered with Display.
+pub type Field<'a> = (&'a str, &'a dyn fmt::Display);
+
+// Logger trait
+pub trait Logger: Send + Sync {
+    fn debug(&self, msg: &str);
+    fn info(&self, msg: &str);
+    fn warn(&self, msg: &str);
+    fn error(&self, msg: &str);
+
+    /// Entry point for messages with fields such as `event_id` and
+    /// `websocket_id`. The default appends them as ` key=value` and calls
+    /// the method for `level`; override it to keep the fields structured.
+    fn log(&self, level: LogLevel, msg: &str, fields: &[Field<'_>]) {
+        let mut line = msg.to_string();
+        for (key, value) in fields {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+        match level {
+            LogLevel::Debug => self.debug(&line),
+            LogLevel::Info => self.info(&line),
+            LogLevel::Warn => self.warn(&line),
+            LogLevel::Error => self.error(&line),
+            LogLevel::Off => {}
+        }
+    }
+}
+
+pub struct NopLogger;
+impl Logger for NopLogger {
+    fn debug(&self, _msg: &str) {}
+    fn info(&self, _msg: &str) {}
+    fn warn(&self, _msg: &str) {}
+    fn error(&self, _msg: &str) {}
+}
+
+/// Minimum level passed through to the configured Logger.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+    Off,
+}
+
+// Adds fixed fields to every message: the device name and account of a
+// listener, and the id of each of its connections.
+#[cfg(feature = "client")]
+pub(crate) struct LabeledLogger {
+    pub(crate) inner: Arc<dyn Logger>,
+    pub(crate) labels: Vec<(&'static str, String)>,
+}
+
+#[cfg(feature = "client")]
+impl Logger for LabeledLogger {
+    fn debug(&self, msg: &str) {
+        self.log(LogLevel::Debug, msg, &[]);
+    }
+    fn info(&self, msg: &str) {
+        self.log(LogLevel::Info, msg, &[]);
+    }
+    fn warn(&self, msg: &str) {
+        self.log(LogLevel::Warn, msg, &[]);
+    }
+    fn error(&self, msg: &str) {
+        self.log(LogLevel::Error, msg, &[]);
+    }
+    fn log(&self, level: LogLevel, msg: &str, fields: &[Field<'_>]) {
+        let mut all = fields.to_vec();
+        all.extend(self.labels.iter().map(|(key, value)| (*key, value as &dyn fmt::Display)));
+        self.inner.log(level, msg, &all);
+    }
+}
+
+// Drops messages below the live log level before they reach the user's Logger.
+#[cfg(feature = "client")]
+pub(crate) struct LevelFilterLogger {
+    pub(crate) inner: Arc<dyn Logger>,
+    pub(crate) config: ConfigHandle,
+}
+
+#[cfg(feature = "client")]
+impl LevelFilterLogger {
+    fn enabled(&self, level: LogLevel) -> bool {
+        level >= self.config.snapshot().log_level
+    }
+}
+
+#[cfg(feature = "client")]
+impl Logger for LevelFilterLogger {
+    fn debug(&self, msg: &str) {
+        if self.enabled(LogLevel::Debug) {
+            self.inner.debug(msg);
+        }
+    }
+    fn info(&self, msg: &str) {
+        if self.enabled(LogLevel::Info) {
+            self.inner.info(msg);
+        }
+    }
+    fn warn(&self, msg: &str) {
+        if self.enabled(LogLevel::Warn) {
+            self.inner.warn(msg);
+        }
+    }
+    fn error(&self, msg: &str) {
+        if self.enabled(LogLevel::Error) {
+            self.inner.error(msg);
+        }
+    }
+    fn log(&self, level: LogLevel, msg: &str, fields: &[Field<'_>]) {
+        if level != LogLevel::Off && self.enabled(level) {
+            self.inner.log(level, msg, fields);
+        }
+    }
+}
+
+/// Coalesces per-event log messages under high volume. Within each window
+/// the first message with a given text, level and `event_type` field is
+/// logged in full and the rest are only counted; once the window ends the
+/// count is logged as one line, e.g. `forwarded: 411 more invoice.paid in
+/// last 10s`. Messages without an `event_type` field, or at a level not in
+/// `levels`, are never coalesced.
+///
+/// Summaries are written when the window ends, by a timer while run() is
+/// going and otherwise with the next message, and whatever is left when the
+/// listener is dropped.
+#[cfg(feature = "client")]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogCoalescing {
+    /// Levels to coalesce (default debug and info).
+    pub levels: Vec<LogLevel>,
+    #[serde(deserialize_with = "de_duration")]
+    pub window: Duration,
+}
+
+#[cfg(feature = "client")]
+impl Default for LogCoalescing {
+    fn default() -> Self {
+        Self {
+            levels: vec![LogLevel::Debug, LogLevel::Info],
+            window: DEFAULT_COALESCE_WINDOW,
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+type CoalesceKey = (LogLevel, String, String);
+
+// Applies LogCoalescing in front of the level filter, so that the
+// connection's debug messages keep flushing windows even when they are not
+// shown.
+#[cfg(feature = "client")]
+pub(crate) struct CoalescingLogger {
+    inner: Arc<dyn Logger>,
+    settings: LogCoalescing,
+    clock: Arc<dyn Clock>,
+    // Start of the current window, and per key the messages suppressed in it.
+    window: Mutex<(Option<Instant>, BTreeMap<CoalesceKey, u64>)>,
+}
+
+#[cfg(feature = "client")]
+impl CoalescingLogger {
+    pub(crate) fn new(inner: Arc<dyn Logger>, settings: LogCoalescing, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            inner,
+            settings,
+            clock,
+            window: Mutex::new((None, BTreeMap::new())),
+        }
+    }
+
+    // Takes the counts of the current window if it has run its length.
+    fn take_ended(&self, window: &mut (Option<Instant>, BTreeMap<CoalesceKey, u64>), now: Instant) -> Option<(BTreeMap<CoalesceKey, u64>, Duration)> {
+        let (started, counts) = window;
+        match *started {
+            Some(start) if now.duration_since(start) >= self.settings.window => {
+                *started = None;
+                Some((std::mem::take(counts), now.duration_since(start)))
+            }
+            _ => None,
+        }
+    }
+
+    // Writes the summary of a window that has ended; returns how long until
+    // the open one, or a window started from now, ends.
+    fn flush(&self) -> Duration {
+        let now = self.clock.instant();
+        let (ended, remaining) = {
+            let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+            let ended = self.take_ended(&mut window, now);
+            let remaining = match window.0 {
+                Some(start) => self.settings.window.saturating_sub(now.duration_since(start)),
+                None => self.settings.window,
+            };
+            (ended, remaining)
+        };
+        if let Some((counts, elapsed)) = ended {
+            self.summarize(counts, elapsed);
+        }
+        remaining
+    }
+
+    fn summarize(&self, counts: BTreeMap<CoalesceKey, u64>, elapsed: Duration) {
+        for ((level, msg, event_type), count) in counts.into_iter().filter(|(_, count)| *count > 0) {
+            let line = format!("{}: {} more {} in last {}s", msg, count, event_type, elapsed.as_secs());
+            self.inner.log(level, &line, &[("event_type", &event_type), ("count", &count)]);
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+impl Logger for CoalescingLogger {
+    fn debug(&self, msg: &str) {
+        self.inner.debug(msg);
+    }
+    fn info(&self, msg: &str) {
+        self.inner.info(msg);
+    }
+    fn warn(&self, msg: &str) {
+        self.inner.warn(msg);
+    }
+    fn error(&self, msg: &str) {
+        self.inner.error(msg);
+    }
+    fn log(&self, level: LogLevel, msg: &str, fields: &[Field<'_>]) {
+        let event_type = match self.settings.levels.contains(&level) {
+            true => fields.iter().find(|(key, _)| *key == "event_type").map(|(_, value)| value.to_string()),
+            false => None,
+        };
+        let now = self.clock.instant();
+        let (ended, suppressed) = {
+            let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+            let ended = self.take_ended(&mut window, now);
+            let (started, counts) = &mut *window;
+            let suppressed = match event_type {
+                Some(event_type) => {
+                    started.get_or_insert(now);
+                    match counts.entry((level, msg.to_string(), event_type)) {
+                        Entry::Vacant(first) => {
+                            first.insert(0);
+                            false
+                        }
+                        Entry::Occupied(mut seen) => {
+                            *seen.get_mut() += 1;
+                            true
+                        }
+                    }
+                }
+                None => false,
+            };
+            (ended, suppressed)
+        };
+        if let Some((counts, elapsed)) = ended {
+            self.summarize(counts, elapsed);
+        }
+        if !suppressed {
+            self.inner.log(level, msg, fields);
+        }
+    }
+}
+
+// Writes each window's summary when it ends rather than with the next
+// message; run() owns it in the listener's TaskSet.
+#[cfg(feature = "client")]
+pub(crate) async fn flush_coalesced(logger: Arc<CoalescingLogger>) {
+    // A zero window never holds a message back.
+    if logger.settings.window.is_zero() {
+        return;
+    }
+    loop {
+        let remaining = logger.flush();
+        logger.clock.sleep(remaining).await;
+    }
+}
+
+#[cfg(feature = "client")]
+impl Drop for CoalescingLogger {
+    fn drop(&mut self) {
+        let (started, counts) = std::mem::take(self.window.get_mut().unwrap_or_else(|e| e.into_inner()));
+        if let Some(start) = started {
+            self.summarize(counts, self.clock.instant().duration_since(start));
+        }
+    }
+}
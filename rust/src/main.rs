@@ -0,0 +1,382 @@
+// stripelistener CLI: runs a listener from a config file (see FileConfig).
+// Forwarding comes from the file's routes; received events are logged.
+//
+//   stripelistener --config listener.toml
+//   stripelistener --config listener.toml --daemon --pid-file /run/stripelistener.pid
+//   stripelistener listen --config listener.toml --output ndjson | my-processor
+//   my-producer | stripelistener pipe [--config listener.toml]
+//
+// Outside --daemon, the session's `whsec_...` signing secret is printed to
+// stderr once authorized, as `stripe listen` does, for configuring local
+// signature verification.
+//
+// `--diagnostics <file>` writes ListenerHandle::dump_diagnostics there
+// whenever the listener fails, for attaching to bug reports.
+//
+// --daemon is for running under a service manager: it writes the pid file,
+// reports readiness with sd_notify (feature `systemd`), restarts the
+// listener after fatal errors, and drains gracefully on SIGTERM.
+//
+// `--output ndjson` writes each event to stdout as one JSON line (the
+// Recording format) instead of logging it; logs stay on stderr. `pipe`
+// reads such lines, or bare event objects, from stdin and runs them through
+// the config's filters, transforms and forward routes; `--signing-secret`
+// re-signs the transformed ones it forwards.
+//
+// `service` (feature `service`) installs the daemon as a Windows service
+// or launchd job; see service.rs.
+#[cfg(feature = "service")]
+mod service;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use env_logger::Env;
+use log::{debug, error, info, warn};
+use stripelistener::{
+    Config, EventHandler, JsonlRecorder, ListenerHandle, Logger, NdjsonInput, NopHandler, StripeEventPayload, StripeListener, V2Event,
+    V2EventPayload, WebhookEvent,
+};
+
+const RESTART_DELAY: Duration = Duration::from_secs(10);
+
+const USAGE: &str = "usage: stripelistener [listen] --config <file> [--output log|ndjson] [--daemon] [--pid-file <file>] [--diagnostics <file>]
+       stripelistener pipe [--config <file>] [--output log|ndjson] [--signing-secret <whsec>]
+       stripelistener service install|uninstall|start|stop [--config <file>] [--name <name>]";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Listen,
+    Pipe,
+    #[cfg(feature = "service")]
+    Service(service::Action),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Log,
+    Ndjson,
+}
+
+struct Args {
+    mode: Mode,
+    config: Option<PathBuf>,
+    output: Output,
+    daemon: bool,
+    pid_file: Option<PathBuf>,
+    diagnostics: Option<PathBuf>,
+    #[cfg(feature = "types")]
+    signing_secret: Option<String>,
+    #[cfg(feature = "service")]
+    service_name: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut mode = Mode::Listen;
+    let mut config = None;
+    let mut output = Output::Log;
+    let mut daemon = false;
+    let mut pid_file = None;
+    let mut diagnostics = None;
+    #[cfg(feature = "types")]
+    let mut signing_secret = None;
+    #[cfg(feature = "service")]
+    let mut service_name = None;
+    let mut args = std::env::args().skip(1).peekable();
+    match args.peek().map(String::as_str) {
+        Some("listen") => {
+            args.next();
+        }
+        Some("pipe") => {
+            mode = Mode::Pipe;
+            args.next();
+        }
+        #[cfg(feature = "service")]
+        Some("service") => {
+            args.next();
+            let action = args.next().and_then(|a| service::Action::parse(&a)).ok_or_else(|| USAGE.to_string())?;
+            mode = Mode::Service(action);
+        }
+        _ => {}
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" | "-c" => config = Some(PathBuf::from(args.next().ok_or("--config needs a path")?)),
+            "--output" | "-o" => {
+                output = match args.next().as_deref() {
+                    Some("log") => Output::Log,
+                    Some("ndjson") => Output::Ndjson,
+                    _ => return Err("--output needs log or ndjson".to_string()),
+                }
+            }
+            "--daemon" if mode == Mode::Listen => daemon = true,
+            "--pid-file" if mode == Mode::Listen => pid_file = Some(PathBuf::from(args.next().ok_or("--pid-file needs a path")?)),
+            "--diagnostics" if mode == Mode::Listen => diagnostics = Some(PathBuf::from(args.next().ok_or("--diagnostics needs a path")?)),
+            #[cfg(feature = "types")]
+            "--signing-secret" if mode == Mode::Pipe => signing_secret = Some(args.next().ok_or("--signing-secret needs a whsec_ secret")?),
+            #[cfg(feature = "service")]
+            "--name" if matches!(mode, Mode::Service(_)) => service_name = Some(args.next().ok_or("--name needs a service name")?),
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            other => return Err(format!("unknown argument {:?}\n{}", other, USAGE)),
+        }
+    }
+    if mode == Mode::Listen && config.is_none() {
+        return Err(USAGE.to_string());
+    }
+    Ok(Args {
+        mode,
+        config,
+        output,
+        daemon,
+        pid_file,
+        diagnostics,
+        #[cfg(feature = "types")]
+        signing_secret,
+        #[cfg(feature = "service")]
+        service_name,
+    })
+}
+
+struct LogHandler;
+
+impl EventHandler for LogHandler {
+    fn on_webhook_event(&self, _evt: WebhookEvent, parsed: StripeEventPayload) {
+        info!("event {} {}", parsed.event_type, parsed.id);
+    }
+
+    fn on_v2_event(&self, _evt: V2Event, parsed: V2EventPayload) {
+        info!("v2 event {} {}", parsed.event_type, parsed.id);
+    }
+
+    fn on_unknown_message(&self, raw_type: String, _data: serde_json::Value) {
+        debug!("unknown message {}", raw_type);
+    }
+}
+
+struct LogCrateLogger;
+
+impl Logger for LogCrateLogger {
+    fn debug(&self, msg: &str) {
+        debug!("{}", msg);
+    }
+    fn info(&self, msg: &str) {
+        info!("{}", msg);
+    }
+    fn warn(&self, msg: &str) {
+        warn!("{}", msg);
+    }
+    fn error(&self, msg: &str) {
+        error!("{}", msg);
+    }
+}
+
+fn handler(output: Output) -> Arc<dyn EventHandler> {
+    match output {
+        Output::Log => Arc::new(LogHandler),
+        Output::Ndjson => Arc::new(JsonlRecorder::new(std::io::stdout(), Arc::new(NopHandler))),
+    }
+}
+
+// Pipe mode runs without a config file too; listen mode always has one.
+fn load_config(args: &Args) -> stripelistener::Result<Config> {
+    let mut cfg = match &args.config {
+        Some(path) => Config::from_file(path)?,
+        None => Config::new("", Arc::new(NopHandler)),
+    };
+    cfg.handler = handler(args.output);
+    cfg.logger = Some(Arc::new(LogCrateLogger));
+    Ok(cfg)
+}
+
+#[cfg(feature = "systemd")]
+fn notify(state: &[sd_notify::NotifyState<'_>]) {
+    if let Err(e) = sd_notify::notify(false, state) {
+        warn!("sd_notify failed: {}", e);
+    }
+}
+
+// Resolves on SIGTERM or Ctrl+C.
+async fn terminated() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => tokio::select! {
+                _ = term.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            },
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+fn dump_diagnostics(args: &Args, handle: &ListenerHandle) {
+    if let Some(path) = &args.diagnostics {
+        match handle.dump_diagnostics(path) {
+            Ok(()) => info!("diagnostics written to {}", path.display()),
+            Err(e) => warn!("diagnostics {}: {}", path.display(), e),
+        }
+    }
+}
+
+// Tells the service manager we are ready once the first frame arrives.
+async fn report_ready(handle: ListenerHandle) {
+    while handle.stats().last_activity.is_none() {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    info!("listener ready");
+    #[cfg(feature = "systemd")]
+    notify(&[sd_notify::NotifyState::Ready]);
+}
+
+// Prints the session's signing secret once authorized, and again whenever a
+// reauthorized session brings a new one.
+async fn print_signing_secret(handle: ListenerHandle) {
+    let mut printed: Option<String> = None;
+    loop {
+        if let Some(secret) = handle.signing_secret() {
+            if printed.as_deref() != Some(secret.expose()) {
+                eprintln!("Your webhook signing secret is {} (^C to quit)", secret.expose());
+                printed = Some(secret.expose().to_string());
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+// Drains and returns once `stop_rx` turns true: on SIGTERM, or when the
+// Windows service manager stops the service.
+async fn run_daemon(args: &Args, stop_rx: tokio::sync::watch::Receiver<bool>) -> stripelistener::Result<()> {
+    let mut ready = false;
+    loop {
+        let mut listener = StripeListener::new(load_config(args)?);
+        let handle = listener.handle();
+        // Watches this listener until one of them has become ready.
+        let reporter = (!ready).then(|| tokio::spawn(report_ready(handle.clone())));
+        let mut stop = stop_rx.clone();
+        let stopping = handle.clone();
+        let stopper = tokio::spawn(async move {
+            if stop.wait_for(|stop| *stop).await.is_ok() {
+                stopping.shutdown();
+            }
+        });
+        let result = listener.run().await;
+        stopper.abort();
+        if let Some(reporter) = reporter {
+            ready = reporter.is_finished();
+            reporter.abort();
+        }
+        if result.is_err() {
+            dump_diagnostics(args, &handle);
+        }
+        if let Ok(report) = &result {
+            info!("{}", report);
+        }
+        // Restarting after a takeover would only take the device back.
+        if *stop_rx.borrow() || matches!(result, Err(stripelistener::Error::TakenOver { .. })) {
+            return result.map(drop);
+        }
+        match result {
+            Ok(_) => info!("listener stopped; restarting in {:?}", RESTART_DELAY),
+            Err(e) => error!("listener failed: {}; restarting in {:?}", e.user_message(), RESTART_DELAY),
+        }
+        let mut stop = stop_rx.clone();
+        tokio::select! {
+            _ = tokio::time::sleep(RESTART_DELAY) => {}
+            _ = stop.wait_for(|stop| *stop) => return Ok(()),
+        }
+    }
+}
+
+async fn run_pipe(args: &Args) -> stripelistener::Result<()> {
+    let cfg = load_config(args)?;
+    let handler = cfg.handler.clone();
+    let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+    #[allow(unused_mut)]
+    let mut input = NdjsonInput::new(stdin).config(cfg);
+    #[cfg(feature = "types")]
+    if let Some(secret) = &args.signing_secret {
+        input = input.signing_secret(secret.as_str());
+    }
+    input.run(handler).await
+}
+
+async fn run_once(args: &Args) -> stripelistener::Result<()> {
+    let mut listener = StripeListener::new(load_config(args)?);
+    let handle = listener.handle();
+    let stopping = handle.clone();
+    tokio::spawn(async move {
+        terminated().await;
+        stopping.shutdown();
+    });
+    let printer = tokio::spawn(print_signing_secret(handle.clone()));
+    let result = listener.run().await;
+    printer.abort();
+    let report = result.inspect_err(|_| dump_diagnostics(args, &handle))?;
+    eprintln!("{}", report);
+    Ok(())
+}
+
+fn stop_on_signal() -> tokio::sync::watch::Receiver<bool> {
+    let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+    tokio::spawn(async move {
+        terminated().await;
+        info!("termination requested, draining");
+        #[cfg(feature = "systemd")]
+        notify(&[sd_notify::NotifyState::Stopping]);
+        let _ = stop_tx.send(true);
+    });
+    stop_rx
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            std::process::exit(2);
+        }
+    };
+    #[cfg(feature = "service")]
+    if let Mode::Service(action) = args.mode {
+        // `run` logs to the Event Log and blocks until the service stops.
+        if action != service::Action::Run {
+            env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+        }
+        if let Err(e) = tokio::task::block_in_place(|| service::manage(action, &args)) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    if let Some(path) = &args.pid_file {
+        if let Err(e) = std::fs::write(path, format!("{}\n", std::process::id())) {
+            eprintln!("pid file {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+    let result = match (args.mode, args.daemon) {
+        (Mode::Pipe, _) => run_pipe(&args).await,
+        (Mode::Listen, true) => run_daemon(&args, stop_on_signal()).await,
+        (Mode::Listen, false) => run_once(&args).await,
+        #[cfg(feature = "service")]
+        (Mode::Service(_), _) => unreachable!(),
+    };
+    if let Some(path) = &args.pid_file {
+        let _ = std::fs::remove_file(path);
+    }
+    if let Err(e) = result {
+        error!("{}", e.user_message());
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,30 @@
+// OpenTelemetry span per event. The span context is injected into the
+// delivery headers as `traceparent`, so forwarded requests and dead-letter
+// records carry it. Uses the globally installed tracer and propagator.
+use std::collections::HashMap;
+
+use opentelemetry::trace::{SpanKind, TraceContextExt, Tracer};
+use opentelemetry::{global, Context, KeyValue};
+
+const TRACER_NAME: &str = "stripelistener";
+
+pub(crate) fn event_context(event_id: &str, event_type: &str, headers: Option<&mut HashMap<String, String>>) -> Context {
+    let tracer = global::tracer(TRACER_NAME);
+    let span = tracer
+        .span_builder(format!("stripe.event {}", event_type))
+        .with_kind(SpanKind::Consumer)
+        .with_attributes(vec![
+            KeyValue::new("stripe.event_id", event_id.to_string()),
+            KeyValue::new("stripe.event_type", event_type.to_string()),
+        ])
+        .start(&tracer);
+    let cx = Context::current_with_span(span);
+    if let Some(headers) = headers {
+        global::get_text_map_propagator(|p| p.inject_context(&cx, headers));
+    }
+    cx
+}
+
+pub(crate) fn end(cx: &Context) {
+    cx.span().end();
+}
@@ -0,0 +1,192 @@
+// Several concurrent sessions feeding one handler, for test environments that
+// produce more traffic than a single listener comfortably handles.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::future::join_all;
+use tokio::time::Instant;
+
+use crate::{Clock, Config, ConfigHandle, LiveConfig, Result, SessionReport, StripeListener};
+
+// How long event ids are remembered to drop copies delivered on sibling
+// sessions, unless ListenerPool::dedupe_window says otherwise.
+const DEFAULT_DEDUPE_WINDOW: Duration = Duration::from_secs(300);
+
+// The event ids claimed by any listener of the pool within the window.
+struct Seen {
+    window: Duration,
+    ids: HashSet<String>,
+    order: VecDeque<(Instant, String)>,
+}
+
+// State the listeners of a pool share: which sessions are connected, for
+// routing, and the claimed event ids, for de-duplication.
+struct Shared {
+    connected: Vec<AtomicBool>,
+    seen: Mutex<Seen>,
+    clock: Arc<dyn Clock>,
+}
+
+// One listener's place in its pool. The dispatcher claims each event through
+// it before anything else runs, so an event is handled, forwarded, counted
+// and saved to the cursor by one listener only.
+#[derive(Clone)]
+pub(crate) struct PoolMember {
+    shared: Arc<Shared>,
+    index: usize,
+}
+
+impl PoolMember {
+    // Marks this listener's session connected until the guard is dropped.
+    pub(crate) fn connected(&self) -> Connected {
+        self.shared.connected[self.index].store(true, Ordering::SeqCst);
+        Connected(self.clone())
+    }
+
+    // Claims the event for this listener; false if a sibling handles it.
+    // The conversation's owner takes it while connected, leaving the copies
+    // on other sessions to be dropped; with the owner down, the first copy
+    // to arrive wins.
+    pub(crate) fn claim(&self, event_id: &str, conversation: &str) -> bool {
+        let owner = self.owner(conversation);
+        if owner != self.index && self.shared.connected[owner].load(Ordering::SeqCst) {
+            return false;
+        }
+        let now = self.shared.clock.instant();
+        let mut seen = self.shared.seen.lock().unwrap_or_else(|e| e.into_inner());
+        while let Some((at, _)) = seen.order.front() {
+            if now.duration_since(*at) < seen.window {
+                break;
+            }
+            if let Some((_, old)) = seen.order.pop_front() {
+                seen.ids.remove(&old);
+            }
+        }
+        if !seen.ids.insert(event_id.to_string()) {
+            return false;
+        }
+        seen.order.push_back((now, event_id.to_string()));
+        true
+    }
+
+    // Forgets claimed events that were not acknowledged, so Stripe's
+    // redelivery is handled on whichever session it arrives.
+    pub(crate) fn release<'a>(&self, event_ids: impl IntoIterator<Item = &'a str>) {
+        let mut seen = self.shared.seen.lock().unwrap_or_else(|e| e.into_inner());
+        for id in event_ids {
+            if seen.ids.remove(id) {
+                seen.order.retain(|(_, claimed)| claimed != id);
+            }
+        }
+    }
+
+    fn owner(&self, conversation: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        conversation.hash(&mut hasher);
+        jump_hash(hasher.finish(), self.shared.connected.len())
+    }
+}
+
+pub(crate) struct Connected(PoolMember);
+
+impl Drop for Connected {
+    fn drop(&mut self) {
+        self.0.shared.connected[self.0.index].store(false, Ordering::SeqCst);
+    }
+}
+
+// Jump consistent hash (Lamping & Veach): maps a key to one of `buckets`
+// sessions, moving only 1/n of keys when the session count changes.
+fn jump_hash(mut key: u64, buckets: usize) -> usize {
+    let (mut b, mut j) = (-1i64, 0i64);
+    while j < buckets as i64 {
+        b = j;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b as usize
+}
+
+/// Runs `connections` listeners side by side, each with its own session
+/// (device name suffixed `-0`, `-1`, ...), delivering into the configured
+/// handler as one de-duplicated stream. All listeners share one ConfigHandle.
+///
+/// Stripe delivers every event to each session. Conversations are routed
+/// across the sessions by consistent hashing: each event is handled,
+/// forwarded or passed to the service by the listener its conversation
+/// hashes to, so that work runs on N read loops, and the other sessions ack
+/// their copies and drop them. While a conversation's session is
+/// disconnected its events go to whichever copy arrives first, so they keep
+/// flowing during a reconnect. If the owner drops after a sibling has
+/// dropped its copy, the owner's unacked copy is redelivered by Stripe.
+///
+/// Copies are recognized for dedupe_window (default 5 minutes) after the
+/// first one is claimed. Each listener's stats count its own share of the
+/// events beyond `events_received`.
+pub struct ListenerPool {
+    listeners: Vec<StripeListener>,
+    live: ConfigHandle,
+    shared: Arc<Shared>,
+}
+
+impl ListenerPool {
+    pub fn new(mut cfg: Config, connections: usize) -> Self {
+        let connections = connections.max(1);
+        cfg.defaults();
+        let shared = Arc::new(Shared {
+            connected: (0..connections).map(|_| AtomicBool::new(false)).collect(),
+            seen: Mutex::new(Seen {
+                window: DEFAULT_DEDUPE_WINDOW,
+                ids: HashSet::new(),
+                order: VecDeque::new(),
+            }),
+            clock: cfg.clock.clone().unwrap(),
+        });
+
+        let live = ConfigHandle::new(LiveConfig::from_config(&cfg));
+        let base_name = cfg.device_name.clone().unwrap_or_default();
+        let listeners = (0..connections)
+            .map(|i| {
+                let mut c = cfg.clone();
+                c.device_name = Some(format!("{}-{}", base_name, i));
+                let mut listener = StripeListener::with_config_handle(c, live.clone());
+                listener.pool = Some(PoolMember {
+                    shared: shared.clone(),
+                    index: i,
+                });
+                listener
+            })
+            .collect();
+
+        Self { listeners, live, shared }
+    }
+
+    /// How long an event id is remembered to drop copies delivered on the
+    /// other sessions. It should cover the lag of the slowest session
+    /// behind the fastest under load.
+    pub fn dedupe_window(self, window: Duration) -> Self {
+        self.shared.seen.lock().unwrap_or_else(|e| e.into_inner()).window = window;
+        self
+    }
+
+    pub fn listeners(&self) -> &[StripeListener] {
+        &self.listeners
+    }
+
+    /// Handle shared by every listener in the pool.
+    pub fn config_handle(&self) -> ConfigHandle {
+        self.live.clone()
+    }
+
+    /// Runs every listener until all of them return. Returns the first error,
+    /// if any listener gave up, or else each listener's SessionReport.
+    /// Cancel-safe like StripeListener::run.
+    pub async fn run(&mut self) -> Result<Vec<SessionReport>> {
+        let results = join_all(self.listeners.iter_mut().map(|l| l.run())).await;
+        results.into_iter().collect()
+    }
+}
@@ -0,0 +1,6 @@
+// The types most handlers need: `use stripelistener::prelude::*;`.
+pub use crate::{
+    Error, EventHandler, EventType, LogLevel, Logger, Result, Router, StripeEventPayload, V2Event, V2EventPayload, V2EventType, WebhookEvent,
+};
+#[cfg(feature = "client")]
+pub use crate::{Config, ConfigHandle, ListenerHandle, StripeListener};
@@ -0,0 +1,438 @@
+// Messages exchanged with the Stripe CLI session endpoint and devproxy
+// websocket.
+use std::fmt;
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{schema, EventRequest, EventType, SecretString};
+#[cfg(feature = "stripe-types")]
+use crate::{Error, Result};
+
+#[cfg(feature = "client")]
+pub use crate::devproxy::{DevProxyClient, IncomingFrame};
+
+#[cfg(feature = "client")]
+pub(crate) const SUBPROTOCOL: &str = "stripecli-devproxy-v1";
+
+/// The response to POST /v1/stripecli/sessions.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Session {
+    pub websocket_id: String,
+    pub websocket_url: String,
+    pub websocket_authorized_feature: String,
+    #[serde(default, skip_serializing)]
+    secret: Option<SecretString>,
+    /// Seconds the server asks clients to wait before reconnecting; the
+    /// listener never waits less than this between attempts.
+    #[serde(default)]
+    pub reconnect_delay: Option<u64>,
+    /// The account's default API version, used for event payloads unless an
+    /// endpoint pins another.
+    #[serde(default)]
+    pub default_version: Option<String>,
+    /// The newest API version, for warning about outdated pins.
+    #[serde(default)]
+    pub latest_version: Option<String>,
+    /// Set when the account has Connect filters the CLI would warn about.
+    #[serde(default)]
+    pub display_connect_filter_warning: bool,
+    /// Fields not modelled above, e.g. ones added after this crate.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Session {
+    /// The `whsec_...` secret Stripe signs this session's deliveries with,
+    /// for configuring local signature verification.
+    pub fn signing_secret(&self) -> Option<&str> {
+        self.secret.as_ref().map(SecretString::expose)
+    }
+
+    /// reconnect_delay as a Duration; None when absent or zero.
+    pub fn server_reconnect_delay(&self) -> Option<Duration> {
+        self.reconnect_delay.filter(|s| *s > 0).map(Duration::from_secs)
+    }
+}
+
+/// A client-to-server message, for DevProxyClient::send and
+/// ListenerHandle::send_message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutgoingMessage {
+    /// Acknowledges an event so it is not redelivered. Encoded by the
+    /// connection's AckFormat; `webhook_id` is a v2 event's
+    /// `destination_id`, and `webhook_conversation_id` is empty for v2.
+    EventAck {
+        event_id: String,
+        webhook_id: String,
+        webhook_conversation_id: String,
+    },
+    /// Reports how a local endpoint answered a forwarded delivery, as
+    /// `stripe listen` does so the Dashboard shows the response.
+    WebhookResponse(WebhookResponse),
+    /// Any JSON message, sent as is: for protocol messages this crate does
+    /// not model yet.
+    Raw(serde_json::Value),
+}
+
+/// The `webhook_response` message: the outcome of delivering a WebhookEvent
+/// to `forward_url`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct WebhookResponse {
+    pub webhook_id: String,
+    pub webhook_conversation_id: String,
+    pub forward_url: String,
+    pub status: u16,
+    /// Response headers.
+    pub http_headers: std::collections::HashMap<String, String>,
+    /// Response body.
+    pub body: String,
+    pub request_headers: std::collections::HashMap<String, String>,
+    pub request_body: String,
+}
+
+impl OutgoingMessage {
+    /// The message as sent on the stripecli-devproxy-v1 subprotocol.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            OutgoingMessage::EventAck {
+                event_id,
+                webhook_id,
+                webhook_conversation_id,
+            } => serde_json::json!({
+                "type": "event_ack",
+                "event_id": event_id,
+                "webhook_conversation_id": webhook_conversation_id,
+                "webhook_id": webhook_id,
+            }),
+            OutgoingMessage::WebhookResponse(response) => {
+                let mut value = serde_json::to_value(response).unwrap_or_default();
+                value["type"] = "webhook_response".into();
+                value
+            }
+            OutgoingMessage::Raw(value) => value.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IncomingMessage {
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    #[serde(flatten)]
+    pub data: serde_json::Value,
+}
+
+/// An `error` message from the devproxy, e.g. for an unknown webhook
+/// endpoint or a feature the session is not authorized for.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ServerError {
+    /// Machine-readable code such as `feature_not_authorized`, if sent.
+    pub code: Option<String>,
+    pub message: String,
+    /// The frame's other fields.
+    pub extra: serde_json::Value,
+}
+
+impl ServerError {
+    /// Builds the error from a frame's fields (everything but `type`).
+    /// Accepts `code`/`message` at the top level or inside an `error`
+    /// object, and a bare `error` string.
+    pub fn from_data(data: serde_json::Value) -> ServerError {
+        let serde_json::Value::Object(mut fields) = data else {
+            return ServerError {
+                code: None,
+                message: data.to_string(),
+                extra: serde_json::Value::Object(Default::default()),
+            };
+        };
+        let (code, message) = match fields.remove("error") {
+            Some(serde_json::Value::Object(mut inner)) => (take(&mut inner, "code"), take(&mut inner, "message")),
+            Some(serde_json::Value::String(s)) => (None, Some(s)),
+            _ => (None, None),
+        };
+        let code = code.or_else(|| take(&mut fields, "code"));
+        let message = message.or_else(|| take(&mut fields, "message")).unwrap_or_else(|| "unspecified server error".to_string());
+        ServerError {
+            code,
+            message,
+            extra: serde_json::Value::Object(fields),
+        }
+    }
+
+    /// Whether the connection cannot usefully continue: the session lacks
+    /// authorization or is no longer valid. ReconnectPolicy::is_fatal
+    /// defaults to this.
+    pub fn is_fatal(&self) -> bool {
+        self.is_unauthorized() || self.is_session_invalid()
+    }
+
+    /// The API key or session is not authorized for the feature.
+    pub fn is_unauthorized(&self) -> bool {
+        matches!(self.code.as_deref(), Some("feature_not_authorized" | "unauthorized" | "permission_denied"))
+    }
+
+    /// The session expired or is unknown; authorizing again may help.
+    pub fn is_session_invalid(&self) -> bool {
+        matches!(self.code.as_deref(), Some("session_expired" | "invalid_session" | "unknown_session"))
+    }
+}
+
+// Removes a field as a string; non-string values keep their JSON form.
+fn take(fields: &mut serde_json::Map<String, serde_json::Value>, name: &str) -> Option<String> {
+    match fields.remove(name) {
+        Some(serde_json::Value::String(s)) => Some(s),
+        Some(serde_json::Value::Null) | None => None,
+        Some(other) => Some(other.to_string()),
+    }
+}
+
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "{}: {}", code, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookEvent {
+    pub webhook_id: String,
+    pub webhook_conversation_id: String,
+    pub event_payload: String,
+    /// Headers Stripe would have sent to the endpoint, including Stripe-Signature.
+    #[serde(default)]
+    pub http_headers: std::collections::HashMap<String, String>,
+    /// The endpoint this delivery was generated for.
+    #[serde(default)]
+    pub endpoint: Option<WebhookEndpoint>,
+    #[serde(flatten)]
+    pub extra: serde_json::Value,
+}
+
+impl WebhookEvent {
+    /// The `t=` of the delivery's Stripe-Signature header: when Stripe
+    /// signed it, in Unix seconds.
+    pub fn signed_at(&self) -> Option<i64> {
+        let (_, header) = self.http_headers.iter().find(|(name, _)| name.eq_ignore_ascii_case("stripe-signature"))?;
+        header.split(',').find_map(|part| part.trim().strip_prefix("t=")?.parse().ok())
+    }
+}
+
+#[cfg(feature = "stripe-types")]
+impl WebhookEvent {
+    /// Deserializes the payload into async-stripe's `Event`, with
+    /// `data.object` as the matching typed model (PaymentIntent, Invoice, ...).
+    /// The payload's API version should match the one async-stripe targets.
+    pub fn into_stripe_event(self) -> Result<stripe::Event> {
+        serde_json::from_str(&self.event_payload).map_err(|e| Error::Other(format!("stripe event {}: {}", self.webhook_id, e)))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookEndpoint {
+    pub url: String,
+    #[serde(default)]
+    pub api_version: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct V2Event {
+    /// The event destination (`ed_...`) this delivery was generated for;
+    /// see StripeListener::event_destination.
+    pub destination_id: String,
+    pub payload: String,
+    #[serde(flatten)]
+    pub extra: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StripeEventPayload {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event_type: EventType,
+    pub created: u64,
+    pub livemode: bool,
+    /// Connected account (`acct_...`) the event happened on, for Connect
+    /// events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// API request (and idempotency key) that triggered the event.
+    #[serde(default, deserialize_with = "schema::de_request", skip_serializing_if = "Option::is_none")]
+    pub request: Option<EventRequest>,
+}
+
+impl StripeEventPayload {
+    pub fn request_id(&self) -> Option<&str> {
+        self.request.as_ref().and_then(|r| r.id.as_deref())
+    }
+
+    pub fn idempotency_key(&self) -> Option<&str> {
+        self.request.as_ref().and_then(|r| r.idempotency_key.as_deref())
+    }
+}
+
+/// The thin event envelope delivered to event destinations. Only `id` and
+/// `type` are required; the rest default when a payload omits them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct V2EventPayload {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    /// Creation time as RFC 3339, e.g. `2024-09-17T06:20:52.246Z`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    #[serde(default)]
+    pub livemode: bool,
+    /// Account (`acct_...`) the event happened on, for Connect events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub related_object: Option<RelatedObject>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<V2EventReason>,
+    /// Event-specific details some types carry inline.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+impl V2EventPayload {
+    pub fn kind(&self) -> V2EventType {
+        V2EventType::from(self.event_type.as_str())
+    }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.reason.as_ref()?.request.as_ref()?.id.as_deref()
+    }
+
+    pub fn idempotency_key(&self) -> Option<&str> {
+        self.reason.as_ref()?.request.as_ref()?.idempotency_key.as_deref()
+    }
+}
+
+/// The object a thin event is about. Fetch its current state from `url`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RelatedObject {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub object_type: String,
+    pub url: String,
+}
+
+/// Why a thin event happened; `request` is set when `reason_type` is
+/// `"request"`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct V2EventReason {
+    #[serde(rename = "type")]
+    pub reason_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request: Option<EventRequest>,
+}
+
+/// Known thin event types. Others, including families added after this
+/// release, parse as Other.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum V2EventType {
+    /// `v1.billing.meter.error_report_triggered`
+    BillingMeterErrorReportTriggered,
+    /// `v1.billing.meter.no_meter_found`
+    BillingMeterNoMeterFound,
+    /// `v2.core.event_destination.ping`
+    EventDestinationPing,
+    /// `v2.core.account.created`
+    AccountCreated,
+    /// `v2.core.account.updated`
+    AccountUpdated,
+    /// `v2.core.account.closed`
+    AccountClosed,
+    Other(String),
+}
+
+impl V2EventType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            V2EventType::BillingMeterErrorReportTriggered => "v1.billing.meter.error_report_triggered",
+            V2EventType::BillingMeterNoMeterFound => "v1.billing.meter.no_meter_found",
+            V2EventType::EventDestinationPing => "v2.core.event_destination.ping",
+            V2EventType::AccountCreated => "v2.core.account.created",
+            V2EventType::AccountUpdated => "v2.core.account.updated",
+            V2EventType::AccountClosed => "v2.core.account.closed",
+            V2EventType::Other(name) => name,
+        }
+    }
+
+    /// The type without its `v1.`/`v2.` prefix and final action, e.g.
+    /// `billing.meter` or `core.account`.
+    pub fn family(&self) -> &str {
+        let name = self.as_str();
+        let name = name.split_once('.').map_or(name, |(_, rest)| rest);
+        name.rsplit_once('.').map_or(name, |(family, _)| family)
+    }
+}
+
+impl From<&str> for V2EventType {
+    fn from(name: &str) -> Self {
+        match name {
+            "v1.billing.meter.error_report_triggered" => V2EventType::BillingMeterErrorReportTriggered,
+            "v1.billing.meter.no_meter_found" => V2EventType::BillingMeterNoMeterFound,
+            "v2.core.event_destination.ping" => V2EventType::EventDestinationPing,
+            "v2.core.account.created" => V2EventType::AccountCreated,
+            "v2.core.account.updated" => V2EventType::AccountUpdated,
+            "v2.core.account.closed" => V2EventType::AccountClosed,
+            other => V2EventType::Other(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for V2EventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for V2EventType {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        s.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for V2EventType {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(d).map(|s| V2EventType::from(s.as_str()))
+    }
+}
+
+/// An event destination's configuration, from
+/// `GET /v2/core/event_destinations/{id}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EventDestination {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// `enabled` or `disabled`.
+    #[serde(default)]
+    pub status: Option<String>,
+    /// `webhook_endpoint` or `amazon_eventbridge`.
+    #[serde(rename = "type", default)]
+    pub destination_type: Option<String>,
+    /// `thin` or `snapshot`.
+    #[serde(default)]
+    pub event_payload: Option<String>,
+    #[serde(default)]
+    pub enabled_events: Vec<String>,
+    #[serde(default)]
+    pub livemode: bool,
+    /// Everything else, e.g. `webhook_endpoint` or `events_from`.
+    #[serde(flatten)]
+    pub extra: serde_json::Value,
+}
+
+impl EventDestination {
+    /// Whether the destination subscribes to `event_type`, honoring `"*"`.
+    pub fn is_enabled_for(&self, event_type: &str) -> bool {
+        self.enabled_events.iter().any(|e| e == "*" || e == event_type)
+    }
+}
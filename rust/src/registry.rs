@@ -0,0 +1,68 @@
+// Handlers for devproxy message types the crate does not model natively.
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+type Erased = dyn Fn(Value) -> std::result::Result<(), String> + Send + Sync;
+
+/// Maps message `type` names to a deserializer and handler. Registered types
+/// are dispatched here instead of EventHandler::on_unknown_message; built-in
+/// types (`webhook_event`, `v2_event`, `error`) cannot be overridden.
+#[derive(Clone, Default)]
+pub struct MessageRegistry {
+    entries: HashMap<String, Arc<Erased>>,
+}
+
+impl MessageRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a handler for `type_name`, deserializing the message body
+    /// (every field except `type`) into `T` with serde.
+    pub fn register<T, H>(&mut self, type_name: impl Into<String>, handler: H) -> &mut Self
+    where
+        T: DeserializeOwned + 'static,
+        H: Fn(T) + Send + Sync + 'static,
+    {
+        self.register_with(
+            type_name,
+            |data: Value| serde_json::from_value::<T>(data).map_err(|e| e.to_string()),
+            handler,
+        )
+    }
+
+    /// Registers a handler with a custom deserializer.
+    pub fn register_with<T, D, H>(&mut self, type_name: impl Into<String>, deserialize: D, handler: H) -> &mut Self
+    where
+        T: 'static,
+        D: Fn(Value) -> std::result::Result<T, String> + Send + Sync + 'static,
+        H: Fn(T) + Send + Sync + 'static,
+    {
+        let erased = move |data: Value| deserialize(data).map(&handler);
+        self.entries.insert(type_name.into(), Arc::new(erased));
+        self
+    }
+
+    pub fn unregister(&mut self, type_name: &str) -> bool {
+        self.entries.remove(type_name).is_some()
+    }
+
+    pub fn contains(&self, type_name: &str) -> bool {
+        self.entries.contains_key(type_name)
+    }
+
+    // None when the type is not registered; Some(Err) when deserialization failed.
+    pub(crate) fn dispatch(&self, type_name: &str, data: Value) -> Option<std::result::Result<(), String>> {
+        self.entries.get(type_name).map(|f| f(data))
+    }
+}
+
+impl fmt::Debug for MessageRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.entries.keys()).finish()
+    }
+}
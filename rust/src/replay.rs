@@ -0,0 +1,470 @@
+// Recording of delivered events to JSONL and replay of such files through
+// the dispatch pipeline, to reproduce production event sequences locally.
+// NdjsonInput does the same for a live stream such as stdin.
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use crate::dispatch::Dispatcher;
+#[cfg(feature = "forwarder")]
+use crate::forward::{Forwarder, RecentDeliveries};
+use crate::sampling::Sampler;
+use crate::stats::StatsRecorder;
+use crate::transport::InFlight;
+use crate::*;
+
+/// One line of a recording: a webhook or v2 event and when it arrived.
+/// JsonlDeadLetter lines have the same `event` key and replay as well.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Recording {
+    /// Unix milliseconds at which the listener received the event. Without
+    /// it, replay spaces events by their `created` timestamps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<WebhookEvent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub v2_event: Option<V2Event>,
+}
+
+impl Recording {
+    /// Parses one line: a Recording, or a bare event object as returned by
+    /// the API (`"object": "event"` or `"v2.core.event"`).
+    pub fn from_line(line: &str) -> serde_json::Result<Recording> {
+        #[derive(Deserialize)]
+        struct Object<'a> {
+            #[serde(borrow)]
+            object: Option<&'a str>,
+        }
+        match serde_json::from_str::<Object>(line)?.object {
+            Some("event") => Ok(Recording {
+                event: Some(WebhookEvent {
+                    webhook_id: String::new(),
+                    webhook_conversation_id: String::new(),
+                    event_payload: line.to_string(),
+                    http_headers: Default::default(),
+                    endpoint: None,
+                    extra: serde_json::Value::Object(Default::default()),
+                }),
+                ..Default::default()
+            }),
+            Some("v2.core.event") => Ok(Recording {
+                v2_event: Some(V2Event {
+                    destination_id: String::new(),
+                    payload: line.to_string(),
+                    extra: serde_json::Value::Object(Default::default()),
+                }),
+                ..Default::default()
+            }),
+            _ => serde_json::from_str(line),
+        }
+    }
+}
+
+/// EventHandler wrapper that appends every webhook and v2 event as a JSONL
+/// line before passing it on, for later use with Replayer or NdjsonInput.
+pub struct JsonlRecorder {
+    inner: Arc<dyn EventHandler>,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonlRecorder {
+    pub fn open(path: impl AsRef<Path>, inner: Arc<dyn EventHandler>) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(file, inner))
+    }
+
+    /// Records to any writer, e.g. stdout for a shell pipeline. Each line
+    /// is flushed as it is written.
+    pub fn new(out: impl Write + Send + 'static, inner: Arc<dyn EventHandler>) -> Self {
+        Self {
+            inner,
+            out: Mutex::new(Box::new(out)),
+        }
+    }
+
+    fn record(&self, recording: &Recording) {
+        let Ok(line) = serde_json::to_string(recording) else { return };
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = writeln!(out, "{}", line);
+        let _ = out.flush();
+    }
+}
+
+fn unix_millis(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default()
+}
+
+impl EventHandler for JsonlRecorder {
+    fn on_webhook_event(&self, evt: WebhookEvent, parsed: StripeEventPayload) {
+        self.record(&Recording {
+            received_at: Some(unix_millis(SystemTime::now())),
+            event: Some(evt.clone()),
+            ..Default::default()
+        });
+        self.inner.on_webhook_event(evt, parsed);
+    }
+
+    fn on_webhook_batch(&self, batch: Vec<(WebhookEvent, StripeEventPayload)>) -> bool {
+        let received_at = unix_millis(SystemTime::now());
+        for (evt, _) in &batch {
+            self.record(&Recording {
+                received_at: Some(received_at),
+                event: Some(evt.clone()),
+                ..Default::default()
+            });
+        }
+        self.inner.on_webhook_batch(batch)
+    }
+
+    fn on_v2_event(&self, evt: V2Event, parsed: V2EventPayload) {
+        self.record(&Recording {
+            received_at: Some(unix_millis(SystemTime::now())),
+            v2_event: Some(evt.clone()),
+            ..Default::default()
+        });
+        self.inner.on_v2_event(evt, parsed);
+    }
+
+    fn on_unknown_message(&self, raw_type: String, data: serde_json::Value) {
+        self.inner.on_unknown_message(raw_type, data);
+    }
+
+    fn on_tenant_webhook_event(&self, tenant: &Tenant, evt: WebhookEvent, parsed: StripeEventPayload) {
+        self.record(&Recording {
+            received_at: Some(unix_millis(SystemTime::now())),
+            event: Some(evt.clone()),
+            ..Default::default()
+        });
+        self.inner.on_tenant_webhook_event(tenant, evt, parsed);
+    }
+
+    fn on_stale_event(&self, evt: WebhookEvent, parsed: StripeEventPayload, age: Duration) {
+        self.record(&Recording {
+            received_at: Some(unix_millis(SystemTime::now())),
+            event: Some(evt.clone()),
+            ..Default::default()
+        });
+        self.inner.on_stale_event(evt, parsed, age);
+    }
+
+    fn on_raw_webhook_event(&self, evt: WebhookEvent) {
+        self.record(&Recording {
+            received_at: Some(unix_millis(SystemTime::now())),
+            event: Some(evt.clone()),
+            ..Default::default()
+        });
+        self.inner.on_raw_webhook_event(evt);
+    }
+
+    fn on_forward_result(&self, result: &ForwardResult) {
+        self.inner.on_forward_result(result);
+    }
+
+    fn on_schema_drift(&self, drift: &SchemaDrift) {
+        self.inner.on_schema_drift(drift);
+    }
+
+    fn on_ack_sent(&self, event_id: &str, conversation_id: &str, timestamp: SystemTime) {
+        self.inner.on_ack_sent(event_id, conversation_id, timestamp);
+    }
+
+    fn on_ack_failed(&self, event_id: &str, conversation_id: &str, error: &Error) {
+        self.inner.on_ack_failed(event_id, conversation_id, error);
+    }
+
+    fn on_handler_panic(&self, panic: &HandlerPanic) {
+        self.inner.on_handler_panic(panic);
+    }
+
+    fn on_server_error(&self, error: &ServerError) {
+        self.inner.on_server_error(error);
+    }
+
+    fn on_shadow_discrepancy(&self, discrepancy: &ShadowDiscrepancy) {
+        self.inner.on_shadow_discrepancy(discrepancy);
+    }
+
+    fn on_ping_received(&self, payload: &[u8]) {
+        self.inner.on_ping_received(payload);
+    }
+
+    fn on_pong_received(&self, payload: &[u8], rtt: Option<Duration>) {
+        self.inner.on_pong_received(payload, rtt);
+    }
+}
+
+/// Replays a recording through the same pipeline as live events: filters,
+/// sampling, transforms, forward routes and finally the handler. No acks
+/// are sent and the cursor is left alone.
+pub struct Replayer {
+    path: PathBuf,
+    recordings: Vec<Recording>,
+    // None replays without waiting between events.
+    speed: Option<f64>,
+    config: Option<Config>,
+    #[cfg(all(feature = "forwarder", feature = "types"))]
+    signing_secret: Option<SecretString>,
+}
+
+impl Replayer {
+    pub fn from_file(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let err = |e: &dyn std::fmt::Display| Error::Other(format!("replay {}: {}", path.display(), e));
+        let file = std::fs::File::open(&path).map_err(|e| err(&e))?;
+        let mut recordings = Vec::new();
+        for (n, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| err(&e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let recording = Recording::from_line(&line).map_err(|e| err(&format!("line {}: {}", n + 1, e)))?;
+            recordings.push(recording);
+        }
+        Ok(Self {
+            path,
+            recordings,
+            speed: Some(1.0),
+            config: None,
+            #[cfg(all(feature = "forwarder", feature = "types"))]
+            signing_secret: None,
+        })
+    }
+
+    /// Playback speed relative to the recording; 2.0 halves every gap.
+    /// Speeds that are not positive replay as fast as possible.
+    pub fn speed(mut self, speed: f64) -> Self {
+        self.speed = (speed.is_finite() && speed > 0.0).then_some(speed);
+        self
+    }
+
+    /// Replays without waiting between events.
+    pub fn as_fast_as_possible(mut self) -> Self {
+        self.speed = None;
+        self
+    }
+
+    /// Runs events through this configuration's filters, sampling,
+    /// transforms and forward routes. Its handler is replaced by the one
+    /// passed to run().
+    pub fn config(mut self, cfg: Config) -> Self {
+        self.config = Some(cfg);
+        self
+    }
+
+    /// `whsec_...` secret to re-sign transformed events with before
+    /// forwarding them, as a live session's signing secret would. Without
+    /// one they are forwarded without a Stripe-Signature.
+    #[cfg(all(feature = "forwarder", feature = "types"))]
+    pub fn signing_secret(mut self, secret: impl Into<SecretString>) -> Self {
+        self.signing_secret = Some(secret.into());
+        self
+    }
+
+    /// Number of events in the recording.
+    pub fn len(&self) -> usize {
+        self.recordings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.recordings.is_empty()
+    }
+
+    /// Dispatches every recorded event in order and waits for the forwards
+    /// they started. Events whose payload does not parse are logged and
+    /// skipped.
+    pub async fn run(self, handler: Arc<dyn EventHandler>) -> Result<()> {
+        let cfg = replay_config(self.config, handler);
+        let clock = cfg.clock.clone().unwrap();
+        let logger = cfg.logger.clone().unwrap();
+        let inflight = InFlight::default();
+        #[allow(unused_mut)]
+        let mut dispatcher = replay_dispatcher(&cfg, &inflight)?;
+        #[cfg(all(feature = "forwarder", feature = "types"))]
+        {
+            dispatcher.forwarder.session_secret = self.signing_secret.clone();
+            dispatcher.warn_unsigned();
+        }
+        logger.log(LogLevel::Info, "replay started", &[("path", &self.path.display()), ("events", &self.recordings.len())]);
+
+        let mut previous: Option<u64> = None;
+        for recording in self.recordings {
+            let Some((at, replayed)) = Replayed::parse(recording, logger.as_ref()) else { continue };
+            // V2 payloads have no timestamp of their own.
+            let at = at.or(previous).unwrap_or_default();
+            if let (Some(speed), Some(previous)) = (self.speed, previous) {
+                let gap = Duration::from_millis(at.saturating_sub(previous)).div_f64(speed);
+                if !gap.is_zero() {
+                    clock.sleep(gap).await;
+                }
+            }
+            previous = Some(at);
+            replayed.dispatch(&dispatcher);
+        }
+
+        inflight.idle().await;
+        Ok(())
+    }
+}
+
+/// Dispatches events read line by line from a stream such as stdin, each as
+/// soon as its line arrives, so the listener can sit in a shell pipeline.
+/// Lines are Recordings (as written by JsonlRecorder) or bare event
+/// objects; see Recording::from_line. Like Replayer, no acks are sent.
+pub struct NdjsonInput<R> {
+    reader: R,
+    config: Option<Config>,
+    #[cfg(all(feature = "forwarder", feature = "types"))]
+    signing_secret: Option<SecretString>,
+}
+
+impl<R: AsyncBufRead + Unpin> NdjsonInput<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            config: None,
+            #[cfg(all(feature = "forwarder", feature = "types"))]
+            signing_secret: None,
+        }
+    }
+
+    /// Runs events through this configuration's filters, sampling,
+    /// transforms and forward routes. Its handler is replaced by the one
+    /// passed to run().
+    pub fn config(mut self, cfg: Config) -> Self {
+        self.config = Some(cfg);
+        self
+    }
+
+    /// `whsec_...` secret to re-sign transformed events with before
+    /// forwarding them, as a live session's signing secret would. Without
+    /// one they are forwarded without a Stripe-Signature.
+    #[cfg(all(feature = "forwarder", feature = "types"))]
+    pub fn signing_secret(mut self, secret: impl Into<SecretString>) -> Self {
+        self.signing_secret = Some(secret.into());
+        self
+    }
+
+    /// Reads until end of input, then waits for the forwards started.
+    /// Lines that do not parse are logged and skipped.
+    pub async fn run(self, handler: Arc<dyn EventHandler>) -> Result<()> {
+        let cfg = replay_config(self.config, handler);
+        let logger = cfg.logger.clone().unwrap();
+        let inflight = InFlight::default();
+        #[allow(unused_mut)]
+        let mut dispatcher = replay_dispatcher(&cfg, &inflight)?;
+        #[cfg(all(feature = "forwarder", feature = "types"))]
+        {
+            dispatcher.forwarder.session_secret = self.signing_secret.clone();
+            dispatcher.warn_unsigned();
+        }
+
+        let mut lines = self.reader.lines();
+        let mut n = 0usize;
+        while let Some(line) = lines.next_line().await.map_err(|e| Error::Other(format!("ndjson input: {}", e)))? {
+            n += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let recording = match Recording::from_line(&line) {
+                Ok(r) => r,
+                Err(e) => {
+                    logger.log(LogLevel::Warn, "could not parse input line", &[("line", &n), ("error", &e)]);
+                    continue;
+                }
+            };
+            if let Some((_, replayed)) = Replayed::parse(recording, logger.as_ref()) {
+                replayed.dispatch(&dispatcher);
+            }
+        }
+
+        inflight.idle().await;
+        Ok(())
+    }
+}
+
+fn replay_config(cfg: Option<Config>, handler: Arc<dyn EventHandler>) -> Config {
+    let mut cfg = cfg.unwrap_or_else(|| Config::new("", Arc::new(NopHandler)));
+    cfg.handler = handler;
+    cfg.defaults();
+    cfg
+}
+
+// Everything run() does with a live event except acks and the cursor.
+#[cfg_attr(not(feature = "forwarder"), allow(unused_variables))]
+fn replay_dispatcher(cfg: &Config, inflight: &InFlight) -> Result<Dispatcher> {
+    let clock = cfg.clock.clone().unwrap();
+    let live = LiveConfig::from_config(cfg);
+    cfg.check(&live)?;
+    Ok(Dispatcher {
+        handler: cfg.handler.clone(),
+        logger: cfg.logger.clone().unwrap(),
+        stats: StatsRecorder::new(clock.clone()),
+        cursor: None,
+        live: ConfigHandle::new(live),
+        transform: cfg.transform.clone(),
+        predicate: cfg.predicate.clone(),
+        tenants: cfg.tenant_resolver.clone(),
+        #[cfg(feature = "forwarder")]
+        forwarder: Forwarder::new(cfg.tls.as_ref().unwrap(), clock.clone(), RecentDeliveries::new(0))?,
+        #[cfg(feature = "forwarder")]
+        dead_letter: cfg.dead_letter.clone(),
+        strict_parse: cfg.strict_parse.unwrap_or(false),
+        // Replayed events are old by design.
+        max_event_age: None,
+        clock: clock.clone(),
+        #[cfg(feature = "forwarder")]
+        inflight: inflight.clone(),
+        sampler: Sampler::new(clock),
+        batching: None,
+        #[cfg(feature = "tower")]
+        service: None,
+        connection_id: None,
+        pool: None,
+    })
+}
+
+enum Replayed {
+    Webhook(WebhookEvent, StripeEventPayload),
+    V2(V2Event, V2EventPayload),
+}
+
+impl Replayed {
+    // Parses the recorded payload, with the event's time in unix ms if it
+    // has one. None, after logging, if the payload does not parse.
+    fn parse(recording: Recording, logger: &dyn Logger) -> Option<(Option<u64>, Replayed)> {
+        if let Some(evt) = recording.event {
+            let parsed: StripeEventPayload = match serde_json::from_str(&evt.event_payload) {
+                Ok(p) => p,
+                Err(e) => {
+                    logger.log(LogLevel::Warn, "could not parse recorded event_payload", &[("webhook_id", &evt.webhook_id), ("error", &e)]);
+                    return None;
+                }
+            };
+            let at = recording.received_at.unwrap_or(parsed.created.saturating_mul(1000));
+            Some((Some(at), Replayed::Webhook(evt, parsed)))
+        } else if let Some(evt) = recording.v2_event {
+            let parsed: V2EventPayload = match serde_json::from_str(&evt.payload) {
+                Ok(p) => p,
+                Err(e) => {
+                    logger.log(LogLevel::Warn, "could not parse recorded v2 payload", &[("destination_id", &evt.destination_id), ("error", &e)]);
+                    return None;
+                }
+            };
+            Some((recording.received_at, Replayed::V2(evt, parsed)))
+        } else {
+            None
+        }
+    }
+
+    fn dispatch(self, dispatcher: &Dispatcher) {
+        match self {
+            Replayed::Webhook(evt, parsed) => dispatcher.webhook(evt, parsed),
+            Replayed::V2(evt, parsed) => dispatcher.v2(evt, parsed),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+// Per-event-type dispatch to several EventHandlers, so each handler only
+// sees the events it registered for.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    Error, EventHandler, ForwardResult, HandlerPanic, SchemaDrift, ServerError, ShadowDiscrepancy, StripeEventPayload, Tenant, V2Event, V2EventPayload,
+    WebhookEvent,
+};
+
+/// Identifies a registration so it can be removed with Router::remove.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RouteId(u64);
+
+type WebhookBatch = Vec<(WebhookEvent, StripeEventPayload)>;
+
+struct Route {
+    id: RouteId,
+    pattern: String,
+    priority: i32,
+    handler: Arc<dyn EventHandler>,
+}
+
+/// An EventHandler that passes each webhook and v2 event to the first
+/// registered handler whose pattern matches its type, or to the fallback.
+///
+/// Patterns are event types where `*` matches any run of characters:
+/// `"invoice.*"`, `"*.succeeded"`, `"*"`. Routes are tried by descending
+/// priority, then in registration order. Routes can be added and removed
+/// while the listener runs, including from inside a handler.
+///
+/// Callbacks that are not tied to an event type (forward results, acks,
+/// panics, pings, unknown messages, raw payloads) go to the fallback only.
+#[derive(Default)]
+pub struct Router {
+    routes: RwLock<Vec<Route>>,
+    fallback: RwLock<Option<Arc<dyn EventHandler>>>,
+    next_id: AtomicU64,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Routes events matching `pattern` to `handler` with priority 0. To
+    /// share one handler between routes, use on_with_priority with an Arc.
+    pub fn on(&self, pattern: impl Into<String>, handler: impl EventHandler + 'static) -> RouteId {
+        self.on_with_priority(pattern, 0, Arc::new(handler))
+    }
+
+    /// Routes events matching `pattern` to `handler`. Higher priorities are
+    /// tried first; equal priorities keep registration order.
+    pub fn on_with_priority(&self, pattern: impl Into<String>, priority: i32, handler: Arc<dyn EventHandler>) -> RouteId {
+        let id = RouteId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut routes = self.routes.write().unwrap_or_else(|e| e.into_inner());
+        let at = routes.iter().position(|r| r.priority < priority).unwrap_or(routes.len());
+        routes.insert(
+            at,
+            Route {
+                id,
+                pattern: pattern.into(),
+                priority,
+                handler,
+            },
+        );
+        id
+    }
+
+    /// Removes a route; false if it was already removed.
+    pub fn remove(&self, id: RouteId) -> bool {
+        let mut routes = self.routes.write().unwrap_or_else(|e| e.into_inner());
+        let before = routes.len();
+        routes.retain(|r| r.id != id);
+        routes.len() != before
+    }
+
+    /// Sets the handler for events no route matches, returning the previous
+    /// one. Without a fallback such events are dropped.
+    pub fn fallback(&self, handler: Arc<dyn EventHandler>) -> Option<Arc<dyn EventHandler>> {
+        self.fallback.write().unwrap_or_else(|e| e.into_inner()).replace(handler)
+    }
+
+    pub fn clear_fallback(&self) -> Option<Arc<dyn EventHandler>> {
+        self.fallback.write().unwrap_or_else(|e| e.into_inner()).take()
+    }
+
+    /// The registered patterns, in the order they are tried.
+    pub fn patterns(&self) -> Vec<(RouteId, String)> {
+        let routes = self.routes.read().unwrap_or_else(|e| e.into_inner());
+        routes.iter().map(|r| (r.id, r.pattern.clone())).collect()
+    }
+
+    // Cloned out of the lock so handlers may change the routes.
+    fn handler_for(&self, event_type: &str) -> Option<Arc<dyn EventHandler>> {
+        let routes = self.routes.read().unwrap_or_else(|e| e.into_inner());
+        match routes.iter().find(|r| glob_matches(&r.pattern, event_type)) {
+            Some(route) => Some(route.handler.clone()),
+            None => self.fallback_handler(),
+        }
+    }
+
+    fn fallback_handler(&self) -> Option<Arc<dyn EventHandler>> {
+        self.fallback.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+// `*` matches any run of characters, including dots.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else { return false };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else { return rest.is_empty() };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+impl EventHandler for Router {
+    fn on_webhook_event(&self, evt: WebhookEvent, parsed: StripeEventPayload) {
+        if let Some(handler) = self.handler_for(parsed.event_type.as_str()) {
+            handler.on_webhook_event(evt, parsed);
+        }
+    }
+
+    fn on_tenant_webhook_event(&self, tenant: &Tenant, evt: WebhookEvent, parsed: StripeEventPayload) {
+        if let Some(handler) = self.handler_for(parsed.event_type.as_str()) {
+            handler.on_tenant_webhook_event(tenant, evt, parsed);
+        }
+    }
+
+    // Each handler gets the events routed to it as one batch, in order; the
+    // batch is accepted only if all of them accept.
+    fn on_webhook_batch(&self, batch: Vec<(WebhookEvent, StripeEventPayload)>) -> bool {
+        let mut groups: Vec<(Arc<dyn EventHandler>, WebhookBatch)> = Vec::new();
+        for (evt, parsed) in batch {
+            let Some(handler) = self.handler_for(parsed.event_type.as_str()) else { continue };
+            match groups.iter_mut().find(|(h, _)| Arc::ptr_eq(h, &handler)) {
+                Some((_, events)) => events.push((evt, parsed)),
+                None => groups.push((handler, vec![(evt, parsed)])),
+            }
+        }
+        groups.into_iter().fold(true, |accepted, (handler, events)| handler.on_webhook_batch(events) && accepted)
+    }
+
+    fn on_v2_event(&self, evt: V2Event, parsed: V2EventPayload) {
+        if let Some(handler) = self.handler_for(&parsed.event_type) {
+            handler.on_v2_event(evt, parsed);
+        }
+    }
+
+    fn on_unknown_message(&self, raw_type: String, data: serde_json::Value) {
+        if let Some(handler) = self.fallback_handler() {
+            handler.on_unknown_message(raw_type, data);
+        }
+    }
+
+    fn on_stale_event(&self, evt: WebhookEvent, parsed: StripeEventPayload, age: Duration) {
+        if let Some(handler) = self.handler_for(parsed.event_type.as_str()) {
+            handler.on_stale_event(evt, parsed, age);
+        }
+    }
+
+    fn on_raw_webhook_event(&self, evt: WebhookEvent) {
+        if let Some(handler) = self.fallback_handler() {
+            handler.on_raw_webhook_event(evt);
+        }
+    }
+
+    fn on_forward_result(&self, result: &ForwardResult) {
+        if let Some(handler) = self.fallback_handler() {
+            handler.on_forward_result(result);
+        }
+    }
+
+    fn on_schema_drift(&self, drift: &SchemaDrift) {
+        if let Some(handler) = self.fallback_handler() {
+            handler.on_schema_drift(drift);
+        }
+    }
+
+    fn on_ack_sent(&self, event_id: &str, conversation_id: &str, timestamp: SystemTime) {
+        if let Some(handler) = self.fallback_handler() {
+            handler.on_ack_sent(event_id, conversation_id, timestamp);
+        }
+    }
+
+    fn on_ack_failed(&self, event_id: &str, conversation_id: &str, error: &Error) {
+        if let Some(handler) = self.fallback_handler() {
+            handler.on_ack_failed(event_id, conversation_id, error);
+        }
+    }
+
+    fn on_handler_panic(&self, panic: &HandlerPanic) {
+        if let Some(handler) = self.fallback_handler() {
+            handler.on_handler_panic(panic);
+        }
+    }
+
+    fn on_server_error(&self, error: &ServerError) {
+        if let Some(handler) = self.fallback_handler() {
+            handler.on_server_error(error);
+        }
+    }
+
+    fn on_shadow_discrepancy(&self, discrepancy: &ShadowDiscrepancy) {
+        if let Some(handler) = self.fallback_handler() {
+            handler.on_shadow_discrepancy(discrepancy);
+        }
+    }
+
+    fn on_ping_received(&self, payload: &[u8]) {
+        if let Some(handler) = self.fallback_handler() {
+            handler.on_ping_received(payload);
+        }
+    }
+
+    fn on_pong_received(&self, payload: &[u8], rtt: Option<Duration>) {
+        if let Some(handler) = self.fallback_handler() {
+            handler.on_pong_received(payload, rtt);
+        }
+    }
+}
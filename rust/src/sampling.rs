@@ -0,0 +1,84 @@
+// Event sampling for debugging in busy accounts: keep 1-in-N events, the
+// first K of each type, or at most K per time window, before dispatch.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize, Serializer};
+use tokio::time::Instant;
+
+use crate::config_file::de_duration_opt;
+use crate::Clock;
+
+/// Sampling limits, applied per event type after the event filter. An event
+/// is dispatched only if every configured limit keeps it. Sampled-out events
+/// are still acknowledged and counted in `ListenerStats::events_sampled_out`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sampling {
+    /// Keep one in every `one_in` events of a type: the 1st, the N+1th, ...
+    pub one_in: Option<u64>,
+    /// Keep only the first `per_type` events of a type.
+    pub per_type: Option<u64>,
+    /// Keep at most `window_max` events of a type per `window`.
+    pub window_max: Option<u64>,
+    #[serde(deserialize_with = "de_duration_opt", serialize_with = "ser_secs_opt")]
+    pub window: Option<Duration>,
+}
+
+fn ser_secs_opt<S: Serializer>(d: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+    match d {
+        Some(d) => s.serialize_some(&d.as_secs_f64()),
+        None => s.serialize_none(),
+    }
+}
+
+#[derive(Default)]
+struct TypeCounts {
+    seen: u64,
+    kept: u64,
+    window_start: Option<Instant>,
+    window_kept: u64,
+}
+
+// Per-type counters behind the Sampling limits; shared across reconnects.
+#[derive(Clone)]
+pub(crate) struct Sampler {
+    counts: Arc<Mutex<HashMap<String, TypeCounts>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Sampler {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            counts: Arc::new(Mutex::new(HashMap::new())),
+            clock,
+        }
+    }
+
+    /// Whether to dispatch the next event of `event_type`.
+    pub(crate) fn keep(&self, sampling: &Sampling, event_type: &str) -> bool {
+        let now = self.clock.instant();
+        let mut counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        let c = counts.entry(event_type.to_string()).or_default();
+        c.seen += 1;
+        if sampling.one_in.is_some_and(|n| n > 1 && !(c.seen - 1).is_multiple_of(n)) {
+            return false;
+        }
+        if sampling.per_type.is_some_and(|max| c.kept >= max) {
+            return false;
+        }
+        if let (Some(max), Some(window)) = (sampling.window_max, sampling.window) {
+            if c.window_start.is_none_or(|start| now.saturating_duration_since(start) >= window) {
+                c.window_start = Some(now);
+                c.window_kept = 0;
+            }
+            if c.window_kept >= max {
+                return false;
+            }
+            c.window_kept += 1;
+        }
+        c.kept += 1;
+        true
+    }
+}
@@ -0,0 +1,132 @@
+// Strict parsing of the event envelope, reporting fields Stripe sends that the
+// crate does not model (and modeled fields that are absent).
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+/// The full snapshot-event envelope. Unknown envelope and `data` fields are
+/// rejected; SchemaDrift::detect also checks the keys inside `request`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EventEnvelope {
+    pub id: String,
+    pub object: String,
+    #[serde(default)]
+    pub api_version: Option<String>,
+    pub created: u64,
+    pub data: EventData,
+    pub livemode: bool,
+    #[serde(default)]
+    pub pending_webhooks: Option<u64>,
+    #[serde(default, deserialize_with = "de_request")]
+    pub request: Option<EventRequest>,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    /// Connected account the event originated from.
+    #[serde(default)]
+    pub account: Option<String>,
+    #[serde(default)]
+    pub context: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EventData {
+    pub object: Value,
+    #[serde(default)]
+    pub previous_attributes: Option<Value>,
+}
+
+/// The API request that caused an event. Absent (or with a null id) for
+/// events Stripe generated on its own.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventRequest {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+}
+
+// Accepts the object form and the bare request-id string used by API
+// versions before 2017-05-25.
+pub(crate) fn de_request<'de, D: Deserializer<'de>>(d: D) -> Result<Option<EventRequest>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Id(String),
+        Full(EventRequest),
+    }
+    Ok(match Option::<Raw>::deserialize(d)? {
+        None => None,
+        Some(Raw::Id(id)) => Some(EventRequest {
+            id: Some(id),
+            idempotency_key: None,
+        }),
+        Some(Raw::Full(req)) => Some(req),
+    })
+}
+
+const ENVELOPE_FIELDS: &[&str] = &[
+    "id", "object", "api_version", "created", "data", "livemode", "pending_webhooks", "request", "type", "account", "context",
+];
+const ENVELOPE_REQUIRED: &[&str] = &["id", "object", "created", "data", "livemode", "type"];
+const DATA_FIELDS: &[&str] = &["object", "previous_attributes"];
+const REQUEST_FIELDS: &[&str] = &["id", "idempotency_key"];
+
+impl EventEnvelope {
+    /// Strictly parses a payload, failing on unknown envelope or `data` fields.
+    pub fn parse(payload: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(payload)
+    }
+}
+
+/// Difference between a received payload and EventEnvelope. Paths are dotted,
+/// e.g. `request.trace_id`.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct SchemaDrift {
+    pub event_id: String,
+    pub event_type: String,
+    pub unknown_fields: Vec<String>,
+    pub missing_fields: Vec<String>,
+    /// Strict-parse error when the payload did not fit for another reason,
+    /// such as a field with the wrong type.
+    pub error: Option<String>,
+}
+
+impl SchemaDrift {
+    /// Compares a payload against EventEnvelope; None when it parses strictly
+    /// and `request` has no unknown keys.
+    pub fn detect(payload: &Value) -> Option<SchemaDrift> {
+        let err = serde_json::from_value::<EventEnvelope>(payload.clone()).err();
+
+        let mut drift = SchemaDrift {
+            event_id: payload.get("id").and_then(Value::as_str).unwrap_or_default().to_string(),
+            event_type: payload.get("type").and_then(Value::as_str).unwrap_or_default().to_string(),
+            ..Default::default()
+        };
+        if let Some(obj) = payload.as_object() {
+            unknown_keys(obj, ENVELOPE_FIELDS, "", &mut drift.unknown_fields);
+            drift.missing_fields = ENVELOPE_REQUIRED
+                .iter()
+                .filter(|k| !obj.contains_key(**k))
+                .map(|k| k.to_string())
+                .collect();
+            if let Some(data) = obj.get("data").and_then(Value::as_object) {
+                unknown_keys(data, DATA_FIELDS, "data.", &mut drift.unknown_fields);
+                if !data.contains_key("object") {
+                    drift.missing_fields.push("data.object".to_string());
+                }
+            }
+            if let Some(request) = obj.get("request").and_then(Value::as_object) {
+                unknown_keys(request, REQUEST_FIELDS, "request.", &mut drift.unknown_fields);
+            }
+        }
+        if drift.unknown_fields.is_empty() && drift.missing_fields.is_empty() {
+            drift.error = Some(err?.to_string());
+        }
+        Some(drift)
+    }
+}
+
+fn unknown_keys(obj: &serde_json::Map<String, Value>, known: &[&str], prefix: &str, out: &mut Vec<String>) {
+    out.extend(obj.keys().filter(|k| !known.contains(&k.as_str())).map(|k| format!("{}{}", prefix, k)));
+}
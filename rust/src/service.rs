@@ -0,0 +1,309 @@
+// `stripelistener service ...` (feature `service`, part of the binary):
+// registers the --daemon listener with the platform's service manager so it
+// starts at boot.
+//
+//   stripelistener service install --config listener.toml [--name <name>]
+//   stripelistener service start|stop|uninstall [--name <name>]
+//
+// On Windows this is a service running as LocalSystem whose logs go to the
+// Event Log under the service name; installing needs an elevated prompt.
+// The SCM starts it as `service run`. On macOS it is a launchd agent, or a
+// daemon in /Library/LaunchDaemons when installed as root, logging to
+// ~/Library/Logs/<name>.log (/Library/Logs for daemons).
+#[cfg(any(windows, target_os = "macos"))]
+use std::path::PathBuf;
+
+use crate::Args;
+
+pub const DEFAULT_NAME: &str = "stripelistener";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Install,
+    Uninstall,
+    Start,
+    Stop,
+    /// Entry point for the Windows service control manager.
+    Run,
+}
+
+impl Action {
+    pub fn parse(s: &str) -> Option<Action> {
+        match s {
+            "install" => Some(Action::Install),
+            "uninstall" => Some(Action::Uninstall),
+            "start" => Some(Action::Start),
+            "stop" => Some(Action::Stop),
+            "run" => Some(Action::Run),
+            _ => None,
+        }
+    }
+}
+
+pub fn manage(action: Action, args: &Args) -> Result<(), String> {
+    let name = args.service_name.as_deref().unwrap_or(DEFAULT_NAME);
+    match action {
+        Action::Install => {
+            let config = args.config.as_deref().ok_or("service install needs --config")?;
+            // The service manager starts us from another directory.
+            let config = std::fs::canonicalize(config).map_err(|e| format!("config {}: {}", config.display(), e))?;
+            platform::install(name, &config)
+        }
+        Action::Uninstall => platform::uninstall(name),
+        Action::Start => platform::start(name),
+        Action::Stop => platform::stop(name),
+        Action::Run => platform::run(name),
+    }
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+fn exe() -> Result<PathBuf, String> {
+    std::env::current_exe().map_err(|e| format!("locating the executable: {}", e))
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::ffi::OsString;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use windows_service::service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo, ServiceStartType, ServiceState, ServiceStatus,
+        ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    fn manager(access: ServiceManagerAccess) -> Result<ServiceManager, String> {
+        ServiceManager::local_computer(None::<&str>, access).map_err(|e| format!("opening the service manager: {}", e))
+    }
+
+    pub fn install(name: &str, config: &Path) -> Result<(), String> {
+        let info = ServiceInfo {
+            name: OsString::from(name),
+            display_name: OsString::from(format!("Stripe listener ({})", name)),
+            service_type: ServiceType::OWN_PROCESS,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path: super::exe()?,
+            launch_arguments: vec!["service".into(), "run".into(), "--name".into(), name.into(), "--config".into(), config.into()],
+            dependencies: vec![],
+            account_name: None,
+            account_password: None,
+        };
+        let manager = manager(ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)?;
+        let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG).map_err(|e| format!("creating service {}: {}", name, e))?;
+        let _ = service.set_description("Receives Stripe webhook events over the Stripe CLI websocket");
+        eventlog::register(name).map_err(|e| format!("registering event log source {}: {}", name, e))
+    }
+
+    pub fn uninstall(name: &str) -> Result<(), String> {
+        let manager = manager(ServiceManagerAccess::CONNECT)?;
+        let access = ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE;
+        let service = manager.open_service(name, access).map_err(|e| format!("opening service {}: {}", name, e))?;
+        if service.query_status().is_ok_and(|s| s.current_state != ServiceState::Stopped) {
+            let _ = service.stop();
+        }
+        service.delete().map_err(|e| format!("deleting service {}: {}", name, e))?;
+        let _ = eventlog::deregister(name);
+        Ok(())
+    }
+
+    pub fn start(name: &str) -> Result<(), String> {
+        let manager = manager(ServiceManagerAccess::CONNECT)?;
+        let service = manager.open_service(name, ServiceAccess::START).map_err(|e| format!("opening service {}: {}", name, e))?;
+        service.start::<&str>(&[]).map_err(|e| format!("starting service {}: {}", name, e))
+    }
+
+    pub fn stop(name: &str) -> Result<(), String> {
+        let manager = manager(ServiceManagerAccess::CONNECT)?;
+        let service = manager.open_service(name, ServiceAccess::STOP).map_err(|e| format!("opening service {}: {}", name, e))?;
+        service.stop().map(drop).map_err(|e| format!("stopping service {}: {}", name, e))
+    }
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    // Blocks until the service stops; the SCM calls service_main on a thread
+    // of its own.
+    pub fn run(name: &str) -> Result<(), String> {
+        if let Err(e) = eventlog::init(name, log::Level::Info) {
+            eprintln!("event log: {}", e);
+        }
+        service_dispatcher::start(name, ffi_service_main).map_err(|e| format!("starting the service dispatcher: {}", e))
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        if let Err(e) = run_service() {
+            log::error!("{}", e);
+        }
+    }
+
+    fn run_service() -> Result<(), String> {
+        let args = crate::parse_args()?;
+        let name = args.service_name.clone().unwrap_or_else(|| super::DEFAULT_NAME.to_string());
+        let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+        let status = service_control_handler::register(&name, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                log::info!("stop requested, draining");
+                let _ = stop_tx.send(true);
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })
+        .map_err(|e| format!("registering the control handler: {}", e))?;
+        let report = |state, exit_code| {
+            let _ = status.set_service_status(ServiceStatus {
+                service_type: ServiceType::OWN_PROCESS,
+                current_state: state,
+                controls_accepted: match state {
+                    ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+                    _ => ServiceControlAccept::empty(),
+                },
+                exit_code,
+                checkpoint: 0,
+                wait_hint: Duration::default(),
+                process_id: None,
+            });
+        };
+        report(ServiceState::Running, ServiceExitCode::Win32(0));
+        let result = match tokio::runtime::Runtime::new() {
+            Ok(runtime) => runtime.block_on(crate::run_daemon(&args, stop_rx)).map_err(|e| e.to_string()),
+            Err(e) => Err(format!("starting the runtime: {}", e)),
+        };
+        let exit_code = match result {
+            Ok(()) => ServiceExitCode::Win32(0),
+            Err(_) => ServiceExitCode::ServiceSpecific(1),
+        };
+        report(ServiceState::Stopped, exit_code);
+        result
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    fn root() -> bool {
+        std::env::var("USER").is_ok_and(|u| u == "root")
+    }
+
+    fn label(name: &str) -> String {
+        format!("com.stripelistener.{}", name)
+    }
+
+    fn home() -> Result<PathBuf, String> {
+        std::env::var_os("HOME").map(PathBuf::from).ok_or_else(|| "HOME is not set".to_string())
+    }
+
+    fn plist(name: &str) -> Result<PathBuf, String> {
+        let dir = match root() {
+            true => PathBuf::from("/Library/LaunchDaemons"),
+            false => home()?.join("Library/LaunchAgents"),
+        };
+        Ok(dir.join(format!("{}.plist", label(name))))
+    }
+
+    fn log_file(name: &str) -> Result<PathBuf, String> {
+        let dir = match root() {
+            true => PathBuf::from("/Library/Logs"),
+            false => home()?.join("Library/Logs"),
+        };
+        Ok(dir.join(format!("{}.log", name)))
+    }
+
+    fn escape(s: &str) -> String {
+        s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+    }
+
+    fn launchctl(args: &[&str]) -> Result<(), String> {
+        let status = Command::new("launchctl").args(args).status().map_err(|e| format!("running launchctl: {}", e))?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(format!("launchctl {} failed: {}", args.join(" "), status)),
+        }
+    }
+
+    // --daemon restarts the listener itself, so launchd only starts it.
+    pub fn install(name: &str, config: &Path) -> Result<(), String> {
+        let path = plist(name)?;
+        let log = log_file(name)?;
+        let program = [super::exe()?.display().to_string(), "--config".to_string(), config.display().to_string(), "--daemon".to_string()];
+        let arguments: String = program.iter().map(|a| format!("        <string>{}</string>\n", escape(a))).collect();
+        let log = escape(&log.display().to_string());
+        let plist = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+{arguments}    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#,
+            label = label(name),
+        );
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        std::fs::write(&path, plist).map_err(|e| format!("{}: {}", path.display(), e))?;
+        println!("installed {}", path.display());
+        Ok(())
+    }
+
+    pub fn uninstall(name: &str) -> Result<(), String> {
+        let path = plist(name)?;
+        let _ = stop(name);
+        std::fs::remove_file(&path).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn start(name: &str) -> Result<(), String> {
+        launchctl(&["load", "-w", &plist(name)?.display().to_string()])
+    }
+
+    pub fn stop(name: &str) -> Result<(), String> {
+        launchctl(&["unload", &plist(name)?.display().to_string()])
+    }
+
+    pub fn run(_name: &str) -> Result<(), String> {
+        Err("`service run` is for the Windows service manager; launchd runs --daemon directly".to_string())
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+mod platform {
+    use std::path::Path;
+
+    const UNSUPPORTED: &str = "`service` supports Windows and macOS; elsewhere run --daemon from a systemd unit (feature `systemd`)";
+
+    pub fn install(_name: &str, _config: &Path) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    pub fn uninstall(_name: &str) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    pub fn start(_name: &str) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    pub fn stop(_name: &str) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    pub fn run(_name: &str) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+}
@@ -0,0 +1,1015 @@
+// StripeListener: authorizing a session, connecting, the read loop, and
+// reconnecting and catching up after drops.
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::time::interval;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::api::{self, ApiClient};
+use crate::batch::{AckKey, Batch};
+use crate::config_file::FileConfig;
+use crate::devproxy;
+use crate::diagnostics::Diagnostics;
+use crate::dispatch::Dispatcher;
+use crate::expand;
+#[cfg(feature = "forwarder")]
+use crate::forward::{Delivery, Forwarder, RecentDeliveries};
+use crate::logging::{flush_coalesced, CoalescingLogger, LabeledLogger, LevelFilterLogger};
+use crate::pool::PoolMember;
+use crate::sampling::Sampler;
+use crate::shadow::{self, ShadowLedger};
+use crate::siblings::SiblingGuard;
+use crate::stats::StatsRecorder;
+#[cfg(feature = "tower")]
+use crate::tower::ServiceAnswer;
+use crate::transport::{send_ack, stopped, Acker, ConnectionGuard, InFlight, Outbox, Outgoing, TaskSet, WriteQueue};
+use crate::*;
+
+// Constants matching pkg/websocket/client.go defaults
+pub(crate) const CLI_VERSION: &str = "1.21.0";
+pub(crate) const SESSION_PATH: &str = "/v1/stripecli/sessions";
+pub(crate) const API_BASE: &str = "https://api.stripe.com";
+
+pub struct StripeListener {
+    pub(crate) cfg: Config,
+    session: Option<Session>,
+    // The signing secret of the last session authorized, for the handle.
+    signing_secret: Arc<Mutex<Option<SecretString>>>,
+    outbox: Outbox,
+    last_close: Option<CloseReason>,
+    established: bool,
+    // Catch-up replay to run once the next connection is up.
+    catch_up: Option<CatchUp>,
+    live: ConfigHandle,
+    stats: StatsRecorder,
+    pub(crate) inflight: InFlight,
+    sampler: Sampler,
+    #[cfg(feature = "forwarder")]
+    recent: RecentDeliveries,
+    // Events received while Config::shadow is set.
+    shadow: Option<ShadowLedger>,
+    diagnostics: Diagnostics,
+    // The logger's coalescing layer, flushed on a timer while run() goes.
+    coalescing: Option<Arc<CoalescingLogger>>,
+    // Set by ListenerPool for its listeners.
+    pub(crate) pool: Option<PoolMember>,
+    // Shadow comparison, catch-up and the coalescing flush, which outlive a
+    // connection but not run().
+    tasks: TaskSet,
+    pub(crate) shutdown: Arc<tokio::sync::watch::Sender<bool>>,
+}
+
+/// The future returned by StripeListener::run. It is Unpin, so a
+/// `tokio::select!` loop can poll it as `&mut run` next to other branches.
+///
+/// Dropping it at any point is safe: the ping, shadow, catch-up and log
+/// flush tasks are aborted, and an open websocket gets a normal close frame
+/// once the ACKs already queued are written. Events dispatched before the
+/// drop stay acknowledged and in the cursor. The listener keeps its
+/// session, so the next run() or connect() reuses it, and
+/// ListenerHandle::report still covers the interrupted run.
+pub struct ListenerFuture<'a>(Pin<Box<dyn Future<Output = Result<SessionReport>> + Send + 'a>>);
+
+impl Future for ListenerFuture<'_> {
+    type Output = Result<SessionReport>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+/// Cloneable view of a running listener, usable from other tasks while
+/// run() or connect() holds the listener.
+#[derive(Clone)]
+pub struct ListenerHandle {
+    live: ConfigHandle,
+    stats: StatsRecorder,
+    #[cfg(feature = "forwarder")]
+    recent: RecentDeliveries,
+    outbox: Outbox,
+    diagnostics: Diagnostics,
+    signing_secret: Arc<Mutex<Option<SecretString>>>,
+    shutdown: Arc<tokio::sync::watch::Sender<bool>>,
+}
+
+impl ListenerHandle {
+    pub fn stats(&self) -> ListenerStats {
+        self.stats.snapshot()
+    }
+
+    /// The SessionReport so far.
+    pub fn report(&self) -> SessionReport {
+        self.stats.report()
+    }
+
+    pub fn config(&self) -> ConfigHandle {
+        self.live.clone()
+    }
+
+    /// The `whsec_...` secret of the last session authorized, as printed by
+    /// `stripe listen`; None until run() or authorize() has created one.
+    /// See Session::signing_secret.
+    pub fn signing_secret(&self) -> Option<SecretString> {
+        self.signing_secret.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// The last `Config::recent_deliveries` forwarded requests with their
+    /// headers, bodies, status and timing, oldest first. Mirror requests
+    /// and each retry are included.
+    #[cfg(feature = "forwarder")]
+    pub fn recent_deliveries(&self) -> Vec<Delivery> {
+        self.recent.snapshot()
+    }
+
+    /// Sends a message on the current connection, queued behind pending
+    /// acks. Fails with `Error::Other` while the listener is not connected.
+    /// Raw messages are sent unchecked; the server may close the connection
+    /// over one it rejects.
+    pub async fn send_message(&self, msg: OutgoingMessage) -> Result<()> {
+        self.outbox.send(msg).await
+    }
+
+    /// Writes a JSON bundle for bug reports to `path`: the last 50 frames
+    /// sent or received, connection state changes, recent errors, the
+    /// config and current stats. API keys, signing secrets and signatures
+    /// are masked, and event payloads and bodies are reduced to their
+    /// length and event id and type.
+    pub fn dump_diagnostics(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let bundle = self.diagnostics.bundle(&self.live.snapshot(), &self.stats.snapshot(), &self.stats.report());
+        std::fs::write(path, serde_json::to_vec_pretty(&bundle).map_err(std::io::Error::other)?)
+    }
+
+    /// Stops the listener gracefully: run() or connect() stops reading,
+    /// closes the websocket after the queued ACKs, waits up to
+    /// `drain_timeout` for in-flight forwards and returns `Ok(())`.
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+}
+
+impl StripeListener {
+    pub fn new(cfg: Config) -> Self {
+        let live = ConfigHandle::new(LiveConfig::from_config(&cfg));
+        Self::with_config_handle(cfg, live)
+    }
+
+    // Builds a listener driven by an existing ConfigHandle, so several
+    // listeners can share live settings.
+    pub(crate) fn with_config_handle(mut cfg: Config, live: ConfigHandle) -> Self {
+        cfg.defaults();
+        let labels: Vec<(&'static str, String)> = [("device_name", &cfg.device_name), ("stripe_account", &cfg.stripe_account)]
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value.clone()?)))
+            .collect();
+        let stats = StatsRecorder::new(cfg.clock.clone().unwrap());
+        stats.set_labels(cfg.device_name.clone(), cfg.stripe_account.clone());
+        let mut logger = cfg.logger.take().unwrap();
+        if !labels.is_empty() {
+            logger = Arc::new(LabeledLogger { inner: logger, labels });
+        }
+        logger = Arc::new(LevelFilterLogger {
+            inner: logger,
+            config: live.clone(),
+        });
+        let coalescing = cfg.log_coalescing.clone().map(|settings| Arc::new(CoalescingLogger::new(logger.clone(), settings, cfg.clock.clone().unwrap())));
+        if let Some(coalescing) = &coalescing {
+            logger = coalescing.clone();
+        }
+        cfg.logger = Some(logger);
+        Self {
+            stats,
+            sampler: Sampler::new(cfg.clock.clone().unwrap()),
+            #[cfg(feature = "forwarder")]
+            recent: RecentDeliveries::new(cfg.recent_deliveries.unwrap()),
+            shadow: cfg.shadow.as_ref().map(|_| ShadowLedger::new(cfg.stripe_account.clone())),
+            diagnostics: Diagnostics::new(&cfg),
+            coalescing,
+            pool: None,
+            tasks: TaskSet::default(),
+            cfg,
+            session: None,
+            signing_secret: Arc::default(),
+            outbox: Outbox::default(),
+            last_close: None,
+            established: false,
+            catch_up: None,
+            live,
+            inflight: InFlight::default(),
+            shutdown: Arc::new(tokio::sync::watch::channel(false).0),
+        }
+    }
+
+    pub fn handle(&self) -> ListenerHandle {
+        ListenerHandle {
+            live: self.live.clone(),
+            stats: self.stats.clone(),
+            #[cfg(feature = "forwarder")]
+            recent: self.recent.clone(),
+            outbox: self.outbox.clone(),
+            diagnostics: self.diagnostics.clone(),
+            signing_secret: self.signing_secret.clone(),
+            shutdown: self.shutdown.clone(),
+        }
+    }
+
+    /// Handle for changing filters, forward target and log level while the
+    /// listener is running.
+    pub fn config_handle(&self) -> ConfigHandle {
+        self.live.clone()
+    }
+
+    /// Polls a config file (see FileConfig) every `poll` and applies its
+    /// `events`, forwarding and `log_level` settings whenever the file's
+    /// modification time changes. Other settings need a restart. The watcher
+    /// stops when the returned task is aborted.
+    pub fn watch_config_file(&self, path: impl Into<PathBuf>, poll: Duration) -> tokio::task::JoinHandle<()> {
+        let path = path.into();
+        let handle = self.live.clone();
+        let logger = self.cfg.logger.clone().unwrap();
+        tokio::spawn(async move {
+            let mut last_modified: Option<SystemTime> = None;
+            let mut ticker = interval(poll);
+            loop {
+                ticker.tick().await;
+                let modified = match std::fs::metadata(&path).and_then(|m| m.modified()) {
+                    Ok(m) => m,
+                    Err(e) => {
+                        logger.log(LogLevel::Warn, "config watch failed", &[("path", &path.display()), ("error", &e)]);
+                        continue;
+                    }
+                };
+                if last_modified == Some(modified) {
+                    continue;
+                }
+                let first = last_modified.is_none();
+                last_modified = Some(modified);
+                match FileConfig::load(&path) {
+                    Ok(file) => {
+                        file.apply_live(&handle);
+                        if !first {
+                            logger.log(LogLevel::Info, "reloaded config", &[("path", &path.display())]);
+                        }
+                    }
+                    Err(e) => logger.log(LogLevel::Error, "config reload failed", &[("path", &path.display()), ("error", &e)]),
+                }
+            }
+        })
+    }
+
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    /// How the most recent connection ended, if the server sent a close frame
+    /// or the socket dropped.
+    pub fn close_reason(&self) -> Option<&CloseReason> {
+        self.last_close.as_ref()
+    }
+
+    /// Authorizes and connects, recovering from failures according to the
+    /// configured ReconnectPolicy. Returns when the server closes normally or
+    /// the policy gives up, or with Error::TakenOver when a newer listener
+    /// took over (see Config::duplicate_sessions). A normal return carries
+    /// the SessionReport for the whole run. Cancel-safe; see ListenerFuture.
+    pub fn run(&mut self) -> ListenerFuture<'_> {
+        ListenerFuture(Box::pin(self.run_to_end()))
+    }
+
+    async fn run_to_end(&mut self) -> Result<SessionReport> {
+        let _tasks = self.tasks.abort_on_drop();
+        let policy = self.cfg.duplicate_sessions.unwrap_or_default();
+        let siblings = match SiblingGuard::register(&self.cfg, policy, self.shutdown.clone()) {
+            Ok(guard) => Some(guard),
+            Err(e) => {
+                self.cfg.logger.as_ref().unwrap().log(LogLevel::Warn, "could not check for duplicate listeners", &[("error", &e)]);
+                None
+            }
+        };
+        self.stats.run_started();
+        if let Some(coalescing) = &self.coalescing {
+            self.tasks.spawn(flush_coalesced(coalescing.clone()));
+        }
+        if let (Some(settings), Some(ledger)) = (&self.cfg.shadow, &self.shadow) {
+            let watch = shadow::watch(settings.clone(), self.api_client()?, ledger.clone(), self.dispatcher()?, self.cfg.clock.clone().unwrap());
+            self.tasks.spawn(watch);
+        }
+        let result = self.run_sessions().await;
+        match siblings.as_ref().and_then(SiblingGuard::taken_over_by) {
+            Some(pid) => Err(Error::TakenOver { pid }),
+            None => result.map(|()| self.stats.report()),
+        }
+    }
+
+    async fn run_sessions(&mut self) -> Result<()> {
+        let policy = self.cfg.reconnect_policy.clone().unwrap();
+        let clock = self.cfg.clock.clone().unwrap();
+        let logger = self.cfg.logger.clone().unwrap();
+        let check = self.cfg.verify_endpoints.unwrap_or_default();
+        if check != EndpointCheck::Off {
+            let missing = match self.unconfigured_events().await {
+                Ok(missing) => missing,
+                Err(e) if check == EndpointCheck::Refuse => return Err(e),
+                Err(e) => {
+                    logger.log(LogLevel::Warn, "could not verify webhook endpoints", &[("error", &e)]);
+                    Vec::new()
+                }
+            };
+            if !missing.is_empty() {
+                let msg = format!("event types not enabled on any webhook endpoint: {}", missing.join(", "));
+                if check == EndpointCheck::Refuse {
+                    return Err(Error::Config(msg));
+                }
+                logger.warn(&msg);
+            }
+        }
+        if self.catch_up.is_none() {
+            self.catch_up = self.stored_cursor();
+        }
+        let mut attempt = 0u32;
+        let mut shutdown = self.shutdown.subscribe();
+        loop {
+            if *shutdown.borrow() {
+                return Ok(());
+            }
+            let result = match self.session {
+                Some(_) => self.connect().await,
+                None => {
+                    self.diagnostics.transition("authorizing", "");
+                    let authorized = tokio::select! {
+                        authorized = self.authorize() => authorized,
+                        _ = stopped(&mut shutdown) => return Ok(()),
+                    };
+                    match authorized {
+                        Ok(_) => self.connect().await,
+                        Err(e) => Err(e),
+                    }
+                }
+            };
+            let err = match result {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            self.diagnostics.error(&err);
+
+            if std::mem::take(&mut self.established) {
+                attempt = 0;
+            }
+            attempt += 1;
+            self.stats.set_reconnect_attempt(attempt);
+            let action = policy.next_action(attempt, &err);
+            if action != ReconnectAction::GiveUp {
+                self.stats.reconnecting();
+            }
+            match action {
+                ReconnectAction::Delay(d) => {
+                    // The server's reconnect_delay is a floor under the policy.
+                    let floor = self.session.as_ref().and_then(Session::server_reconnect_delay);
+                    let d = floor.map_or(d, |floor| d.max(floor));
+                    logger.log(LogLevel::Warn, "reconnecting", &[("error", &err), ("delay", &format!("{:?}", d)), ("attempt", &attempt)]);
+                    self.diagnostics.transition("reconnecting", format!("attempt {} in {:?}", attempt, d));
+                    tokio::select! {
+                        _ = clock.sleep(d) => {}
+                        _ = stopped(&mut shutdown) => return Ok(()),
+                    }
+                }
+                ReconnectAction::Reauthorize => {
+                    logger.log(LogLevel::Warn, "reauthorizing", &[("error", &err), ("attempt", &attempt)]);
+                    self.diagnostics.transition("reauthorizing", format!("attempt {}", attempt));
+                    self.session = None;
+                }
+                ReconnectAction::GiveUp => {
+                    logger.log(LogLevel::Error, "giving up", &[("error", &err), ("attempt", &attempt)]);
+                    self.diagnostics.transition("gave_up", format!("attempt {}", attempt));
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    // Catch-up position from the configured cursor, if it holds one.
+    fn stored_cursor(&self) -> Option<CatchUp> {
+        match self.cfg.cursor.as_ref()?.load() {
+            Ok(pos) => pos.map(|p| CatchUp {
+                created: p.created,
+                after: Some(p.event_id),
+            }),
+            Err(e) => {
+                self.cfg.logger.as_ref().unwrap().log(LogLevel::Warn, "could not load cursor", &[("error", &e)]);
+                None
+            }
+        }
+    }
+
+    // The event pipeline for one connection, or for run_tunnel.
+    pub(crate) fn dispatcher(&self) -> Result<Dispatcher> {
+        self.cfg.check(&self.live.snapshot())?;
+        Ok(Dispatcher {
+            handler: self.cfg.handler.clone(),
+            logger: self.cfg.logger.clone().unwrap(),
+            stats: self.stats.clone(),
+            cursor: self.cfg.cursor.clone(),
+            live: self.live.clone(),
+            transform: self.cfg.transform.clone(),
+            predicate: self.cfg.predicate.clone(),
+            tenants: self.cfg.tenant_resolver.clone(),
+            #[cfg(feature = "forwarder")]
+            forwarder: Forwarder::new(self.cfg.tls.as_ref().unwrap(), self.cfg.clock.clone().unwrap(), self.recent.clone())?,
+            #[cfg(feature = "forwarder")]
+            dead_letter: self.cfg.dead_letter.clone(),
+            strict_parse: self.cfg.strict_parse.unwrap_or(false),
+            max_event_age: self.cfg.max_event_age,
+            clock: self.cfg.clock.clone().unwrap(),
+            #[cfg(feature = "forwarder")]
+            inflight: self.inflight.clone(),
+            sampler: self.sampler.clone(),
+            batching: self.cfg.batching.clone(),
+            #[cfg(feature = "tower")]
+            service: self.cfg.service.clone(),
+            connection_id: None,
+            pool: self.pool.clone(),
+        })
+    }
+
+    pub(crate) fn api_client(&self) -> Result<ApiClient> {
+        devproxy::api_client(&self.cfg)
+    }
+
+    /// Event types named in the event filter or forward routes that no
+    /// enabled webhook endpoint on the account subscribes to. Wildcard
+    /// filters are not checked.
+    pub async fn unconfigured_events(&self) -> Result<Vec<String>> {
+        let live = self.live.snapshot();
+        let mut wanted: Vec<&str> = live.events.iter().flatten().map(EventType::as_str).collect();
+        #[cfg(feature = "forwarder")]
+        wanted.extend(live.forward.iter().flat_map(|r| r.events.iter().flatten()).map(EventType::as_str));
+        wanted.retain(|e| *e != "*");
+        wanted.sort_unstable();
+        wanted.dedup();
+        if wanted.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let api = self.api_client()?;
+        let endpoints = api.list_enabled_endpoints().await?;
+        let enabled: Vec<&str> = endpoints
+            .iter()
+            .filter_map(|e| e.get("enabled_events").and_then(serde_json::Value::as_array))
+            .flatten()
+            .filter_map(serde_json::Value::as_str)
+            .collect();
+        if enabled.contains(&"*") {
+            return Ok(Vec::new());
+        }
+        Ok(wanted.into_iter().filter(|e| !enabled.contains(e)).map(str::to_string).collect())
+    }
+
+    /// Looks up the configuration of the event destination a V2Event was
+    /// delivered for (`V2Event::destination_id`).
+    pub async fn event_destination(&self, id: &str) -> Result<EventDestination> {
+        let value = self.api_client()?.fetch_event_destination(id).await?;
+        serde_json::from_value(value).map_err(|e| Error::Other(format!("event destination {}: {}", id, e)))
+    }
+
+    /// Creates a CLI session for connect(). Fails with `Error::Timeout` when
+    /// Config::authorize_timeout or authorize_attempt_timeout runs out. The
+    /// future is cancel-safe: dropping it leaves the listener as it was.
+    pub async fn authorize(&mut self) -> Result<Session> {
+        let session = devproxy::create_session(&self.cfg).await?;
+        self.cfg.logger.as_ref().unwrap().log(
+            LogLevel::Info,
+            "session created",
+            &[("websocket_id", &session.websocket_id), ("feature", &session.websocket_authorized_feature)],
+        );
+        self.diagnostics.transition("authorized", &session.websocket_id);
+        *self.signing_secret.lock().unwrap_or_else(|e| e.into_inner()) = session.signing_secret().map(SecretString::from);
+        self.session = Some(session.clone());
+        Ok(session)
+    }
+
+    /// Connects and runs the read loop until the socket closes. A normal
+    /// closure returns `Ok(())`; anything else returns `Error::Closed` with
+    /// the server's code and reason. Dropping the future closes the
+    /// connection as described for ListenerFuture.
+    pub async fn connect(&mut self) -> Result<()> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or_else(|| Error::Other("call authorize() before connect()".to_string()))?;
+        let connection_id = connection_id();
+        let logger: Arc<dyn Logger> = Arc::new(LabeledLogger {
+            inner: self.cfg.logger.clone().unwrap(),
+            labels: vec![("connection_id", connection_id.clone())],
+        });
+        self.stats.set_connection_id(&connection_id);
+        let clock = self.cfg.clock.clone().unwrap();
+        let mut dispatcher = self.dispatcher()?;
+        dispatcher.logger = logger.clone();
+        dispatcher.connection_id = Some(connection_id);
+        #[cfg(all(feature = "forwarder", feature = "types"))]
+        {
+            dispatcher.forwarder.session_secret = session.signing_secret().map(SecretString::from);
+            dispatcher.warn_unsigned();
+        }
+        let api = self.api_client()?;
+        let (ws_stream, acker) = devproxy::dial(&self.cfg, session, logger.as_ref()).await?;
+        let websocket_id = session.websocket_id.clone();
+        logger.log(
+            LogLevel::Info,
+            "websocket connected",
+            &[("websocket_id", &websocket_id), ("subprotocol", &acker.subprotocol)],
+        );
+        self.established = true;
+        let _connected = self.pool.as_ref().map(PoolMember::connected);
+        self.stats.set_reconnect_attempt(0);
+        self.diagnostics.transition("connected", &websocket_id);
+
+        let (mut write, mut read) = ws_stream.split();
+        let (tx, mut lanes) = WriteQueue::new();
+        let _outbox = self.outbox.open(tx.clone(), acker.clone());
+        let mut guard = ConnectionGuard::new(tx.clone());
+
+        // Write loop
+        let logger_clone = logger.clone();
+        let stats_write = self.stats.clone();
+        let dispatcher_write = dispatcher.clone();
+        let clock_write = clock.clone();
+        let diagnostics_write = self.diagnostics.clone();
+        let writer = tokio::spawn(async move {
+            while let Some(out) = lanes.recv().await {
+                stats_write.frame_out(out.message.len());
+                diagnostics_write.frame("out", &out.message);
+                let closing = matches!(out.message, Message::Close(_));
+                if let Err(e) = write.send(out.message).await {
+                    logger_clone.log(LogLevel::Error, "write error", &[("error", &e)]);
+                    let err = Error::from(e);
+                    if let Some(ack) = out.ack {
+                        dispatcher_write.ack_failed(&ack.event_id, &ack.conversation_id, &err);
+                    }
+                    // Whatever is still queued will never be written.
+                    for out in lanes.close() {
+                        if let Some(ack) = out.ack {
+                            dispatcher_write.ack_failed(&ack.event_id, &ack.conversation_id, &err);
+                        }
+                    }
+                    break;
+                }
+                if let Some(ack) = out.ack {
+                    stats_write.ack_sent();
+                    let now = clock_write.now();
+                    dispatcher_write.guarded("on_ack_sent", Some(&ack.event_id), |h| h.on_ack_sent(&ack.event_id, &ack.conversation_id, now));
+                }
+                if closing {
+                    break;
+                }
+            }
+        });
+
+        // Ping loop
+        let tx_clone = tx.clone();
+        let ping_period = self.cfg.ping_period.unwrap();
+        let logger_ping = logger.clone();
+        let stats_ping = self.stats.clone();
+        let resume_threshold = self.cfg.resume_threshold.unwrap();
+        let (resume_tx, mut resume_rx) = tokio::sync::oneshot::channel::<(SystemTime, Duration)>();
+        // Set once resume_rx has resolved; a finished oneshot must not be
+        // polled again.
+        let mut resume_done = false;
+        guard.tasks.spawn(async move {
+            let mut last_tick = clock.now();
+            loop {
+                clock.sleep(ping_period).await;
+                // The monotonic timer stops while the host is suspended; the
+                // wall clock does not.
+                let now = clock.now();
+                let gap = now.duration_since(last_tick).unwrap_or_default();
+                if gap > ping_period + resume_threshold {
+                    logger_ping.log(LogLevel::Warn, "host likely slept", &[("gap", &format!("{:?}", gap))]);
+                    let _ = resume_tx.send((last_tick, gap));
+                    break;
+                }
+                last_tick = now;
+                match tx_clone.try_send_control(Outgoing::frame(Message::Ping(stats_ping.ping_payload()))) {
+                    Ok(()) => logger_ping.debug("ping sent"),
+                    // Earlier pings have not been written yet; another adds nothing.
+                    Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => logger_ping.debug("ping skipped, control lane busy"),
+                    Err(e) => {
+                        logger_ping.log(LogLevel::Error, "ping send error", &[("error", &e)]);
+                        break;
+                    }
+                }
+            }
+        });
+
+        // Read loop
+        let logger_read = logger.clone();
+        let messages = self.cfg.messages.clone().unwrap_or_default();
+        let policy = self.cfg.reconnect_policy.clone().unwrap();
+        let stats = self.stats.clone();
+        let rest_fallback = self.cfg.rest_fallback.unwrap_or(true);
+        let expansions = self.cfg.expand.clone().unwrap_or_default();
+        let skew_warning = self.cfg.clock_skew_warning.unwrap();
+        let mut skewed = false;
+        let unparseable = self.cfg.unparseable_payload.unwrap_or_default();
+        
+        // We need to move tx into read loop for ACKs
+        let tx_ack = tx.clone();
+
+        self.last_close = None;
+        let mut close = CloseReason::abnormal();
+        if let Some(from) = self.catch_up.take() {
+            self.tasks.spawn(catch_up(api.clone(), dispatcher.clone(), expansions.clone(), from));
+        }
+        let mut shutdown = self.shutdown.subscribe();
+        let mut batch = dispatcher.batching.clone().map(Batch::new);
+
+        loop {
+            let msg = tokio::select! {
+                msg = read.next() => match msg {
+                    Some(msg) => msg,
+                    None => break,
+                },
+                _ = batch_due(batch.as_ref(), self.cfg.clock.as_deref().unwrap()) => {
+                    flush_batch(&mut batch, &tx_ack, &dispatcher, &acker).await;
+                    continue;
+                }
+                _ = stopped(&mut shutdown) => {
+                    logger_read.log(LogLevel::Info, "shutting down", &[("websocket_id", &websocket_id)]);
+                    flush_batch(&mut batch, &tx_ack, &dispatcher, &acker).await;
+                    tx.close_after_data().await;
+                    let drained = async {
+                        let _ = writer.await;
+                        self.inflight.idle().await;
+                    };
+                    tokio::select! {
+                        _ = drained => {}
+                        _ = self.cfg.clock.as_ref().unwrap().sleep(self.cfg.drain_timeout.unwrap()) => {
+                            logger_read.log(LogLevel::Warn, "drain timed out", &[("websocket_id", &websocket_id)]);
+                        }
+                    }
+                    self.last_close = Some(CloseReason::normal());
+                    self.diagnostics.transition("stopped", &websocket_id);
+                    guard.returned = true;
+                    return Ok(());
+                }
+                resumed = &mut resume_rx, if !resume_done => {
+                    resume_done = true;
+                    // The ping task ended without a resume, e.g. after the
+                    // write task stopped.
+                    let Ok((since, slept)) = resumed else { continue };
+                    flush_batch(&mut batch, &tx_ack, &dispatcher, &acker).await;
+                    if self.cfg.catch_up_on_resume.unwrap_or(false) {
+                        let since = since.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+                        self.catch_up = Some(self.stored_cursor().unwrap_or(CatchUp { created: since, after: None }));
+                    }
+                    guard.returned = true;
+                    return Err(Error::Resumed { slept });
+                }
+            };
+            if let Ok(frame) = &msg {
+                stats.frame_in(frame.len());
+                self.diagnostics.frame("in", frame);
+            }
+            match msg {
+                Ok(Message::Text(text)) => {
+                    let frame = match Frame::parse(&text) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            logger_read.log(LogLevel::Warn, "malformed message", &[("error", &e)]);
+                            continue;
+                        }
+                    };
+
+                    match frame {
+                        Frame::Webhook(mut evt) => {
+                            stats.event_received();
+                            if let Some(signed_at) = evt.signed_at() {
+                                let now = self.cfg.clock.as_ref().unwrap().now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default();
+                                let skew = now - signed_at;
+                                stats.clock_skew(skew);
+                                // Logged when the skew crosses the threshold, not per event.
+                                let over = skew.unsigned_abs() > skew_warning.as_secs();
+                                if over && !skewed {
+                                    logger_read.log(
+                                        LogLevel::Warn,
+                                        "local clock differs from Stripe's; signature checks may fail",
+                                        &[("skew_secs", &skew), ("threshold", &format!("{:?}", skew_warning))],
+                                    );
+                                } else if !over && skewed {
+                                    logger_read.log(LogLevel::Info, "local clock back in line with Stripe's", &[("skew_secs", &skew)]);
+                                }
+                                skewed = over;
+                            }
+                            let parsed: StripeEventPayload = match serde_json::from_str(&evt.event_payload) {
+                                Ok(p) => p,
+                                Err(e) => {
+                                    let recovered = match api::truncated_event_id(&evt.event_payload) {
+                                        Some(id) if rest_fallback => {
+                                            logger_read.log(LogLevel::Warn, "event_payload truncated, fetching it from the API", &[("event_id", &id), ("error", &e)]);
+                                            fetch_full_event(&api, id).await
+                                        }
+                                        _ => Err(Error::Other(e.to_string())),
+                                    };
+                                    match recovered {
+                                        Ok((payload, p)) => {
+                                            evt.event_payload = payload;
+                                            p
+                                        }
+                                        Err(e) => {
+                                            logger_read.log(
+                                                LogLevel::Warn,
+                                                "could not parse event_payload",
+                                                &[("webhook_id", &evt.webhook_id), ("error", &e), ("policy", &format!("{:?}", unparseable))],
+                                            );
+                                            if unparseable == UnparseablePayload::DropNoAck {
+                                                continue;
+                                            }
+                                            // Best effort: a payload cut off before its id
+                                            // is acked with an empty one.
+                                            let event_id = api::truncated_event_id(&evt.event_payload).unwrap_or_default().to_string();
+                                            let ack = AckFields {
+                                                event_id: &event_id,
+                                                webhook_id: &evt.webhook_id,
+                                                webhook_conversation_id: &evt.webhook_conversation_id,
+                                            };
+                                            send_ack(&tx_ack, &dispatcher, &acker, ack).await;
+                                            if unparseable == UnparseablePayload::DeliverRaw {
+                                                dispatcher.guarded("on_raw_webhook_event", Some(&event_id), |h| h.on_raw_webhook_event(evt));
+                                            }
+                                            continue;
+                                        }
+                                    }
+                                }
+                            };
+                            if let Some(ledger) = &self.shadow {
+                                ledger.record(&parsed);
+                            }
+                            if !expansions.is_empty() && dispatcher.live.snapshot().matches_event(parsed.event_type.as_str()) {
+                                expand::expand(&api, &expansions, &mut evt, &parsed, logger_read.as_ref()).await;
+                            }
+
+                            // With a service, events are acked once it answers
+                            // Ack, dropped ones right away.
+                            #[cfg(feature = "tower")]
+                            if let Some(service) = &dispatcher.service {
+                                let key = AckKey::of(&evt, &parsed);
+                                match dispatcher.admit(evt, parsed) {
+                                    Some(admitted) => {
+                                        let answer = dispatcher.serve_webhook(service, admitted).await;
+                                        self.inflight.spawn(ack_when_answered(answer, key, tx_ack.clone(), dispatcher.clone(), acker.clone()));
+                                    }
+                                    None => send_ack(&tx_ack, &dispatcher, &acker, key.fields()).await,
+                                }
+                                continue;
+                            }
+
+                            // Batched events are acked once their batch is
+                            // accepted, dropped ones right away.
+                            if let Some(pending) = &mut batch {
+                                let key = AckKey::of(&evt, &parsed);
+                                match dispatcher.admit(evt, parsed) {
+                                    Some(admitted) => {
+                                        if pending.push(key, admitted, self.cfg.clock.as_ref().unwrap().now()) {
+                                            flush_batch(&mut batch, &tx_ack, &dispatcher, &acker).await;
+                                        }
+                                    }
+                                    None => send_ack(&tx_ack, &dispatcher, &acker, key.fields()).await,
+                                }
+                                continue;
+                            }
+
+                            // Send ACK
+                            let ack = AckFields {
+                                event_id: &parsed.id,
+                                webhook_id: &evt.webhook_id,
+                                webhook_conversation_id: &evt.webhook_conversation_id,
+                            };
+                            send_ack(&tx_ack, &dispatcher, &acker, ack).await;
+
+                            dispatcher.webhook(evt, parsed);
+                        }
+                        Frame::V2(evt) => {
+                            stats.event_received();
+                            let parsed: V2EventPayload = match serde_json::from_str(&evt.payload) {
+                                 Ok(p) => p,
+                                 Err(_) => {
+                                     logger_read.warn("could not parse v2 payload");
+                                     continue;
+                                 }
+                            };
+
+                            #[cfg(feature = "tower")]
+                            if let Some(service) = &dispatcher.service {
+                                let key = AckKey::of_v2(&evt, &parsed);
+                                match dispatcher.admit_v2(evt, parsed) {
+                                    Some((evt, parsed)) => {
+                                        let answer = dispatcher.serve_v2(service, evt, parsed).await;
+                                        self.inflight.spawn(ack_when_answered(answer, key, tx_ack.clone(), dispatcher.clone(), acker.clone()));
+                                    }
+                                    None => send_ack(&tx_ack, &dispatcher, &acker, key.fields()).await,
+                                }
+                                continue;
+                            }
+
+                            // Send ACK
+                            let ack = AckFields {
+                                event_id: &parsed.id,
+                                webhook_id: &evt.destination_id,
+                                webhook_conversation_id: "",
+                            };
+                            send_ack(&tx_ack, &dispatcher, &acker, ack).await;
+
+                            dispatcher.v2(evt, parsed);
+                        }
+                        Frame::Error(error) => {
+                            stats.server_error(&error);
+                            let fatal = policy.is_fatal(&error);
+                            logger_read.log(
+                                LogLevel::Error,
+                                "server error",
+                                &[("websocket_id", &websocket_id), ("code", &error.code.as_deref().unwrap_or("")), ("message", &error.message), ("fatal", &fatal)],
+                            );
+                            dispatcher.guarded("on_server_error", None, |h| h.on_server_error(&error));
+                            if fatal {
+                                flush_batch(&mut batch, &tx_ack, &dispatcher, &acker).await;
+                                tx.close_after_data().await;
+                                guard.returned = true;
+                                return Err(Error::Server(error));
+                            }
+                        }
+                        Frame::Other(incoming) if messages.contains(&incoming.msg_type) => {
+                            let msg_type = incoming.msg_type.as_str();
+                            let mut result = None;
+                            dispatcher.guarded("message_registry", None, |_| result = messages.dispatch(msg_type, incoming.data));
+                            if let Some(Err(e)) = result {
+                                logger_read.log(LogLevel::Warn, "could not parse message", &[("type", &msg_type), ("error", &e)]);
+                            }
+                        }
+                        Frame::Other(incoming) => {
+                            dispatcher.guarded("on_unknown_message", None, |h| h.on_unknown_message(incoming.msg_type, incoming.data));
+                        }
+                    }
+                }
+                Ok(Message::Close(frame)) => {
+                    close = CloseReason::from_frame(frame);
+                    logger_read.log(LogLevel::Info, "websocket closed", &[("websocket_id", &websocket_id), ("close", &close)]);
+                    break;
+                }
+                Err(e) => {
+                    if let tokio_tungstenite::tungstenite::Error::Capacity(cap) = &e {
+                        logger_read.log(
+                            LogLevel::Error,
+                            "message exceeds websocket limits; raise max_message_size / max_frame_size",
+                            &[("websocket_id", &websocket_id), ("error", &cap)],
+                        );
+                    } else {
+                        logger_read.log(LogLevel::Error, "read error", &[("websocket_id", &websocket_id), ("error", &e)]);
+                    }
+                    flush_batch(&mut batch, &tx_ack, &dispatcher, &acker).await;
+                    guard.returned = true;
+                    return Err(e.into());
+                }
+                Ok(Message::Ping(payload)) => {
+                    stats.ping_received();
+                    // Replaces the reply tungstenite queues on its own, which
+                    // would only be flushed by the next write.
+                    match tx_ack.try_send_control(Outgoing::frame(Message::Pong(payload.clone()))) {
+                        Ok(()) => {}
+                        Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => logger_read.debug("pong skipped, control lane busy"),
+                        Err(e) => logger_read.log(LogLevel::Warn, "pong send error", &[("error", &e)]),
+                    }
+                    dispatcher.guarded("on_ping_received", None, |h| h.on_ping_received(&payload));
+                }
+                Ok(Message::Pong(payload)) => {
+                    let rtt = stats.pong(&payload);
+                    dispatcher.guarded("on_pong_received", None, |h| h.on_pong_received(&payload, rtt));
+                }
+                _ => {}
+            }
+        }
+
+        flush_batch(&mut batch, &tx_ack, &dispatcher, &acker).await;
+        guard.returned = true;
+        self.last_close = Some(close.clone());
+        self.diagnostics.transition("closed", &close);
+        if close.is_normal() {
+            Ok(())
+        } else {
+            Err(Error::Closed(close))
+        }
+    }
+}
+
+// Random so ids stay distinct across the listeners of a pool and across
+// processes sharing a log sink.
+fn connection_id() -> String {
+    use std::hash::BuildHasher;
+    static NEXT: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let n = NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let hash = std::collections::hash_map::RandomState::new().hash_one((std::process::id(), n));
+    format!("conn_{:012x}", hash & 0xffff_ffff_ffff)
+}
+
+// A batch still pending when the connection ends is dispatched all the same;
+// its acks only go out if the socket still takes writes.
+async fn flush_batch(batch: &mut Option<Batch>, tx: &WriteQueue, dispatcher: &Dispatcher, acker: &Acker) {
+    if let Some(batch) = batch {
+        batch.flush(tx, dispatcher, acker).await;
+    }
+}
+
+async fn batch_due(batch: Option<&Batch>, clock: &dyn Clock) {
+    match batch {
+        Some(batch) => batch.due(clock).await,
+        None => std::future::pending().await,
+    }
+}
+
+// Where catch-up replay starts: events created at or after `created`,
+// skipping everything up to and including `after` when it is listed.
+struct CatchUp {
+    created: u64,
+    after: Option<String>,
+}
+
+// Replays events missed while asleep or stopped. They carry no delivery
+// headers since they did not come through the websocket.
+async fn catch_up(api: ApiClient, dispatcher: Dispatcher, expansions: Vec<Expand>, from: CatchUp) {
+    let mut events = match api.list_events_since(from.created as i64).await {
+        Ok(events) => events,
+        Err(e) => {
+            dispatcher.logger.log(LogLevel::Warn, "catch-up failed", &[("error", &e)]);
+            return;
+        }
+    };
+    if let Some(after) = &from.after {
+        if let Some(pos) = events.iter().position(|e| e.get("id").and_then(serde_json::Value::as_str) == Some(after)) {
+            events.drain(..=pos);
+        }
+    }
+    dispatcher.logger.log(LogLevel::Info, "catching up on missed events", &[("count", &events.len())]);
+    let mut batch = Vec::new();
+    for value in events {
+        let parsed: StripeEventPayload = match serde_json::from_value(value.clone()) {
+            Ok(p) => p,
+            Err(e) => {
+                dispatcher.logger.log(LogLevel::Warn, "could not parse caught-up event", &[("error", &e)]);
+                continue;
+            }
+        };
+        let mut evt = WebhookEvent {
+            webhook_id: String::new(),
+            webhook_conversation_id: String::new(),
+            event_payload: value.to_string(),
+            http_headers: Default::default(),
+            endpoint: None,
+            extra: serde_json::json!({}),
+        };
+        if !expansions.is_empty() && dispatcher.live.snapshot().matches_event(parsed.event_type.as_str()) {
+            expand::expand(&api, &expansions, &mut evt, &parsed, dispatcher.logger.as_ref()).await;
+        }
+        // Caught-up events need no acks; the service's answer only decides
+        // whether the cursor moves.
+        #[cfg(feature = "tower")]
+        if let Some(service) = &dispatcher.service {
+            if let Some(admitted) = dispatcher.admit(evt, parsed) {
+                dispatcher.serve_webhook(service, admitted).await.await;
+            }
+            continue;
+        }
+        // Caught-up events need no acks, so batches are only cut by size.
+        match &dispatcher.batching {
+            Some(batching) => {
+                batch.extend(dispatcher.admit(evt, parsed));
+                if batch.len() >= batching.max_size.max(1) {
+                    dispatcher.webhook_batch(std::mem::take(&mut batch));
+                }
+            }
+            None => dispatcher.webhook(evt, parsed),
+        }
+    }
+    dispatcher.webhook_batch(batch);
+}
+
+// Sends the ack of a served event if the service answers Ack; otherwise
+// Stripe redelivers it.
+#[cfg(feature = "tower")]
+async fn ack_when_answered(answer: ServiceAnswer, key: AckKey, tx: WriteQueue, dispatcher: Dispatcher, acker: Acker) {
+    match answer.await {
+        AckDecision::Ack => send_ack(&tx, &dispatcher, &acker, key.fields()).await,
+        AckDecision::NoAck => dispatcher.logger.log(LogLevel::Debug, "not acknowledged", &[("event_id", &key.fields().event_id)]),
+    }
+}
+
+// Fetches an event whose websocket payload was cut off and returns it as the
+// replacement event_payload together with its typed view.
+async fn fetch_full_event(api: &ApiClient, id: &str) -> Result<(String, StripeEventPayload)> {
+    let value = api.fetch_event(id).await?;
+    let parsed = serde_json::from_value(value.clone()).map_err(|e| Error::Other(format!("event {}: {}", id, e)))?;
+    Ok((value.to_string(), parsed))
+}
@@ -0,0 +1,150 @@
+// Shadow mode: periodically compares the events this listener received with
+// Stripe's record of delivering them to the account's webhook endpoints.
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::api::ApiClient;
+use crate::config_file::de_duration;
+use crate::dispatch::Dispatcher;
+use crate::{Clock, DiscrepancyKind, LogLevel, Result, ShadowDiscrepancy, StripeEventPayload};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(300);
+const DEFAULT_SETTLE: Duration = Duration::from_secs(300);
+
+/// Shadow mode, for validating a move from a public webhook endpoint to
+/// local listening. Every `interval` the listener lists the events created
+/// since the previous pass, up to `settle` ago, and compares them with the
+/// events it received: events Stripe delivered to the account's webhook
+/// endpoints but not here, and events received here but not delivered
+/// there, are reported through EventHandler::on_shadow_discrepancy and
+/// counted in ListenerStats.
+///
+/// Stripe reports delivery per event, not per endpoint: an event counts as
+/// delivered there once every endpoint subscribed to it got it. Only events
+/// of the configured account are compared, and only event types the
+/// listener subscribes to count as missing here.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Shadow {
+    #[serde(deserialize_with = "de_duration")]
+    pub interval: Duration,
+    /// How long Stripe gets to deliver an event to the endpoints before it
+    /// is compared.
+    #[serde(deserialize_with = "de_duration")]
+    pub settle: Duration,
+}
+
+impl Default for Shadow {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_INTERVAL,
+            settle: DEFAULT_SETTLE,
+        }
+    }
+}
+
+// Event ids received over the websocket, with their created time and type,
+// until a comparison pass covers them.
+#[derive(Clone)]
+pub(crate) struct ShadowLedger {
+    account: Option<String>,
+    received: Arc<Mutex<BTreeMap<String, (u64, String)>>>,
+}
+
+impl ShadowLedger {
+    pub(crate) fn new(account: Option<String>) -> Self {
+        Self {
+            account,
+            received: Arc::default(),
+        }
+    }
+
+    // Connected accounts' events are not in the configured account's event
+    // list, so they are left out.
+    pub(crate) fn record(&self, event: &StripeEventPayload) {
+        if event.account.is_some() && event.account != self.account {
+            return;
+        }
+        let mut received = self.received.lock().unwrap_or_else(|e| e.into_inner());
+        received.insert(event.id.clone(), (event.created, event.event_type.as_str().to_string()));
+    }
+
+    // Removes and returns what was created before `before`.
+    fn take_before(&self, before: u64) -> BTreeMap<String, (u64, String)> {
+        let mut received = self.received.lock().unwrap_or_else(|e| e.into_inner());
+        let (taken, kept) = std::mem::take(&mut *received).into_iter().partition(|(_, (created, _))| *created < before);
+        *received = kept;
+        taken
+    }
+}
+
+// Runs comparison passes until the task is aborted. A failed pass is
+// retried over the same window on the next one.
+pub(crate) async fn watch(shadow: Shadow, api: ApiClient, ledger: ShadowLedger, dispatcher: Dispatcher, clock: Arc<dyn Clock>) {
+    let mut from = unix_secs(clock.now());
+    loop {
+        clock.sleep(shadow.interval).await;
+        let to = unix_secs(clock.now()).saturating_sub(shadow.settle.as_secs());
+        if to <= from {
+            continue;
+        }
+        match compare(&api, &ledger, &dispatcher, from, to).await {
+            Ok(()) => from = to,
+            Err(e) => dispatcher.logger.log(LogLevel::Warn, "shadow comparison failed", &[("error", &e)]),
+        }
+    }
+}
+
+async fn compare(api: &ApiClient, ledger: &ShadowLedger, dispatcher: &Dispatcher, from: u64, to: u64) -> Result<()> {
+    let events = api.list_events_between(from, to, false).await?;
+    let undelivered: HashSet<String> = api
+        .list_events_between(from, to, true)
+        .await?
+        .iter()
+        .filter_map(|e| e.get("id").and_then(Value::as_str).map(str::to_string))
+        .collect();
+    let mut received = ledger.take_before(to);
+    let live = dispatcher.live.snapshot();
+    let mut found = Vec::new();
+    for event in &events {
+        let (Some(id), Some(event_type)) = (event.get("id").and_then(Value::as_str), event.get("type").and_then(Value::as_str)) else {
+            continue;
+        };
+        let here = received.remove(id).is_some();
+        let there = !undelivered.contains(id);
+        let kind = match (here, there) {
+            (false, true) if live.matches_event(event_type) => DiscrepancyKind::NotReceived,
+            (true, false) => DiscrepancyKind::NotDelivered,
+            _ => continue,
+        };
+        found.push(ShadowDiscrepancy {
+            event_id: id.to_string(),
+            event_type: event_type.to_string(),
+            created: event.get("created").and_then(Value::as_u64).unwrap_or_default(),
+            kind,
+        });
+    }
+    for discrepancy in &found {
+        dispatcher.stats.shadow_discrepancy(discrepancy.kind);
+        let msg = match discrepancy.kind {
+            DiscrepancyKind::NotReceived => "shadow: delivered to webhook endpoints but not received here",
+            DiscrepancyKind::NotDelivered => "shadow: received here but not delivered to webhook endpoints",
+        };
+        dispatcher.logger.log(LogLevel::Warn, msg, &[("event_id", &discrepancy.event_id), ("event_type", &discrepancy.event_type)]);
+        dispatcher.guarded("on_shadow_discrepancy", Some(&discrepancy.event_id), |h| h.on_shadow_discrepancy(discrepancy));
+    }
+    dispatcher.logger.log(
+        LogLevel::Info,
+        "shadow comparison",
+        &[("from", &from), ("to", &to), ("events", &events.len()), ("discrepancies", &found.len())],
+    );
+    Ok(())
+}
+
+fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
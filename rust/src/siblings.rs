@@ -0,0 +1,192 @@
+// Detection of other listeners running with the same device name on this
+// host. Stripe splits deliveries between sessions of one device, so a
+// forgotten second listener silently eats events. There is no API to list
+// or revoke CLI sessions; listeners find each other through a registry
+// directory in the host's temp dir instead, one heartbeat file per running
+// listener, so listeners on other hosts or in other containers go unseen.
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+const HEARTBEAT: Duration = Duration::from_secs(5);
+// A file not touched for this long belongs to a listener that died.
+const STALE_AFTER: Duration = Duration::from_secs(20);
+
+/// What run() does when another listener with the same device name, API
+/// key and account is already running on this host.
+///
+/// Detection is host-local: listeners find each other through files in the
+/// temp dir, so one on another machine or in another container (without a
+/// shared temp dir) is not noticed. Stripe has no API to list or revoke CLI
+/// sessions, so a takeover only stops the older local process; its session
+/// expires on Stripe's side on its own.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateSessions {
+    /// Log a warning naming the other listeners' pids.
+    #[default]
+    Warn,
+    /// Duplicates are intended, e.g. for failover; nothing is logged.
+    Shared,
+    /// Ask the older listeners on this host to stop: their run() returns
+    /// Error::TakenOver. Their sessions are not revoked.
+    Takeover,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Entry {
+    pid: u32,
+    started_at: SystemTime,
+    policy: DuplicateSessions,
+    /// Set by a newer listener that took over.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    taken_over_by: Option<u32>,
+}
+
+// This listener's registry file, removed on drop.
+pub(crate) struct SiblingGuard {
+    path: PathBuf,
+    taken_over_by: Arc<OnceLock<u32>>,
+    heartbeat: tokio::task::JoinHandle<()>,
+}
+
+impl SiblingGuard {
+    /// Registers this listener, applies `policy` to any live siblings and
+    /// starts the heartbeat. A takeover by a newer listener calls
+    /// ListenerHandle::shutdown on this one.
+    pub(crate) fn register(
+        cfg: &Config,
+        policy: DuplicateSessions,
+        shutdown: Arc<tokio::sync::watch::Sender<bool>>,
+    ) -> std::io::Result<SiblingGuard> {
+        let logger = cfg.logger.clone().unwrap();
+        let clock = cfg.clock.clone().unwrap();
+        let dir = registry_dir(cfg);
+        std::fs::create_dir_all(&dir)?;
+        let pid = std::process::id();
+
+        let mut siblings = Vec::new();
+        for file in std::fs::read_dir(&dir)? {
+            let path = file?.path();
+            let fresh = std::fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .is_ok_and(|m| clock.now().duration_since(m).unwrap_or_default() < STALE_AFTER);
+            match read_entry(&path) {
+                Some(entry) if fresh && entry.taken_over_by.is_none() => siblings.push((path, entry)),
+                // Taken over and still shutting down.
+                Some(_) if fresh => {}
+                _ => {
+                    let _ = std::fs::remove_file(&path);
+                }
+            }
+        }
+
+        if !siblings.is_empty() {
+            let pids: Vec<u32> = siblings.iter().map(|(_, e)| e.pid).collect();
+            let device = cfg.device_name.as_deref().unwrap_or_default();
+            match policy {
+                DuplicateSessions::Warn => logger.log(
+                    LogLevel::Warn,
+                    "another listener with this device name is running; events will be split between them",
+                    &[("device_name", &device), ("pids", &format!("{:?}", pids))],
+                ),
+                DuplicateSessions::Shared => logger.log(LogLevel::Debug, "sharing device with other listeners", &[("pids", &format!("{:?}", pids))]),
+                DuplicateSessions::Takeover => {
+                    for (path, mut entry) in siblings {
+                        entry.taken_over_by = Some(pid);
+                        write_entry(&path, &entry)?;
+                    }
+                    logger.log(LogLevel::Info, "took over from older listeners", &[("device_name", &device), ("pids", &format!("{:?}", pids))]);
+                }
+            }
+        }
+
+        let path = dir.join(format!("{}-{}.json", pid, unix_nanos(clock.now())));
+        write_entry(
+            &path,
+            &Entry {
+                pid,
+                started_at: clock.now(),
+                policy,
+                taken_over_by: None,
+            },
+        )?;
+
+        let taken_over_by = Arc::new(OnceLock::new());
+        let heartbeat = tokio::spawn(heartbeat(path.clone(), taken_over_by.clone(), shutdown, logger, clock));
+        Ok(SiblingGuard {
+            path,
+            taken_over_by,
+            heartbeat,
+        })
+    }
+
+    pub(crate) fn taken_over_by(&self) -> Option<u32> {
+        self.taken_over_by.get().copied()
+    }
+}
+
+impl Drop for SiblingGuard {
+    fn drop(&mut self) {
+        self.heartbeat.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+async fn heartbeat(
+    path: PathBuf,
+    taken_over_by: Arc<OnceLock<u32>>,
+    shutdown: Arc<tokio::sync::watch::Sender<bool>>,
+    logger: Arc<dyn Logger>,
+    clock: Arc<dyn Clock>,
+) {
+    loop {
+        if let Some(pid) = read_entry(&path).and_then(|e| e.taken_over_by) {
+            logger.log(LogLevel::Warn, "taken over by a newer listener; shutting down", &[("pid", &pid)]);
+            let _ = taken_over_by.set(pid);
+            shutdown.send_replace(true);
+            return;
+        }
+        let touched = std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|f| f.set_modified(clock.now()));
+        if let Err(e) = touched {
+            logger.log(LogLevel::Debug, "sibling heartbeat failed", &[("path", &path.display()), ("error", &e)]);
+        }
+        clock.sleep(HEARTBEAT).await;
+    }
+}
+
+// One directory per device name, account and API key, under the temp dir.
+// The key is hashed so it never touches the disk.
+fn registry_dir(cfg: &Config) -> PathBuf {
+    let scope = format!(
+        "{}\0{}\0{}",
+        cfg.device_name.as_deref().unwrap_or_default(),
+        cfg.stripe_account.as_deref().unwrap_or_default(),
+        cfg.api_key.expose()
+    );
+    std::env::temp_dir().join("stripelistener").join(format!("{:016x}", fnv1a(scope.as_bytes())))
+}
+
+// Stable across builds, unlike DefaultHasher, so different binaries agree.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+fn unix_nanos(t: SystemTime) -> u128 {
+    t.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default()
+}
+
+fn read_entry(path: &Path) -> Option<Entry> {
+    serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+}
+
+fn write_entry(path: &Path, entry: &Entry) -> std::io::Result<()> {
+    std::fs::write(path, serde_json::to_vec(entry).map_err(std::io::Error::other)?)
+}
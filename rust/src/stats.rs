@@ -0,0 +1,340 @@
+// Connection counters and liveness timestamps exposed through ListenerHandle,
+// and the SessionReport summarizing a run.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::{Clock, DiscrepancyKind, ServerError};
+
+/// Point-in-time copy of a listener's counters. Counters accumulate across
+/// reconnects; `reconnect_attempt` is 0 while connected.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ListenerStats {
+    pub last_ping_sent: Option<SystemTime>,
+    pub last_pong_received: Option<SystemTime>,
+    /// Last ping from the server; each one is answered with a pong.
+    pub last_ping_received: Option<SystemTime>,
+    /// Round trip of the most recent ping/pong pair.
+    pub rtt: Option<Duration>,
+    /// Last time any frame arrived from the server.
+    pub last_activity: Option<SystemTime>,
+    /// Seconds the clock was ahead of Stripe's signing time of the last
+    /// delivery (negative when behind), delivery latency included.
+    pub clock_skew_secs: Option<i64>,
+    pub events_received: u64,
+    pub acks_sent: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub reconnect_attempt: u32,
+    /// Handler callbacks that panicked; see EventHandler::on_handler_panic.
+    pub handler_panics: u64,
+    /// Events dropped by the event-type filter, LiveConfig::filter or
+    /// Config::predicate.
+    pub events_filtered_out: u64,
+    /// Events dropped by LiveConfig::sampling.
+    pub events_sampled_out: u64,
+    /// Events older than Config::max_event_age; see
+    /// EventHandler::on_stale_event.
+    pub events_stale: u64,
+    /// Error messages received from the server.
+    pub server_errors: u64,
+    pub last_server_error: Option<ServerError>,
+    /// Batches the handler rejected; see Config::batching.
+    pub batches_rejected: u64,
+    /// Differences found by shadow mode; see Config::shadow.
+    pub shadow_not_received: u64,
+    pub shadow_not_delivered: u64,
+    /// The current or most recent connection, as in its log lines.
+    pub connection_id: Option<String>,
+    pub device_name: Option<String>,
+    pub stripe_account: Option<String>,
+}
+
+/// Summary of a listener's run, returned by StripeListener::run and
+/// available from ListenerHandle::report while it runs. Its Display is the
+/// multi-line summary the CLI prints on exit.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct SessionReport {
+    /// When run() was first called.
+    pub started: Option<SystemTime>,
+    pub duration: Duration,
+    pub events_received: u64,
+    /// Parsed events by type, counted before filtering and sampling.
+    pub events_by_type: BTreeMap<String, u64>,
+    /// Events by the tenant Config::tenant_resolver gave them, counted
+    /// before the tenant filter and sampling.
+    pub events_by_tenant: BTreeMap<String, u64>,
+    pub events_filtered_out: u64,
+    pub events_sampled_out: u64,
+    pub events_stale: u64,
+    pub acks_sent: u64,
+    /// Acks that could not be built or written; see EventHandler::on_ack_failed.
+    pub acks_failed: u64,
+    /// Deliveries to forward routes that got a 2xx.
+    pub forwarded: u64,
+    pub forward_failed: u64,
+    /// Final response status of each forward delivery; deliveries that got
+    /// no response are only in forward_failed.
+    pub forward_statuses: BTreeMap<u16, u64>,
+    /// Mean ForwardResult::duration over all forward deliveries.
+    pub average_forward_latency: Option<Duration>,
+    /// Times the listener reconnected or re-authorized after a failure.
+    pub reconnects: u64,
+    pub handler_panics: u64,
+    pub server_errors: u64,
+}
+
+impl fmt::Display for SessionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "session report ({:?})", self.duration)?;
+        write!(
+            f,
+            "  events received: {} ({} filtered out, {} sampled out",
+            self.events_received, self.events_filtered_out, self.events_sampled_out
+        )?;
+        if self.events_stale > 0 {
+            write!(f, ", {} stale", self.events_stale)?;
+        }
+        writeln!(f, ")")?;
+        for (event_type, count) in &self.events_by_type {
+            writeln!(f, "    {}: {}", event_type, count)?;
+        }
+        if !self.events_by_tenant.is_empty() {
+            writeln!(f, "  events by tenant:")?;
+            for (tenant, count) in &self.events_by_tenant {
+                writeln!(f, "    {}: {}", tenant, count)?;
+            }
+        }
+        writeln!(f, "  acks: {} sent, {} failed", self.acks_sent, self.acks_failed)?;
+        write!(f, "  forwarded: {} ok, {} failed", self.forwarded, self.forward_failed)?;
+        if !self.forward_statuses.is_empty() {
+            let statuses: Vec<String> = self.forward_statuses.iter().map(|(status, count)| format!("{}: {}", status, count)).collect();
+            write!(f, " ({})", statuses.join(", "))?;
+        }
+        if let Some(latency) = self.average_forward_latency {
+            write!(f, ", average {:?}", latency)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "  reconnects: {}", self.reconnects)?;
+        write!(f, "  handler panics: {}, server errors: {}", self.handler_panics, self.server_errors)
+    }
+}
+
+// Counters only the SessionReport needs, kept out of ListenerStats so its
+// snapshots stay cheap.
+#[derive(Default)]
+struct Tally {
+    started: Option<SystemTime>,
+    events_by_type: BTreeMap<String, u64>,
+    events_by_tenant: BTreeMap<String, u64>,
+    acks_failed: u64,
+    forwarded: u64,
+    forward_failed: u64,
+    forward_statuses: BTreeMap<u16, u64>,
+    forward_time: Duration,
+    reconnects: u64,
+}
+
+#[derive(Clone)]
+pub(crate) struct StatsRecorder {
+    inner: Arc<Mutex<ListenerStats>>,
+    tally: Arc<Mutex<Tally>>,
+    clock: Arc<dyn Clock>,
+    // Pings carry the elapsed nanos since `epoch`, echoed back in the pong.
+    epoch: Instant,
+}
+
+impl StatsRecorder {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(ListenerStats::default())),
+            tally: Arc::default(),
+            epoch: clock.instant(),
+            clock,
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.clock.instant().saturating_duration_since(self.epoch)
+    }
+
+    fn with(&self, f: impl FnOnce(&mut ListenerStats)) {
+        f(&mut self.inner.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    fn tally(&self, f: impl FnOnce(&mut Tally)) {
+        f(&mut self.tally.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    pub(crate) fn snapshot(&self) -> ListenerStats {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub(crate) fn report(&self) -> SessionReport {
+        let stats = self.snapshot();
+        let tally = self.tally.lock().unwrap_or_else(|e| e.into_inner());
+        let deliveries = tally.forwarded + tally.forward_failed;
+        SessionReport {
+            started: tally.started,
+            duration: tally.started.and_then(|s| self.clock.now().duration_since(s).ok()).unwrap_or_default(),
+            events_received: stats.events_received,
+            events_by_type: tally.events_by_type.clone(),
+            events_by_tenant: tally.events_by_tenant.clone(),
+            events_filtered_out: stats.events_filtered_out,
+            events_sampled_out: stats.events_sampled_out,
+            events_stale: stats.events_stale,
+            acks_sent: stats.acks_sent,
+            acks_failed: tally.acks_failed,
+            forwarded: tally.forwarded,
+            forward_failed: tally.forward_failed,
+            forward_statuses: tally.forward_statuses.clone(),
+            average_forward_latency: (deliveries > 0).then(|| tally.forward_time / deliveries as u32),
+            reconnects: tally.reconnects,
+            handler_panics: stats.handler_panics,
+            server_errors: stats.server_errors,
+        }
+    }
+
+    pub(crate) fn run_started(&self) {
+        let now = self.clock.now();
+        self.tally(|t| {
+            t.started.get_or_insert(now);
+        });
+    }
+
+    /// Payload for an outgoing ping; records the send time.
+    pub(crate) fn ping_payload(&self) -> Vec<u8> {
+        let now = self.clock.now();
+        self.with(|s| s.last_ping_sent = Some(now));
+        (self.elapsed().as_nanos() as u64).to_be_bytes().to_vec()
+    }
+
+    /// Records a pong and returns its round trip; payloads not produced by
+    /// ping_payload leave rtt as is.
+    pub(crate) fn pong(&self, payload: &[u8]) -> Option<Duration> {
+        let rtt = <[u8; 8]>::try_from(payload).ok().and_then(|b| {
+            let sent = Duration::from_nanos(u64::from_be_bytes(b));
+            self.elapsed().checked_sub(sent)
+        });
+        let now = self.clock.now();
+        self.with(|s| {
+            s.last_pong_received = Some(now);
+            if rtt.is_some() {
+                s.rtt = rtt;
+            }
+        });
+        rtt
+    }
+
+    pub(crate) fn ping_received(&self) {
+        let now = self.clock.now();
+        self.with(|s| s.last_ping_received = Some(now));
+    }
+
+    pub(crate) fn frame_in(&self, bytes: usize) {
+        let now = self.clock.now();
+        self.with(|s| {
+            s.last_activity = Some(now);
+            s.bytes_in += bytes as u64;
+        });
+    }
+
+    pub(crate) fn frame_out(&self, bytes: usize) {
+        self.with(|s| s.bytes_out += bytes as u64);
+    }
+
+    pub(crate) fn clock_skew(&self, secs: i64) {
+        self.with(|s| s.clock_skew_secs = Some(secs));
+    }
+
+    pub(crate) fn event_received(&self) {
+        self.with(|s| s.events_received += 1);
+    }
+
+    pub(crate) fn ack_sent(&self) {
+        self.with(|s| s.acks_sent += 1);
+    }
+
+    pub(crate) fn event_type(&self, event_type: &str) {
+        self.tally(|t| *t.events_by_type.entry(event_type.to_string()).or_default() += 1);
+    }
+
+    pub(crate) fn tenant_event(&self, tenant: &str) {
+        self.tally(|t| *t.events_by_tenant.entry(tenant.to_string()).or_default() += 1);
+    }
+
+    pub(crate) fn ack_failed(&self) {
+        self.tally(|t| t.acks_failed += 1);
+    }
+
+    #[cfg(feature = "forwarder")]
+    pub(crate) fn forward_result(&self, result: &crate::ForwardResult) {
+        self.tally(|t| {
+            match result.is_success() {
+                true => t.forwarded += 1,
+                false => t.forward_failed += 1,
+            }
+            if let Some(status) = result.status {
+                *t.forward_statuses.entry(status).or_default() += 1;
+            }
+            t.forward_time += result.duration;
+        });
+    }
+
+    pub(crate) fn reconnecting(&self) {
+        self.tally(|t| t.reconnects += 1);
+    }
+
+    pub(crate) fn filtered_out(&self) {
+        self.with(|s| s.events_filtered_out += 1);
+    }
+
+    pub(crate) fn sampled_out(&self) {
+        self.with(|s| s.events_sampled_out += 1);
+    }
+
+    pub(crate) fn stale_event(&self) {
+        self.with(|s| s.events_stale += 1);
+    }
+
+    pub(crate) fn batch_rejected(&self) {
+        self.with(|s| s.batches_rejected += 1);
+    }
+
+    pub(crate) fn shadow_discrepancy(&self, kind: DiscrepancyKind) {
+        self.with(|s| match kind {
+            DiscrepancyKind::NotReceived => s.shadow_not_received += 1,
+            DiscrepancyKind::NotDelivered => s.shadow_not_delivered += 1,
+        });
+    }
+
+    pub(crate) fn server_error(&self, error: &ServerError) {
+        self.with(|s| {
+            s.server_errors += 1;
+            s.last_server_error = Some(error.clone());
+        });
+    }
+
+    pub(crate) fn handler_panic(&self) {
+        self.with(|s| s.handler_panics += 1);
+    }
+
+    pub(crate) fn set_labels(&self, device_name: Option<String>, stripe_account: Option<String>) {
+        self.with(|s| {
+            s.device_name = device_name;
+            s.stripe_account = stripe_account;
+        });
+    }
+
+    pub(crate) fn set_connection_id(&self, id: &str) {
+        self.with(|s| s.connection_id = Some(id.to_string()));
+    }
+
+    pub(crate) fn set_reconnect_attempt(&self, attempt: u32) {
+        self.with(|s| s.reconnect_attempt = attempt);
+    }
+}
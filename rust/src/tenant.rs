@@ -0,0 +1,118 @@
+// Multi-tenant dispatch: a TenantResolver maps each webhook event to the
+// tenant it belongs to, and the handler receives the tenant with the event.
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::{SecretString, StripeEventPayload, WebhookEvent};
+
+/// The tenant an event belongs to, as returned by a TenantResolver and
+/// passed to EventHandler::on_tenant_webhook_event.
+#[derive(Clone)]
+pub struct Tenant {
+    /// Used in logs, SessionReport::events_by_tenant and
+    /// EventFilter::tenants.
+    pub id: String,
+    /// The tenant's restricted key, for API calls made on its behalf.
+    pub api_key: Option<SecretString>,
+    context: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl Tenant {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            api_key: None,
+            context: None,
+        }
+    }
+
+    pub fn with_api_key(mut self, api_key: impl Into<SecretString>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Attaches whatever else the handler needs for this tenant; read it
+    /// back with `context`.
+    pub fn with_context<T: Any + Send + Sync>(mut self, context: T) -> Self {
+        self.context = Some(Arc::new(context));
+        self
+    }
+
+    /// The value attached with `with_context`, if it is a `T`.
+    pub fn context<T: Any>(&self) -> Option<&T> {
+        self.context.as_deref()?.downcast_ref()
+    }
+}
+
+impl fmt::Debug for Tenant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tenant")
+            .field("id", &self.id)
+            .field("api_key", &self.api_key)
+            .field("context", &self.context.is_some())
+            .finish()
+    }
+}
+
+/// Maps webhook events to tenants; see Config::tenant_resolver. Events it
+/// returns None for are dispatched without one. Implemented for `Fn`
+/// closures.
+pub trait TenantResolver: Send + Sync {
+    fn resolve(&self, evt: &WebhookEvent, parsed: &StripeEventPayload) -> Option<Tenant>;
+}
+
+impl<F> TenantResolver for F
+where
+    F: Fn(&WebhookEvent, &StripeEventPayload) -> Option<Tenant> + Send + Sync,
+{
+    fn resolve(&self, evt: &WebhookEvent, parsed: &StripeEventPayload) -> Option<Tenant> {
+        self(evt, parsed)
+    }
+}
+
+enum TenantKey {
+    Account,
+    Metadata(String),
+}
+
+/// A TenantResolver looking tenants up by the event's connected account,
+/// or by a metadata value of the event's object.
+pub struct TenantMap {
+    key: TenantKey,
+    tenants: HashMap<String, Tenant>,
+}
+
+impl TenantMap {
+    /// Tenants keyed by connected account (`acct_...`).
+    pub fn by_account(tenants: impl IntoIterator<Item = (String, Tenant)>) -> Self {
+        Self {
+            key: TenantKey::Account,
+            tenants: tenants.into_iter().collect(),
+        }
+    }
+
+    /// Tenants keyed by the value of `data.object.metadata[key]`.
+    pub fn by_metadata(key: impl Into<String>, tenants: impl IntoIterator<Item = (String, Tenant)>) -> Self {
+        Self {
+            key: TenantKey::Metadata(key.into()),
+            tenants: tenants.into_iter().collect(),
+        }
+    }
+}
+
+impl TenantResolver for TenantMap {
+    fn resolve(&self, evt: &WebhookEvent, parsed: &StripeEventPayload) -> Option<Tenant> {
+        let tenant = match &self.key {
+            TenantKey::Account => self.tenants.get(parsed.account.as_deref()?),
+            TenantKey::Metadata(key) => {
+                let payload: Value = serde_json::from_str(&evt.event_payload).ok()?;
+                self.tenants.get(payload["data"]["object"]["metadata"][key.as_str()].as_str()?)
+            }
+        };
+        tenant.cloned()
+    }
+}
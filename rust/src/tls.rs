@@ -0,0 +1,91 @@
+// TLS settings shared by the REST client, the websocket and the forwarder,
+// for networks that intercept TLS with their own CA.
+use std::path::Path;
+
+use serde::Deserialize;
+use tokio_tungstenite::Connector;
+
+use crate::{Error, Result};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsVersion {
+    #[serde(rename = "1.0")]
+    Tls10,
+    #[serde(rename = "1.1")]
+    Tls11,
+    #[serde(rename = "1.2")]
+    Tls12,
+}
+
+/// TLS overrides applied to every outbound connection (Stripe API,
+/// websocket, forward routes). The default trusts the system store only.
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    /// Accept any certificate and hostname. UNSAFE: this disables protection
+    /// against interception entirely; use only behind a trusted corporate
+    /// proxy in development, never in production.
+    pub insecure_skip_verify: bool,
+    /// PEM-encoded CA certificates trusted in addition to the system store,
+    /// e.g. the corporate proxy's root.
+    pub extra_root_certs: Vec<Vec<u8>>,
+    pub min_version: Option<TlsVersion>,
+}
+
+impl TlsOptions {
+    /// Reads a PEM file and adds it to `extra_root_certs`.
+    pub fn add_root_cert_file(&mut self, path: impl AsRef<Path>) -> Result<&mut Self> {
+        let path = path.as_ref();
+        let pem = std::fs::read(path).map_err(|e| Error::Tls(format!("{}: {}", path.display(), e)))?;
+        self.extra_root_certs.push(pem);
+        Ok(self)
+    }
+
+    fn is_default(&self) -> bool {
+        !self.insecure_skip_verify && self.extra_root_certs.is_empty() && self.min_version.is_none()
+    }
+
+    pub(crate) fn http_client_builder(&self) -> Result<reqwest::ClientBuilder> {
+        let mut builder = reqwest::Client::builder();
+        if self.insecure_skip_verify {
+            builder = builder.danger_accept_invalid_certs(true).danger_accept_invalid_hostnames(true);
+        }
+        for pem in &self.extra_root_certs {
+            let cert = reqwest::Certificate::from_pem(pem).map_err(|e| Error::Tls(format!("invalid root certificate: {}", e)))?;
+            builder = builder.add_root_certificate(cert);
+        }
+        if let Some(v) = self.min_version {
+            builder = builder.min_tls_version(match v {
+                TlsVersion::Tls10 => reqwest::tls::Version::TLS_1_0,
+                TlsVersion::Tls11 => reqwest::tls::Version::TLS_1_1,
+                TlsVersion::Tls12 => reqwest::tls::Version::TLS_1_2,
+            });
+        }
+        Ok(builder)
+    }
+
+    pub(crate) fn http_client(&self) -> Result<reqwest::Client> {
+        Ok(self.http_client_builder()?.build()?)
+    }
+
+    /// None when the defaults apply, so the websocket uses its stock connector.
+    pub(crate) fn ws_connector(&self) -> Result<Option<Connector>> {
+        if self.is_default() {
+            return Ok(None);
+        }
+        let mut builder = native_tls::TlsConnector::builder();
+        if self.insecure_skip_verify {
+            builder.danger_accept_invalid_certs(true).danger_accept_invalid_hostnames(true);
+        }
+        for pem in &self.extra_root_certs {
+            let cert = native_tls::Certificate::from_pem(pem).map_err(|e| Error::Tls(format!("invalid root certificate: {}", e)))?;
+            builder.add_root_certificate(cert);
+        }
+        builder.min_protocol_version(self.min_version.map(|v| match v {
+            TlsVersion::Tls10 => native_tls::Protocol::Tlsv10,
+            TlsVersion::Tls11 => native_tls::Protocol::Tlsv11,
+            TlsVersion::Tls12 => native_tls::Protocol::Tlsv12,
+        }));
+        let connector = builder.build().map_err(|e| Error::Tls(e.to_string()))?;
+        Ok(Some(Connector::NativeTls(connector)))
+    }
+}
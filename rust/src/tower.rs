@@ -0,0 +1,107 @@
+// Dispatch to a tower Service: with Config::service set, events that pass
+// the filters go to the service instead of the EventHandler, and its
+// AckDecision says whether they are acknowledged.
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::sync::Mutex;
+use tower_service::Service;
+
+use crate::{StripeEventPayload, Tenant, V2Event, V2EventPayload, WebhookEvent};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+type Call = Pin<Box<dyn Future<Output = Result<AckDecision, BoxError>> + Send>>;
+
+// The service's answer for one event, with errors already taken as NoAck.
+pub(crate) type ServiceAnswer = Pin<Box<dyn Future<Output = AckDecision> + Send>>;
+
+/// An event handed to the service set as Config::service.
+#[derive(Debug, Clone)]
+pub enum ListenerEvent {
+    /// `tenant` is set when Config::tenant_resolver resolved one.
+    Webhook {
+        evt: WebhookEvent,
+        parsed: StripeEventPayload,
+        tenant: Option<Tenant>,
+    },
+    V2 { evt: V2Event, parsed: V2EventPayload },
+}
+
+impl ListenerEvent {
+    pub fn id(&self) -> &str {
+        match self {
+            ListenerEvent::Webhook { parsed, .. } => &parsed.id,
+            ListenerEvent::V2 { parsed, .. } => &parsed.id,
+        }
+    }
+
+    pub fn event_type(&self) -> &str {
+        match self {
+            ListenerEvent::Webhook { parsed, .. } => parsed.event_type.as_str(),
+            ListenerEvent::V2 { parsed, .. } => &parsed.event_type,
+        }
+    }
+}
+
+/// What the service wants done with an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckDecision {
+    Ack,
+    /// Leave the event unacknowledged so Stripe redelivers it.
+    NoAck,
+}
+
+trait DynService: Send {
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>>;
+    fn call(&mut self, event: ListenerEvent) -> Call;
+}
+
+impl<S> DynService for S
+where
+    S: Service<ListenerEvent, Response = AckDecision> + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        Service::poll_ready(self, cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, event: ListenerEvent) -> Call {
+        let response = Service::call(self, event);
+        Box::pin(async move { response.await.map_err(Into::into) })
+    }
+}
+
+/// A `tower::Service<ListenerEvent, Response = AckDecision>` for
+/// Config::service, so tower middleware (timeouts, retries, rate limits,
+/// load shedding) can wrap event delivery.
+///
+/// One instance serves every connection of the listener. Events are passed
+/// to it in delivery order, each once `poll_ready` succeeds; the connection
+/// reads nothing while it waits, so a service that is not ready holds up
+/// delivery. Responses are awaited concurrently and each event is
+/// acknowledged when its response is AckDecision::Ack. An error, from
+/// `poll_ready` or the response, leaves the event unacknowledged.
+#[derive(Clone)]
+pub struct EventService(Arc<Mutex<Box<dyn DynService>>>);
+
+impl EventService {
+    pub fn new<S>(service: S) -> Self
+    where
+        S: Service<ListenerEvent, Response = AckDecision> + Send + 'static,
+        S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+        S::Future: Send + 'static,
+    {
+        Self(Arc::new(Mutex::new(Box::new(service))))
+    }
+
+    // Waits for the service to be ready and calls it; the returned future
+    // is its response.
+    pub(crate) async fn call(&self, event: ListenerEvent) -> Result<Call, BoxError> {
+        let mut service = self.0.lock().await;
+        poll_fn(|cx| service.poll_ready(cx)).await?;
+        Ok(service.call(event))
+    }
+}
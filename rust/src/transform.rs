@@ -0,0 +1,167 @@
+// Payload rewriting applied before events reach the handler or forward routes.
+use std::sync::Arc;
+
+use serde_json::Value;
+
+/// Rewrites an event payload in place. Runs after filtering and before
+/// dispatch, so both the handler and forward routes see the result.
+///
+/// Stripe's Stripe-Signature no longer matches a changed payload, so
+/// forward and mirror routes without their own ForwardRoute::signing get
+/// the delivery re-signed with the session's secret (feature `types`;
+/// without it, a transform cannot be combined with forward routes).
+/// Replays use the secret given to Replayer::signing_secret or
+/// NdjsonInput::signing_secret, and without one forward such deliveries
+/// with no Stripe-Signature. A payload the transform leaves unchanged is
+/// passed on byte for byte.
+pub trait Transform: Send + Sync {
+    fn apply(&self, payload: &mut Value);
+}
+
+impl<F> Transform for F
+where
+    F: Fn(&mut Value) + Send + Sync,
+{
+    fn apply(&self, payload: &mut Value) {
+        self(payload)
+    }
+}
+
+/// Runs transforms in the order they were added.
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    stages: Vec<Arc<dyn Transform>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn then(mut self, stage: impl Transform + 'static) -> Self {
+        self.stages.push(Arc::new(stage));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+}
+
+impl Transform for Pipeline {
+    fn apply(&self, payload: &mut Value) {
+        for stage in &self.stages {
+            stage.apply(payload);
+        }
+    }
+}
+
+/// Masks or removes fields addressed by JSON pointers (RFC 6901), e.g.
+/// `/data/object/email`. A `*` segment matches every array element or object
+/// member, so `/data/object/lines/data/*/metadata` reaches each line item.
+/// Pointers that do not resolve are ignored.
+#[derive(Debug, Clone)]
+pub struct Redact {
+    pointers: Vec<Vec<String>>,
+    replacement: Option<Value>,
+}
+
+impl Redact {
+    /// Replaces matched values with `"[REDACTED]"`.
+    pub fn new<I, S>(pointers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            pointers: pointers.into_iter().map(|p| parse_pointer(p.as_ref())).collect(),
+            replacement: Some(Value::String("[REDACTED]".to_string())),
+        }
+    }
+
+    /// Replaces matched values with `value` instead of the default marker.
+    pub fn with_replacement(mut self, value: Value) -> Self {
+        self.replacement = Some(value);
+        self
+    }
+
+    /// Removes matched fields (or array elements) entirely.
+    pub fn removing(mut self) -> Self {
+        self.replacement = None;
+        self
+    }
+}
+
+impl Transform for Redact {
+    fn apply(&self, payload: &mut Value) {
+        for tokens in &self.pointers {
+            redact_at(payload, tokens, self.replacement.as_ref());
+        }
+    }
+}
+
+fn parse_pointer(pointer: &str) -> Vec<String> {
+    pointer
+        .split('/')
+        .skip(1)
+        .map(|t| t.replace("~1", "/").replace("~0", "~"))
+        .collect()
+}
+
+fn redact_at(node: &mut Value, tokens: &[String], replacement: Option<&Value>) {
+    let (head, rest) = match tokens.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    if rest.is_empty() {
+        match (node, replacement) {
+            (Value::Object(map), Some(r)) => {
+                if head == "*" {
+                    map.values_mut().for_each(|v| *v = r.clone());
+                } else if let Some(v) = map.get_mut(head) {
+                    *v = r.clone();
+                }
+            }
+            (Value::Object(map), None) => {
+                if head == "*" {
+                    map.clear();
+                } else {
+                    map.remove(head);
+                }
+            }
+            (Value::Array(items), Some(r)) => {
+                if head == "*" {
+                    items.iter_mut().for_each(|v| *v = r.clone());
+                } else if let Some(v) = head.parse::<usize>().ok().and_then(|i| items.get_mut(i)) {
+                    *v = r.clone();
+                }
+            }
+            (Value::Array(items), None) => {
+                if head == "*" {
+                    items.clear();
+                } else if let Some(i) = head.parse::<usize>().ok().filter(|i| *i < items.len()) {
+                    items.remove(i);
+                }
+            }
+            _ => {}
+        }
+        return;
+    }
+
+    match node {
+        Value::Object(map) if head == "*" => map.values_mut().for_each(|v| redact_at(v, rest, replacement)),
+        Value::Object(map) => {
+            if let Some(v) = map.get_mut(head) {
+                redact_at(v, rest, replacement);
+            }
+        }
+        Value::Array(items) if head == "*" => items.iter_mut().for_each(|v| redact_at(v, rest, replacement)),
+        Value::Array(items) => {
+            if let Some(v) = head.parse::<usize>().ok().and_then(|i| items.get_mut(i)) {
+                redact_at(v, rest, replacement);
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,336 @@
+// The websocket's write side: prioritized write lanes, acks, close frames,
+// tracking of in-flight deliveries for shutdown, and the guards that end a
+// connection's tasks when its future is dropped.
+#[cfg(any(feature = "forwarder", feature = "tower"))]
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::dispatch::Dispatcher;
+use crate::*;
+
+// Write queue capacities; see WriteQueue.
+const CONTROL_QUEUE: usize = 8;
+const DATA_QUEUE: usize = 32;
+
+impl CloseReason {
+    pub(crate) fn from_frame(frame: Option<tokio_tungstenite::tungstenite::protocol::CloseFrame<'_>>) -> Self {
+        match frame {
+            Some(frame) => CloseReason {
+                code: u16::from(frame.code),
+                reason: frame.reason.into_owned(),
+            },
+            None => CloseReason {
+                code: Self::NO_STATUS,
+                reason: String::new(),
+            },
+        }
+    }
+
+    pub(crate) fn normal() -> Self {
+        CloseReason {
+            code: u16::from(CloseCode::Normal),
+            reason: String::new(),
+        }
+    }
+
+    pub(crate) fn abnormal() -> Self {
+        CloseReason {
+            code: Self::ABNORMAL,
+            reason: "connection dropped without close frame".to_string(),
+        }
+    }
+}
+
+// A frame for the write task. ACK frames carry their ids so the outcome of
+// the write can be reported.
+pub(crate) struct Outgoing {
+    pub(crate) message: Message,
+    pub(crate) ack: Option<PendingAck>,
+}
+
+pub(crate) struct PendingAck {
+    pub(crate) event_id: String,
+    pub(crate) conversation_id: String,
+}
+
+impl Outgoing {
+    pub(crate) fn frame(message: Message) -> Self {
+        Self { message, ack: None }
+    }
+}
+
+// Sending side of the write task. Control frames (ping, pong, close) have
+// their own lane, which the write task always drains first, so they never
+// wait behind a backlog of ACKs.
+#[derive(Clone)]
+pub(crate) struct WriteQueue {
+    pub(crate) control: tokio::sync::mpsc::Sender<Outgoing>,
+    pub(crate) data: tokio::sync::mpsc::Sender<Outgoing>,
+}
+
+pub(crate) struct WriteLanes {
+    pub(crate) control: tokio::sync::mpsc::Receiver<Outgoing>,
+    pub(crate) data: tokio::sync::mpsc::Receiver<Outgoing>,
+}
+
+impl WriteQueue {
+    pub(crate) fn new() -> (Self, WriteLanes) {
+        let (control_tx, control_rx) = tokio::sync::mpsc::channel(CONTROL_QUEUE);
+        let (data_tx, data_rx) = tokio::sync::mpsc::channel(DATA_QUEUE);
+        (
+            Self {
+                control: control_tx,
+                data: data_tx,
+            },
+            WriteLanes {
+                control: control_rx,
+                data: data_rx,
+            },
+        )
+    }
+
+    pub(crate) async fn send(&self, out: Outgoing) -> std::result::Result<(), tokio::sync::mpsc::error::SendError<Outgoing>> {
+        match out.message {
+            Message::Ping(_) | Message::Pong(_) | Message::Close(_) => self.control.send(out).await,
+            _ => self.data.send(out).await,
+        }
+    }
+
+    // Queues a control frame unless the control lane is already full.
+    pub(crate) fn try_send_control(&self, out: Outgoing) -> std::result::Result<(), tokio::sync::mpsc::error::TrySendError<Outgoing>> {
+        self.control.try_send(out)
+    }
+
+    // Queues a normal close behind everything on the data lane, so queued
+    // ACKs are written first. The write task stops after sending it.
+    pub(crate) async fn close_after_data(&self) {
+        let _ = self.data.send(normal_close()).await;
+    }
+
+    // close_after_data for when waiting is not possible; with the data lane
+    // full the close goes ahead of the queued ACKs.
+    pub(crate) fn try_close(&self) {
+        if let Err(e) = self.data.try_send(normal_close()) {
+            let _ = self.control.try_send(e.into_inner());
+        }
+    }
+}
+
+fn normal_close() -> Outgoing {
+    let frame = tokio_tungstenite::tungstenite::protocol::CloseFrame {
+        code: CloseCode::Normal,
+        reason: "".into(),
+    };
+    Outgoing::frame(Message::Close(Some(frame)))
+}
+
+// Resolves once ListenerHandle::shutdown has been called.
+pub(crate) async fn stopped(shutdown: &mut tokio::sync::watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stop| *stop).await;
+}
+
+// Counts spawned deliveries so shutdown can wait for them to finish.
+#[derive(Clone, Default)]
+pub(crate) struct InFlight(Arc<(AtomicUsize, tokio::sync::Notify)>);
+
+impl InFlight {
+    #[cfg(any(feature = "forwarder", feature = "tower"))]
+    pub(crate) fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        self.0 .0.fetch_add(1, Ordering::SeqCst);
+        let inflight = self.clone();
+        tokio::spawn(async move {
+            task.await;
+            if inflight.0 .0.fetch_sub(1, Ordering::SeqCst) == 1 {
+                inflight.0 .1.notify_waiters();
+            }
+        });
+    }
+
+    pub(crate) async fn idle(&self) {
+        loop {
+            let notified = self.0 .1.notified();
+            if self.0 .0.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+// Background tasks that must not outlive the run() or connect() that
+// spawned them, aborted by an AbortOnDrop.
+#[derive(Clone, Default)]
+pub(crate) struct TaskSet(Arc<Mutex<Vec<tokio::task::AbortHandle>>>);
+
+impl TaskSet {
+    pub(crate) fn spawn(&self, task: impl std::future::Future<Output = ()> + Send + 'static) {
+        let handle = tokio::spawn(task).abort_handle();
+        let mut tasks = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.retain(|t| !t.is_finished());
+        tasks.push(handle);
+    }
+
+    pub(crate) fn abort_on_drop(&self) -> AbortOnDrop {
+        AbortOnDrop(self.clone())
+    }
+}
+
+pub(crate) struct AbortOnDrop(TaskSet);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        for task in self.0 .0.lock().unwrap_or_else(|e| e.into_inner()).drain(..) {
+            task.abort();
+        }
+    }
+}
+
+// Ends a connection when connect() returns or its future is dropped: its
+// tasks are aborted and, if the future was dropped mid-connection, the write
+// task gets a close frame behind the queued ACKs, after which it drops the
+// socket. connect() sets `returned` wherever it returns on its own.
+pub(crate) struct ConnectionGuard {
+    tx: WriteQueue,
+    pub(crate) tasks: TaskSet,
+    _abort: AbortOnDrop,
+    pub(crate) returned: bool,
+}
+
+impl ConnectionGuard {
+    pub(crate) fn new(tx: WriteQueue) -> Self {
+        let tasks = TaskSet::default();
+        Self {
+            tx,
+            _abort: tasks.abort_on_drop(),
+            tasks,
+            returned: false,
+        }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if !self.returned {
+            self.tx.try_close();
+        }
+    }
+}
+
+impl WriteLanes {
+    pub(crate) async fn recv(&mut self) -> Option<Outgoing> {
+        tokio::select! {
+            biased;
+            Some(out) = self.control.recv() => Some(out),
+            Some(out) = self.data.recv() => Some(out),
+            else => None,
+        }
+    }
+
+    // Closes both lanes and returns whatever was still queued.
+    pub(crate) fn close(&mut self) -> Vec<Outgoing> {
+        self.control.close();
+        self.data.close();
+        let mut pending = Vec::new();
+        while let Ok(out) = self.control.try_recv() {
+            pending.push(out);
+        }
+        while let Ok(out) = self.data.try_recv() {
+            pending.push(out);
+        }
+        pending
+    }
+}
+
+// The AckFormat and the subprotocol negotiated for this connection.
+#[derive(Clone)]
+pub(crate) struct Acker {
+    pub(crate) format: Arc<dyn AckFormat>,
+    pub(crate) subprotocol: String,
+}
+
+// The write queue of the current connection, shared with ListenerHandle so
+// messages can be sent from other tasks. Empty between connections.
+#[derive(Clone, Default)]
+pub(crate) struct Outbox(Arc<Mutex<Option<(WriteQueue, Acker)>>>);
+
+// Empties the outbox when the connection that filled it ends, so the write
+// task is not kept alive by the handle's sender.
+pub(crate) struct OutboxGuard(Outbox);
+
+impl Drop for OutboxGuard {
+    fn drop(&mut self) {
+        self.0 .0.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
+}
+
+impl Outbox {
+    pub(crate) fn open(&self, tx: WriteQueue, acker: Acker) -> OutboxGuard {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Some((tx, acker));
+        OutboxGuard(self.clone())
+    }
+
+    // EventAck goes through the AckFormat and is reported to on_ack_sent or
+    // on_ack_failed like any other ack.
+    pub(crate) async fn send(&self, msg: OutgoingMessage) -> Result<()> {
+        let Some((tx, acker)) = self.0.lock().unwrap_or_else(|e| e.into_inner()).clone() else {
+            return Err(Error::Other("not connected".to_string()));
+        };
+        let out = match &msg {
+            OutgoingMessage::EventAck {
+                event_id,
+                webhook_id,
+                webhook_conversation_id,
+            } => Outgoing {
+                message: Message::Text(
+                    acker
+                        .format
+                        .build(
+                            &acker.subprotocol,
+                            &AckFields {
+                                event_id,
+                                webhook_id,
+                                webhook_conversation_id,
+                            },
+                        )?
+                        .to_string(),
+                ),
+                ack: Some(PendingAck {
+                    event_id: event_id.clone(),
+                    conversation_id: webhook_conversation_id.clone(),
+                }),
+            },
+            _ => Outgoing::frame(Message::Text(msg.to_json().to_string())),
+        };
+        tx.send(out).await.map_err(|_| Error::Other("connection closed before the message was sent".to_string()))
+    }
+}
+
+// Queues an event_ack; the write task reports whether it reached the socket.
+pub(crate) async fn send_ack(tx: &WriteQueue, dispatcher: &Dispatcher, acker: &Acker, fields: AckFields<'_>) {
+    let built = acker.format.build(&acker.subprotocol, &fields).map(|frame| frame.to_string());
+    let json = match built {
+        Ok(json) => json,
+        Err(e) => {
+            dispatcher.logger.log(LogLevel::Error, "could not build ack", &[("event_id", &fields.event_id), ("error", &e)]);
+            dispatcher.ack_failed(fields.event_id, fields.webhook_conversation_id, &e);
+            return;
+        }
+    };
+    let out = Outgoing {
+        message: Message::Text(json),
+        ack: Some(PendingAck {
+            event_id: fields.event_id.to_string(),
+            conversation_id: fields.webhook_conversation_id.to_string(),
+        }),
+    };
+    if let Err(e) = tx.send(out).await {
+        if let Some(ack) = e.0.ack {
+            let err = Error::Other("connection closed before the ack was written".to_string());
+            dispatcher.ack_failed(&ack.event_id, &ack.conversation_id, &err);
+        }
+    }
+}
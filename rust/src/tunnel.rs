@@ -0,0 +1,312 @@
+// Delivery through a public tunnel instead of the CLI websocket: a local
+// receiver exposed by ngrok or cloudflared and registered as a temporary
+// webhook endpoint, for events that never reach the devproxy session.
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::dispatch::Dispatcher;
+use crate::signature::{self, DEFAULT_TOLERANCE};
+use crate::transport::stopped;
+use crate::*;
+
+/// Where the public url in front of the local receiver comes from.
+#[derive(Debug, Clone)]
+pub enum TunnelProvider {
+    /// Runs `cloudflared tunnel --url` for a quick tunnel on
+    /// trycloudflare.com, which needs no account. Without `binary`,
+    /// `cloudflared` is looked up on PATH.
+    Cloudflared { binary: Option<PathBuf> },
+    /// Runs `ngrok http`, passing `authtoken` when the agent is not
+    /// configured with one already.
+    Ngrok { binary: Option<PathBuf>, authtoken: Option<String> },
+    /// A tunnel run elsewhere that forwards this https url to the
+    /// receiver's `addr`.
+    External(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct TunnelConfig {
+    pub provider: TunnelProvider,
+    /// Address the local receiver listens on; port 0 picks a free port.
+    pub addr: SocketAddr,
+    /// Path registered on the endpoint and served by the receiver.
+    pub path: String,
+    /// Event types the temporary endpoint subscribes to (default `["*"]`).
+    /// The listener's event filter still applies.
+    pub enabled_events: Vec<String>,
+    /// API version of the endpoint's payloads; the account default if unset.
+    pub api_version: Option<String>,
+    /// Maximum age of a Stripe-Signature timestamp.
+    pub tolerance: Duration,
+    /// How long to wait for the tunnel to report its public url.
+    pub startup_timeout: Duration,
+}
+
+impl TunnelConfig {
+    pub fn new(provider: TunnelProvider) -> Self {
+        Self {
+            provider,
+            addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            path: "/webhooks".to_string(),
+            enabled_events: vec!["*".to_string()],
+            api_version: None,
+            tolerance: DEFAULT_TOLERANCE,
+            startup_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+// The temporary endpoint, known once the tunnel is up and registered.
+struct Registered {
+    id: String,
+    secret: SecretString,
+    endpoint: WebhookEndpoint,
+}
+
+struct Receiver {
+    dispatcher: Dispatcher,
+    path: String,
+    tolerance: Duration,
+    registered: OnceLock<Registered>,
+}
+
+impl StripeListener {
+    /// Alternative to run() for events that do not flow through the CLI
+    /// websocket. Starts a local receiver and a tunnel to it, registers a
+    /// temporary webhook endpoint on the account pointing at the tunnel,
+    /// and runs each delivery whose signature verifies through the same
+    /// pipeline as run(): filters, sampling, transforms, forward routes and
+    /// the handler. Deliveries are answered 200 once dispatched.
+    ///
+    /// Runs until ListenerHandle::shutdown, or fails if the tunnel exits.
+    /// The endpoint is deleted either way.
+    pub async fn run_tunnel(&mut self, tunnel: TunnelConfig) -> Result<()> {
+        let logger = self.cfg.logger.clone().unwrap();
+        let api = self.api_client()?;
+        let receiver = Arc::new(Receiver {
+            dispatcher: self.dispatcher()?,
+            path: tunnel.path.clone(),
+            tolerance: tunnel.tolerance,
+            registered: OnceLock::new(),
+        });
+
+        let svc_receiver = receiver.clone();
+        let make_svc = make_service_fn(move |_| {
+            let receiver = svc_receiver.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| handle(receiver.clone(), req))) }
+        });
+        let server = hyper::Server::try_bind(&tunnel.addr)
+            .map_err(|e| Error::Tunnel(format!("bind {}: {}", tunnel.addr, e)))?
+            .serve(make_svc);
+        let local = server.local_addr();
+        // Dropping the sender, on any return below, stops the server.
+        let (_stop_server, server_stopped) = oneshot::channel::<()>();
+        tokio::spawn(server.with_graceful_shutdown(async {
+            let _ = server_stopped.await;
+        }));
+
+        // The child is killed when dropped.
+        let (mut child, public_url) = start_tunnel(&tunnel, local, logger.clone()).await?;
+        let url = format!("{}/{}", public_url.trim_end_matches('/'), tunnel.path.trim_start_matches('/'));
+        let endpoint = api
+            .create_webhook_endpoint(&url, &tunnel.enabled_events, tunnel.api_version.as_deref())
+            .await?;
+        let field = |name: &str| endpoint.get(name).and_then(Value::as_str).unwrap_or_default().to_string();
+        let registered = Registered {
+            id: field("id"),
+            secret: field("secret").into(),
+            endpoint: WebhookEndpoint {
+                url: url.clone(),
+                api_version: endpoint.get("api_version").and_then(Value::as_str).map(str::to_string),
+            },
+        };
+        let endpoint_id = registered.id.clone();
+        logger.log(LogLevel::Info, "tunnel endpoint registered", &[("url", &url), ("endpoint_id", &endpoint_id)]);
+        let _ = receiver.registered.set(registered);
+
+        let mut shutdown = self.shutdown.subscribe();
+        let result = tokio::select! {
+            _ = stopped(&mut shutdown) => {
+                logger.log(LogLevel::Info, "shutting down", &[("endpoint_id", &endpoint_id)]);
+                Ok(())
+            }
+            status = exited(&mut child) => Err(Error::Tunnel(match status {
+                Ok(status) => format!("tunnel exited with {}", status),
+                Err(e) => format!("tunnel: {}", e),
+            })),
+        };
+
+        if let Err(e) = api.delete(&format!("/v1/webhook_endpoints/{}", endpoint_id)).await {
+            logger.log(LogLevel::Warn, "could not delete tunnel endpoint", &[("endpoint_id", &endpoint_id), ("error", &e)]);
+        }
+        tokio::select! {
+            _ = self.inflight.idle() => {}
+            _ = self.cfg.clock.as_ref().unwrap().sleep(self.cfg.drain_timeout.unwrap()) => {
+                logger.log(LogLevel::Warn, "drain timed out", &[("endpoint_id", &endpoint_id)]);
+            }
+        }
+        result
+    }
+}
+
+// Picks the public url out of one line of a provider's log output.
+type UrlFinder = fn(&str) -> Option<String>;
+
+// Starts the provider's process and waits for it to print the public url.
+async fn start_tunnel(tunnel: &TunnelConfig, local: SocketAddr, logger: Arc<dyn Logger>) -> Result<(Option<Child>, String)> {
+    let (program, args, find_url): (PathBuf, Vec<String>, UrlFinder) = match &tunnel.provider {
+        TunnelProvider::External(url) => return Ok((None, url.clone())),
+        TunnelProvider::Cloudflared { binary } => (
+            binary.clone().unwrap_or_else(|| "cloudflared".into()),
+            vec!["tunnel".into(), "--no-autoupdate".into(), "--url".into(), format!("http://{}", local)],
+            cloudflared_url,
+        ),
+        TunnelProvider::Ngrok { binary, authtoken } => {
+            let mut args = vec!["http".into(), local.to_string(), "--log".into(), "stdout".into(), "--log-format".into(), "json".into()];
+            if let Some(token) = authtoken {
+                args.extend(["--authtoken".into(), token.clone()]);
+            }
+            (binary.clone().unwrap_or_else(|| "ngrok".into()), args, ngrok_url)
+        }
+    };
+    let mut child = Command::new(&program)
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| Error::Tunnel(format!("{}: {}", program.display(), e)))?;
+
+    // cloudflared logs to stderr and ngrok (with --log stdout) to stdout.
+    // Both pipes are read for as long as the process runs so it never
+    // blocks on a full pipe.
+    let (tx, mut lines) = mpsc::unbounded_channel();
+    if let Some(out) = child.stdout.take() {
+        tokio::spawn(read_lines(out, tx.clone(), logger.clone()));
+    }
+    if let Some(err) = child.stderr.take() {
+        tokio::spawn(read_lines(err, tx, logger));
+    }
+    let found = async {
+        while let Some(line) = lines.recv().await {
+            if let Some(url) = find_url(&line) {
+                return Some(url);
+            }
+        }
+        None
+    };
+    match tokio::time::timeout(tunnel.startup_timeout, found).await {
+        Ok(Some(url)) => Ok((Some(child), url)),
+        Ok(None) => Err(Error::Tunnel(format!("{} exited before reporting a url", program.display()))),
+        Err(_) => Err(Error::Tunnel(format!("no url from {} after {:?}", program.display(), tunnel.startup_timeout))),
+    }
+}
+
+async fn read_lines(pipe: impl AsyncRead + Unpin, tx: mpsc::UnboundedSender<String>, logger: Arc<dyn Logger>) {
+    let mut lines = BufReader::new(pipe).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        logger.log(LogLevel::Debug, "tunnel output", &[("line", &line)]);
+        let _ = tx.send(line);
+    }
+}
+
+// Resolves when the tunnel process exits; never for an external tunnel.
+async fn exited(child: &mut Option<Child>) -> std::io::Result<ExitStatus> {
+    match child {
+        Some(child) => child.wait().await,
+        None => std::future::pending().await,
+    }
+}
+
+// cloudflared prints the quick tunnel's url inside a banner; other https
+// links in its output (terms of service) are skipped.
+fn cloudflared_url(line: &str) -> Option<String> {
+    let start = line.find("https://")?;
+    let url = line[start..].split(|c: char| c.is_whitespace() || c == '|').next()?;
+    url.ends_with(".trycloudflare.com").then(|| url.to_string())
+}
+
+fn ngrok_url(line: &str) -> Option<String> {
+    let entry: Value = serde_json::from_str(line).ok()?;
+    if entry.get("msg").and_then(Value::as_str) != Some("started tunnel") {
+        return None;
+    }
+    let url = entry.get("url").and_then(Value::as_str)?;
+    url.starts_with("https://").then(|| url.to_string())
+}
+
+async fn handle(receiver: Arc<Receiver>, req: Request<Body>) -> std::result::Result<Response<Body>, Infallible> {
+    if req.method() != Method::POST || req.uri().path() != receiver.path {
+        return Ok(text_response(StatusCode::NOT_FOUND, "not found"));
+    }
+    let Some(registered) = receiver.registered.get() else {
+        return Ok(text_response(StatusCode::SERVICE_UNAVAILABLE, "endpoint not registered yet"));
+    };
+    let http_headers: HashMap<String, String> = req
+        .headers()
+        .iter()
+        .map(|(k, v)| (k.as_str().to_string(), String::from_utf8_lossy(v.as_bytes()).into_owned()))
+        .collect();
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(b) => b,
+        Err(e) => return Ok(text_response(StatusCode::BAD_REQUEST, &e.to_string())),
+    };
+
+    let logger = &receiver.dispatcher.logger;
+    let Some(header) = http_headers.get("stripe-signature") else {
+        logger.log(LogLevel::Warn, "tunnel delivery without signature", &[]);
+        return Ok(text_response(StatusCode::BAD_REQUEST, "missing Stripe-Signature"));
+    };
+    if let Err(e) = signature::verify(&body, header, registered.secret.expose(), receiver.tolerance) {
+        logger.log(LogLevel::Warn, "tunnel delivery signature invalid", &[("error", &e)]);
+        return Ok(text_response(StatusCode::BAD_REQUEST, "signature verification failed"));
+    }
+    let Ok(event_payload) = String::from_utf8(body.to_vec()) else {
+        return Ok(text_response(StatusCode::BAD_REQUEST, "payload is not utf-8"));
+    };
+    let parsed: StripeEventPayload = match serde_json::from_str(&event_payload) {
+        Ok(p) => p,
+        Err(e) => {
+            logger.log(LogLevel::Warn, "could not parse tunnel delivery", &[("error", &e)]);
+            return Ok(text_response(StatusCode::BAD_REQUEST, "unparseable event"));
+        }
+    };
+
+    receiver.dispatcher.stats.event_received();
+    let evt = WebhookEvent {
+        webhook_id: registered.id.clone(),
+        // Deliveries over HTTP are acknowledged by the response, not an ack
+        // frame, so there is no conversation.
+        webhook_conversation_id: String::new(),
+        event_payload,
+        http_headers,
+        endpoint: Some(registered.endpoint.clone()),
+        extra: Value::Object(Default::default()),
+    };
+    receiver.dispatcher.webhook(evt, parsed);
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/json")
+        .body(Body::from(r#"{"received":true}"#))
+        .unwrap_or_default())
+}
+
+fn text_response(status: StatusCode, msg: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(msg.to_string()))
+        .unwrap_or_default()
+}
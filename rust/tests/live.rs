@@ -0,0 +1,112 @@
+// End-to-end run against a real Stripe test-mode account:
+//   STRIPE_API_KEY=sk_test_... cargo test --features live-tests --test live
+// Creates (and deletes) a customer to trigger customer.created, and expects
+// the listener to receive and acknowledge it within the deadline.
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use stripelistener::{Config, EventHandler, EventType, StripeEventPayload, StripeListener, V2Event, V2EventPayload, WebhookEvent};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+const DEADLINE: Duration = Duration::from_secs(30);
+
+enum Seen {
+    Event(StripeEventPayload, String),
+    Ack(String),
+}
+
+struct Recorder(Mutex<UnboundedSender<Seen>>);
+
+impl EventHandler for Recorder {
+    fn on_webhook_event(&self, evt: WebhookEvent, parsed: StripeEventPayload) {
+        let _ = self.0.lock().unwrap().send(Seen::Event(parsed, evt.event_payload));
+    }
+    fn on_v2_event(&self, _evt: V2Event, _parsed: V2EventPayload) {}
+    fn on_unknown_message(&self, _raw_type: String, _data: serde_json::Value) {}
+
+    fn on_ack_sent(&self, event_id: &str, _conversation_id: &str, _timestamp: SystemTime) {
+        let _ = self.0.lock().unwrap().send(Seen::Ack(event_id.to_string()));
+    }
+}
+
+fn api_key() -> Option<String> {
+    match std::env::var("STRIPE_API_KEY") {
+        Ok(key) if key.starts_with("sk_test_") || key.starts_with("rk_test_") => Some(key),
+        Ok(_) => panic!("STRIPE_API_KEY must be a test-mode key"),
+        Err(_) => {
+            eprintln!("STRIPE_API_KEY not set; skipping live test");
+            None
+        }
+    }
+}
+
+#[tokio::test]
+async fn receives_and_acks_triggered_event() {
+    let Some(api_key) = api_key() else { return };
+
+    let (tx, mut rx) = unbounded_channel();
+    let mut cfg = Config::new(api_key.clone(), Arc::new(Recorder(Mutex::new(tx))));
+    cfg.device_name = Some("stripelistener-live-test".to_string());
+    cfg.events = Some(vec![EventType::CustomerCreated]);
+
+    let mut listener = StripeListener::new(cfg);
+    let session = listener.authorize().await.expect("authorize");
+    assert!(!session.websocket_id.is_empty());
+    let handle = listener.handle();
+    let run = tokio::spawn(async move { listener.run().await });
+
+    // The first pong means the websocket is up.
+    tokio::time::timeout(DEADLINE, async {
+        while handle.stats().last_pong_received.is_none() {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await
+    .expect("websocket did not connect in time");
+
+    let client = reqwest::Client::new();
+    let customer: serde_json::Value = client
+        .post("https://api.stripe.com/v1/customers")
+        .bearer_auth(&api_key)
+        .form(&[("description", "stripelistener live test")])
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .expect("create customer")
+        .json()
+        .await
+        .expect("customer json");
+    let customer_id = customer["id"].as_str().expect("customer id").to_string();
+
+    let outcome = tokio::time::timeout(DEADLINE, async {
+        // The ACK is written on another task and may be reported first.
+        let mut event_id = None;
+        let mut acked = Vec::new();
+        while let Some(seen) = rx.recv().await {
+            match seen {
+                Seen::Event(parsed, payload) => {
+                    let payload: serde_json::Value = serde_json::from_str(&payload).unwrap();
+                    if parsed.event_type == EventType::CustomerCreated && payload["data"]["object"]["id"] == customer_id.as_str() {
+                        event_id = Some(parsed.id);
+                    }
+                }
+                Seen::Ack(id) => acked.push(id),
+            }
+            if let Some(id) = event_id.as_ref().filter(|id| acked.contains(id)) {
+                return id.clone();
+            }
+        }
+        panic!("listener stopped before the event arrived");
+    })
+    .await;
+
+    run.abort();
+    let _ = client
+        .delete(format!("https://api.stripe.com/v1/customers/{}", customer_id))
+        .bearer_auth(&api_key)
+        .send()
+        .await;
+
+    let event_id = outcome.expect("customer.created was not received and acked in time");
+    assert!(event_id.starts_with("evt_"));
+}